@@ -0,0 +1,144 @@
+use error::{Error, Result};
+use utils::ring_buffer::BiasedRingBuffer;
+use index::posting::Posting;
+use page_manager::Block;
+use compressor::{Compressor, NaiveCompressor, VByteCompressor, PForCompressor,
+                 StreamVByteCompressor};
+
+/// Compresses every block with the codec that packs the most postings into
+/// it and decompresses blocks of any codec.
+///
+/// Bit packing usually fits the most postings. Codecs that decode faster are
+/// listed first, so they win if they fit as many postings. Like for the last
+/// block of a short listing, that all codecs can hold completely.
+pub struct BestCompressor;
+
+impl BestCompressor {
+    /// Returns the id of the codec that fits the most postings from the front
+    /// of `data` into a block, that number and whether every codec is full.
+    /// Ties go to the codec listed first.
+    fn choose(data: &BiasedRingBuffer<Posting>) -> (u8, usize, bool) {
+        let candidates = [(StreamVByteCompressor::ID, StreamVByteCompressor::fitting(data)),
+                          (VByteCompressor::ID, VByteCompressor::fitting(data)),
+                          (PForCompressor::ID, PForCompressor::fitting(data)),
+                          (NaiveCompressor::ID, NaiveCompressor::fitting(data))];
+        let all_full = candidates.iter().all(|&(_, (_, full))| full);
+        let (id, (count, _)) = candidates.iter()
+            .fold(candidates[0], |best, candidate| {
+                if (candidate.1).0 > (best.1).0 { *candidate } else { best }
+            });
+        (id, count, all_full)
+    }
+
+    fn write_block(data: &mut BiasedRingBuffer<Posting>, id: u8, count: usize) -> Block {
+        match id {
+            StreamVByteCompressor::ID => StreamVByteCompressor::write_block(data, count),
+            PForCompressor::ID => PForCompressor::write_block(data, count),
+            VByteCompressor::ID => VByteCompressor::write_block(data, count),
+            _ => NaiveCompressor::write_block(data, count),
+        }
+    }
+
+    /// Compresses a block once no codec can take further postings into it.
+    /// Until then a codec might still improve by waiting for more postings
+    pub fn compress(data: &mut BiasedRingBuffer<Posting>) -> Option<Block> {
+        // Most calls happen while the buffer fills up. Variable bytes are
+        // the cheapest to check
+        if !VByteCompressor::fitting(data).1 {
+            return None;
+        }
+        match Self::choose(data) {
+            (id, count, true) => Some(Self::write_block(data, id, count)),
+            _ => None,
+        }
+    }
+
+    /// Compresses as many postings as the best codec fits into a block
+    pub fn force_compress(data: &mut BiasedRingBuffer<Posting>) -> Block {
+        let (id, count, _) = Self::choose(data);
+        Self::write_block(data, id, count)
+    }
+
+    /// Decompresses a block with the codec whose id it starts with.
+    /// Fails with `Error::UnknownCodec` if there is no such codec
+    pub fn decompress(data: Block, target: &mut BiasedRingBuffer<Posting>) -> Result<()> {
+        match data.0[0] {
+            NaiveCompressor::ID => NaiveCompressor::decompress(data, target),
+            VByteCompressor::ID => VByteCompressor::decompress(data, target),
+            PForCompressor::ID => PForCompressor::decompress(data, target),
+            StreamVByteCompressor::ID => StreamVByteCompressor::decompress(data, target),
+            id => Err(Error::UnknownCodec(id)),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use error::Error;
+    use utils::ring_buffer::BiasedRingBuffer;
+    use index::posting::{DocId, Posting};
+    use compressor::{Compressor, VByteCompressor, PForCompressor, StreamVByteCompressor};
+
+    use super::BestCompressor;
+
+    fn compress_all(postings: &[Posting]) -> Vec<u8> {
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        let mut target = BiasedRingBuffer::<Posting>::new();
+        let mut ids = Vec::new();
+        for posting in postings {
+            buffer.push_back(*posting);
+            if let Some(block) = BestCompressor::compress(&mut buffer) {
+                ids.push(block.0[0]);
+                BestCompressor::decompress(block, &mut target).unwrap();
+            }
+        }
+        while !buffer.is_empty() {
+            let block = BestCompressor::force_compress(&mut buffer);
+            ids.push(block.0[0]);
+            BestCompressor::decompress(block, &mut target).unwrap();
+        }
+        let mut decompressed = Vec::new();
+        while let Some(posting) = target.pop_front() {
+            decompressed.push(posting);
+        }
+        assert_eq!(decompressed, postings);
+        assert!(decompressed.iter().zip(postings).all(|(a, b)| a.tf() == b.tf()));
+        ids
+    }
+
+    #[test]
+    fn dense() {
+        let postings = (0..60).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>();
+        // Any codec holds the remaining postings
+        assert_eq!(compress_all(&postings), vec![PForCompressor::ID, StreamVByteCompressor::ID]);
+    }
+
+    #[test]
+    fn few_postings() {
+        let postings = (0..5).map(|i| Posting(DocId(i * 1000), 1)).collect::<Vec<_>>();
+        assert_eq!(compress_all(&postings), vec![StreamVByteCompressor::ID]);
+    }
+
+    #[test]
+    fn waits_for_more() {
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        for i in 0..40 {
+            buffer.push_back(Posting(DocId(i), 1));
+        }
+        // Variable bytes would be full, but bit packing takes more
+        assert!(VByteCompressor::compress(&mut buffer.clone()).is_some());
+        assert_eq!(BestCompressor::compress(&mut buffer), None);
+    }
+
+    #[test]
+    fn unknown_codec() {
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        buffer.push_back(Posting(DocId(0), 1));
+        let mut block = BestCompressor::force_compress(&mut buffer);
+        block.0[0] = 0;
+        assert!(matches!(BestCompressor::decompress(block, &mut buffer),
+                         Err(Error::UnknownCodec(0))));
+        assert_eq!(buffer.count(), 0);
+    }
+}
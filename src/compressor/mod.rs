@@ -1,12 +1,53 @@
+//! Codecs that pack postings into blocks.
+//!
+//! The first byte of every block holds the `ID` of the codec that wrote it.
+//! `BestCompressor` chooses a codec for each block and dispatches on that id
+//! when decompressing.
 mod naive_compressor;
+mod vbyte_compressor;
+mod pfor_compressor;
+mod stream_vbyte_compressor;
+mod best_compressor;
 
 pub use compressor::naive_compressor::NaiveCompressor;
+pub use compressor::pfor_compressor::PForCompressor;
+pub use compressor::vbyte_compressor::VByteCompressor;
+pub use compressor::stream_vbyte_compressor::StreamVByteCompressor;
+pub use compressor::best_compressor::BestCompressor;
+use error::Result;
 use utils::ring_buffer::BiasedRingBuffer;
 use index::posting::Posting;
 use page_manager::Block;
 
+/// Offset of the encoded postings in a block. The byte before is the codec id
+pub const PAYLOAD_START: usize = 1;
+
 pub trait Compressor {
-    fn compress(&mut BiasedRingBuffer<Posting>) -> Option<Block>;
-    fn force_compress(&mut BiasedRingBuffer<Posting>) -> Block;
-    fn decompress(Block, &mut BiasedRingBuffer<Posting>);
+    /// Identifies the codec in the blocks it writes. Never 0
+    const ID: u8;
+
+    /// Returns how many postings from the front of `data` fit into a block and
+    /// whether that block is full, e.g. no further posting would fit.
+    fn fitting(data: &BiasedRingBuffer<Posting>) -> (usize, bool);
+
+    /// Compresses the first `count` postings of `data` into a block
+    fn write_block(data: &mut BiasedRingBuffer<Posting>, count: usize) -> Block;
+
+    /// Compresses a block if `data` holds enough postings to fill one
+    fn compress(data: &mut BiasedRingBuffer<Posting>) -> Option<Block> {
+        match Self::fitting(data) {
+            (count, true) => Some(Self::write_block(data, count)),
+            _ => None,
+        }
+    }
+
+    /// Compresses as many postings as fit into a block
+    fn force_compress(data: &mut BiasedRingBuffer<Posting>) -> Block {
+        let (count, _) = Self::fitting(data);
+        Self::write_block(data, count)
+    }
+
+    /// Decompresses the postings of a block into `target`. Fails with
+    /// `Error::InvalidBlock` if the block is malformed
+    fn decompress(Block, &mut BiasedRingBuffer<Posting>) -> Result<()>;
 }
@@ -1,52 +1,85 @@
-use std::mem;
-
+use error::Result;
 use utils::ring_buffer::{BiasedRingBuffer};
-use utils::Baseable;
 use index::posting::{Posting, DocId};
 use page_manager::{BLOCKSIZE, Block};
-use compressor::Compressor;
+use compressor::{Compressor, PAYLOAD_START};
 
+/// Offset of the postings: After the codec id and the term frequency flag
+const POSTINGS_START: usize = PAYLOAD_START + 1;
+/// Size of a doc id, stored as u32
+const DOC_ID_SIZE: usize = 4;
+/// Size of a posting with its term frequency, also stored as u32
+const POSTING_SIZE: usize = 8;
 
+/// Stores postings uncompressed.
+///
+/// The byte after the codec id tells whether the block stores term
+/// frequencies. It does not if every posting in it has a term frequency of 1,
+/// then the block holds the doc ids only. Unused space is filled with
+/// `DocId::none()`.
 pub struct NaiveCompressor;
 
+impl NaiveCompressor {
+    /// Bytes per posting in a block with or without term frequencies
+    fn posting_size(with_tf: bool) -> usize {
+        if with_tf { POSTING_SIZE } else { DOC_ID_SIZE }
+    }
+
+    fn read_u32(source: &[u8]) -> u32 {
+        u32::from_le_bytes([source[0], source[1], source[2], source[3]])
+    }
+}
+
 impl Compressor for NaiveCompressor {
-    fn compress(data: &mut BiasedRingBuffer<Posting>) -> Option<Block>
-        where Posting: for<'x> Baseable<&'x Posting>
-    {
-        if data.count() >= BLOCKSIZE / 4 {
-            // Enough in there to fill the block
-            let mut block = [0u8; BLOCKSIZE];
-            for i in 0..BLOCKSIZE / 4 {
-                block[i * 4..(i * 4) + 4].copy_from_slice(unsafe {
-                    &mem::transmute::<Posting, [u8; 4]>(data.pop_front_biased().unwrap())
-                });
+    const ID: u8 = 1;
+
+    fn fitting(data: &BiasedRingBuffer<Posting>) -> (usize, bool) {
+        let mut probe = data.clone();
+        let mut count = 0;
+        let mut with_tf = false;
+        while let Some(posting) = probe.pop_front_biased() {
+            let fits = (BLOCKSIZE - POSTINGS_START) /
+                       Self::posting_size(with_tf || posting.tf() != 1);
+            if count + 1 > fits {
+                return (count, true);
             }
-            Some(Block(block))
-        } else {
-            None
+            with_tf |= posting.tf() != 1;
+            count += 1;
         }
+        (count, count == (BLOCKSIZE - POSTINGS_START) / Self::posting_size(with_tf))
     }
 
-    fn force_compress(data: &mut BiasedRingBuffer<Posting>) -> Block {
+    fn write_block(data: &mut BiasedRingBuffer<Posting>, count: usize) -> Block {
+        let mut postings = Vec::with_capacity(count);
+        for _ in 0..count {
+            postings.push(data.pop_front_biased().unwrap());
+        }
+        let with_tf = postings.iter().any(|posting| posting.tf() != 1);
         let mut block = [0u8; BLOCKSIZE];
-        for i in 0..BLOCKSIZE / 4 {
-            let posting = data.pop_front_biased().unwrap_or_else(|| Posting(DocId::none()));
-            block[i * 4..(i * 4) + 4]
-                .copy_from_slice(unsafe { &mem::transmute::<Posting, [u8; 4]>(posting) });
+        block[0] = Self::ID;
+        block[PAYLOAD_START] = with_tf as u8;
+        let chunks = block[POSTINGS_START..].chunks_exact_mut(Self::posting_size(with_tf));
+        for (i, chunk) in chunks.enumerate() {
+            let posting = postings.get(i).cloned().unwrap_or_else(Posting::none);
+            chunk[..DOC_ID_SIZE].copy_from_slice(&(posting.0).0.to_le_bytes());
+            if with_tf {
+                chunk[DOC_ID_SIZE..].copy_from_slice(&posting.1.to_le_bytes());
+            }
         }
         Block(block)
     }
 
-    fn decompress(data: Block, target: &mut BiasedRingBuffer<Posting>) {
-        let nums: [u32; BLOCKSIZE / 4] = unsafe { mem::transmute(data) };
-        for num in &nums {
-            let did = DocId(*num);
-            if did != DocId::none() {
-                target.push_back_biased(Posting(did));
-            } else {
-                return;
+    fn decompress(data: Block, target: &mut BiasedRingBuffer<Posting>) -> Result<()> {
+        let with_tf = data.0[PAYLOAD_START] != 0;
+        for chunk in data.0[POSTINGS_START..].chunks_exact(Self::posting_size(with_tf)) {
+            let did = DocId(Self::read_u32(chunk));
+            if did == DocId::none() {
+                break;
             }
+            let tf = if with_tf { Self::read_u32(&chunk[DOC_ID_SIZE..]) } else { 1 };
+            target.push_back_biased(Posting(did, tf));
         }
+        Ok(())
     }
 }
 
@@ -55,17 +88,19 @@ impl Compressor for NaiveCompressor {
 mod tests {
     use utils::ring_buffer::BiasedRingBuffer;
     use index::posting::{DocId, Posting};
-    use page_manager::{BLOCKSIZE};
     use compressor::Compressor;
 
-    use super::NaiveCompressor;
+    use super::{NaiveCompressor, POSTINGS_START, DOC_ID_SIZE, POSTING_SIZE};
+    use page_manager::BLOCKSIZE;
+
+    const POSTINGS_PER_BLOCK: usize = (BLOCKSIZE - POSTINGS_START) / DOC_ID_SIZE;
 
     #[test]
     fn compress() {
         let mut buffer = BiasedRingBuffer::<Posting>::new();
         assert_eq!(NaiveCompressor::compress(&mut buffer), None);
-        for i in 0..BLOCKSIZE / 4 {
-            buffer.push_back(Posting(DocId(i as u32)));
+        for i in 0..POSTINGS_PER_BLOCK {
+            buffer.push_back(Posting(DocId(i as u32), 1));
         }
         assert!(NaiveCompressor::compress(&mut buffer).is_some());
         assert_eq!(buffer.count(), 0);
@@ -75,14 +110,14 @@ mod tests {
     fn decompress() {
         let mut buffer = BiasedRingBuffer::<Posting>::new();
         assert_eq!(NaiveCompressor::compress(&mut buffer), None);
-        for i in 0..BLOCKSIZE / 4 {
-            buffer.push_back(Posting(DocId(i as u32)));
+        for i in 0..POSTINGS_PER_BLOCK {
+            buffer.push_back(Posting(DocId(i as u32), 1));
         }
         let block = NaiveCompressor::compress(&mut buffer).unwrap();
         assert_eq!(buffer.count(), 0);
-        NaiveCompressor::decompress(block, &mut buffer);
-        for i in 0..BLOCKSIZE / 4 {
-            assert_eq!(buffer.pop_front().unwrap(), Posting(DocId(i as u32)));
+        NaiveCompressor::decompress(block, &mut buffer).unwrap();
+        for i in 0..POSTINGS_PER_BLOCK {
+            assert_eq!(buffer.pop_front().unwrap(), Posting(DocId(i as u32), 1));
         }
     }
 
@@ -90,15 +125,38 @@ mod tests {
     fn force_compress() {
         let mut buffer = BiasedRingBuffer::<Posting>::new();
         assert_eq!(NaiveCompressor::compress(&mut buffer), None);
-        buffer.push_back(Posting(DocId(0)));
-        buffer.push_back(Posting(DocId(1)));
+        buffer.push_back(Posting(DocId(0), 1));
+        buffer.push_back(Posting(DocId(1), 7));
         assert_eq!(NaiveCompressor::compress(&mut buffer), None);
         let block = NaiveCompressor::force_compress(&mut buffer);
         assert_eq!(buffer.count(), 0);
-        NaiveCompressor::decompress(block, &mut buffer);
-        assert_eq!(buffer.pop_front().unwrap(), Posting(DocId(0)));
-        assert_eq!(buffer.pop_front().unwrap(), Posting(DocId(1)));
+        NaiveCompressor::decompress(block, &mut buffer).unwrap();
+        assert_eq!(buffer.pop_front().unwrap().tf(), 1);
+        assert_eq!(buffer.pop_front().unwrap().tf(), 7);
         assert_eq!(buffer.pop_front(), None);
     }
 
+    #[test]
+    fn optional_tf() {
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        for i in 0..POSTINGS_PER_BLOCK {
+            buffer.push_back(Posting(DocId(i as u32), 1));
+        }
+        assert_eq!(NaiveCompressor::fitting(&buffer), (POSTINGS_PER_BLOCK, true));
+        // A single term frequency halves the postings that fit
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        buffer.push_back(Posting(DocId(0), 3));
+        for i in 1..POSTINGS_PER_BLOCK {
+            buffer.push_back(Posting(DocId(i as u32), 1));
+        }
+        let with_tf = (BLOCKSIZE - POSTINGS_START) / POSTING_SIZE;
+        assert_eq!(NaiveCompressor::fitting(&buffer), (with_tf, true));
+        let block = NaiveCompressor::compress(&mut buffer).unwrap();
+        let mut target = BiasedRingBuffer::<Posting>::new();
+        NaiveCompressor::decompress(block, &mut target).unwrap();
+        assert_eq!(target.count(), with_tf);
+        assert_eq!(target.pop_front().unwrap(), Posting(DocId(0), 3));
+        assert_eq!(target.pop_front().unwrap(), Posting(DocId(1), 1));
+    }
+
 }
@@ -0,0 +1,323 @@
+use std::cmp;
+
+use error::Result;
+use utils::ring_buffer::BiasedRingBuffer;
+use index::posting::{Posting, DocId};
+use page_manager::{BLOCKSIZE, Block};
+use compressor::{Compressor, PAYLOAD_START};
+use compressor::vbyte_compressor::{read_vbyte, write_vbyte};
+
+/// Size of the block header: codec id, count, the two bit widths and the
+/// exception count
+const HEADER_SIZE: usize = PAYLOAD_START + 4;
+
+/// Most postings a block can hold.
+/// Listings check for full blocks every 16 postings, so a buffer holding
+/// less than a full block must still be able to take 16 more postings.
+const MAX_POSTINGS: usize = 48;
+
+/// Marks an exception of the term frequencies. Others belong to the gaps
+const TF_EXCEPTION: u8 = 0x80;
+
+/// Frame of reference bit packing with exceptions (PFor).
+///
+/// The gaps between doc ids and the term frequencies of a block are packed
+/// with a fixed number of bits each. The widths are chosen per block to
+/// minimize its size. Values that need more bits are exceptions: Their low
+/// bits are packed like all others, the high bits are stored behind the
+/// packed values.
+///
+/// Block layout:
+/// `id | count | gap width | tf width | exception count | gaps | tfs | exceptions`
+/// where an exception is the index of the value, with `TF_EXCEPTION` set for
+/// term frequencies, followed by its high bits as variable-byte integer.
+/// The gap of the first posting is taken to the bias of the block.
+pub struct PForCompressor;
+
+impl PForCompressor {
+    /// The gaps and term frequencies of the buffered postings
+    fn values(data: &BiasedRingBuffer<Posting>) -> (Vec<u32>, Vec<u32>) {
+        let mut probe = data.clone();
+        let mut gaps = Vec::with_capacity(probe.count());
+        let mut tfs = Vec::with_capacity(probe.count());
+        let mut last = 0;
+        while let Some(posting) = probe.pop_front_biased() {
+            gaps.push((posting.0).0 - last);
+            tfs.push(posting.1);
+            last = (posting.0).0;
+        }
+        (gaps, tfs)
+    }
+
+    /// Returns how many of the postings fit into a block and whether that
+    /// block is full, e.g. no further posting could be added.
+    fn fitting_values(gaps: &[u32], tfs: &[u32]) -> (usize, bool) {
+        let limit = cmp::min(gaps.len(), MAX_POSTINGS);
+        // Usually everything fits while the buffer fills up
+        if limit < MAX_POSTINGS && block_size(gaps, tfs) <= BLOCKSIZE {
+            return (limit, false);
+        }
+        // Every block can take a posting. Fewer postings never take more space
+        let (mut fits, mut exceeds) = (cmp::min(limit, 1), limit + 1);
+        while exceeds - fits > 1 {
+            let count = (fits + exceeds) / 2;
+            if block_size(&gaps[..count], &tfs[..count]) <= BLOCKSIZE {
+                fits = count;
+            } else {
+                exceeds = count;
+            }
+        }
+        (fits, fits == MAX_POSTINGS || fits < gaps.len())
+    }
+
+    fn write_values(data: &mut BiasedRingBuffer<Posting>, gaps: &[u32], tfs: &[u32]) -> Block {
+        let (gap_width, _) = best_width(gaps);
+        let (tf_width, _) = best_width(tfs);
+        let mut block = [0u8; BLOCKSIZE];
+        block[0] = Self::ID;
+        block[PAYLOAD_START] = gaps.len() as u8;
+        block[PAYLOAD_START + 1] = gap_width as u8;
+        block[PAYLOAD_START + 2] = tf_width as u8;
+        let mut bit = HEADER_SIZE * 8;
+        for gap in gaps {
+            pack(&mut block, &mut bit, *gap, gap_width);
+        }
+        for tf in tfs {
+            pack(&mut block, &mut bit, *tf, tf_width);
+        }
+        let mut offset = bit.div_ceil(8);
+        let mut exceptions = 0;
+        for (values, width, flag) in [(gaps, gap_width, 0), (tfs, tf_width, TF_EXCEPTION)] {
+            for (i, value) in values.iter().enumerate() {
+                let high = high_bits(*value, width);
+                if high > 0 {
+                    block[offset] = i as u8 | flag;
+                    offset += 1;
+                    offset += write_vbyte(&mut block[offset..], high);
+                    exceptions += 1;
+                }
+            }
+        }
+        block[PAYLOAD_START + 3] = exceptions;
+        for _ in 0..gaps.len() {
+            data.pop_front_biased();
+        }
+        Block(block)
+    }
+}
+
+impl Compressor for PForCompressor {
+    const ID: u8 = 3;
+
+    fn fitting(data: &BiasedRingBuffer<Posting>) -> (usize, bool) {
+        let (gaps, tfs) = Self::values(data);
+        Self::fitting_values(&gaps, &tfs)
+    }
+
+    fn write_block(data: &mut BiasedRingBuffer<Posting>, count: usize) -> Block {
+        let (gaps, tfs) = Self::values(data);
+        Self::write_values(data, &gaps[..count], &tfs[..count])
+    }
+
+    fn decompress(data: Block, target: &mut BiasedRingBuffer<Posting>) -> Result<()> {
+        let block = data.0;
+        let count = block[PAYLOAD_START] as usize;
+        let (gap_width, tf_width) = (block[PAYLOAD_START + 1] as u32, block[PAYLOAD_START + 2] as u32);
+        let mut bit = HEADER_SIZE * 8;
+        let mut gaps = [0u32; MAX_POSTINGS];
+        let mut tfs = [0u32; MAX_POSTINGS];
+        for gap in &mut gaps[..count] {
+            *gap = unpack(&block, &mut bit, gap_width);
+        }
+        for tf in &mut tfs[..count] {
+            *tf = unpack(&block, &mut bit, tf_width);
+        }
+        let mut offset = bit.div_ceil(8);
+        for _ in 0..block[PAYLOAD_START + 3] {
+            let index = block[offset];
+            let (high, read) = read_vbyte(&block[offset + 1..]);
+            offset += 1 + read;
+            if index & TF_EXCEPTION == 0 {
+                gaps[index as usize] |= high << gap_width;
+            } else {
+                tfs[(index & !TF_EXCEPTION) as usize] |= high << tf_width;
+            }
+        }
+        let mut last = 0;
+        for (gap, tf) in gaps[..count].iter().zip(&tfs[..count]) {
+            last += gap;
+            target.push_back_biased(Posting(DocId(last), *tf));
+        }
+        Ok(())
+    }
+}
+
+/// The bits of `value` that do not fit into `width` bits
+fn high_bits(value: u32, width: u32) -> u32 {
+    value.checked_shr(width).unwrap_or(0)
+}
+
+/// Returns the bit width that stores `values` in the fewest bits, including
+/// exceptions, and that number of bits
+fn best_width(values: &[u32]) -> (u32, usize) {
+    // Number of values needing each number of bits
+    let mut lengths = [0usize; 33];
+    for value in values {
+        lengths[(32 - value.leading_zeros()) as usize] += 1;
+    }
+    let used = (1..33u32).filter(|length| lengths[*length as usize] > 0).collect::<Vec<_>>();
+    // Wider than the widest value never pays off
+    (0..used.last().map_or(1, |widest| widest + 1))
+        .map(|width| {
+            // An exception takes a byte for its index and 7 high bits per byte
+            let exceptions = used.iter()
+                .filter(|length| **length > width)
+                .map(|length| lengths[*length as usize] * (1 + (length - width).div_ceil(7) as usize))
+                .sum::<usize>();
+            (width, values.len() * width as usize + exceptions * 8)
+        })
+        .min_by_key(|&(_, bits)| bits)
+        .unwrap()
+}
+
+/// Number of bytes a block with these gaps and term frequencies takes
+fn block_size(gaps: &[u32], tfs: &[u32]) -> usize {
+    // Exceptions take whole bytes, so the sum of the bits can be rounded up
+    HEADER_SIZE + (best_width(gaps).1 + best_width(tfs).1).div_ceil(8)
+}
+
+/// Writes the lowest `width` bits of `value` to `target`, starting at `bit`
+fn pack(target: &mut [u8], bit: &mut usize, value: u32, width: u32) {
+    let mut value = value as u64 & ((1u64 << width) - 1);
+    let mut remaining = width as usize;
+    while remaining > 0 {
+        let shift = *bit % 8;
+        let taken = cmp::min(8 - shift, remaining);
+        target[*bit / 8] |= ((value & ((1 << taken) - 1)) << shift) as u8;
+        value >>= taken;
+        remaining -= taken;
+        *bit += taken;
+    }
+}
+
+/// Reads `width` bits from `source`, starting at `bit`
+fn unpack(source: &[u8], bit: &mut usize, width: u32) -> u32 {
+    let mut value = 0u64;
+    let mut read = 0;
+    while read < width as usize {
+        let shift = *bit % 8;
+        let taken = cmp::min(8 - shift, width as usize - read);
+        let bits = (source[*bit / 8] as u64 >> shift) & ((1 << taken) - 1);
+        value |= bits << read;
+        read += taken;
+        *bit += taken;
+    }
+    value as u32
+}
+
+
+#[cfg(test)]
+mod tests {
+    use utils::ring_buffer::BiasedRingBuffer;
+    use index::posting::{DocId, Posting};
+    use page_manager::Block;
+    use compressor::Compressor;
+
+    use super::{PForCompressor, MAX_POSTINGS};
+
+    /// Compresses the postings like a listing does and checks that they
+    /// decompress to the same
+    fn roundtrip(postings: &[Posting]) -> Vec<Block> {
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        let mut blocks = Vec::new();
+        let mut bias = Posting::default();
+        for chunk in postings.chunks(16) {
+            for posting in chunk {
+                buffer.push_back(*posting);
+            }
+            while let Some(block) = PForCompressor::compress(&mut buffer) {
+                blocks.push((bias, block));
+                // Like listings, take the last posting if the buffer ran empty
+                bias = buffer.peek_front().cloned().unwrap_or(chunk[chunk.len() - 1]);
+                buffer.set_base(bias);
+            }
+        }
+        if !buffer.is_empty() {
+            blocks.push((bias, PForCompressor::force_compress(&mut buffer)));
+        }
+        let mut target = BiasedRingBuffer::<Posting>::new();
+        let mut decompressed = Vec::new();
+        for &(bias, block) in &blocks {
+            target.set_base(bias);
+            PForCompressor::decompress(block, &mut target).unwrap();
+            while let Some(posting) = target.pop_front() {
+                decompressed.push(posting);
+            }
+        }
+        assert_eq!(decompressed, postings);
+        assert!(decompressed.iter().zip(postings).all(|(a, b)| a.tf() == b.tf()));
+        blocks.into_iter().map(|(_, block)| block).collect()
+    }
+
+    #[test]
+    fn compress() {
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        assert_eq!(PForCompressor::compress(&mut buffer), None);
+        for i in 0..40 {
+            buffer.push_back(Posting(DocId(i), 1));
+        }
+        assert_eq!(PForCompressor::compress(&mut buffer), None);
+        for i in 40..60 {
+            buffer.push_back(Posting(DocId(i), 1));
+        }
+        assert!(PForCompressor::compress(&mut buffer).is_some());
+        assert_eq!(buffer.count(), 60 - MAX_POSTINGS);
+    }
+
+    #[test]
+    fn dense() {
+        // One bit per gap and term frequency
+        let postings = (0..480).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>();
+        let blocks = roundtrip(&postings);
+        assert_eq!(blocks.len(), 480 / MAX_POSTINGS);
+        assert_eq!(&blocks[1].0[..5], &[PForCompressor::ID, MAX_POSTINGS as u8, 1, 1, 0]);
+    }
+
+    #[test]
+    fn exceptions() {
+        let postings = (0..100u32)
+            .map(|i| {
+                let tf = if i % 10 == 0 { 1000 } else { 2 };
+                Posting(DocId(i * 3 + (i / 20) * 100_000), tf)
+            })
+            .collect::<Vec<_>>();
+        let blocks = roundtrip(&postings);
+        // Outliers do not widen the other values
+        assert!(blocks.iter().all(|block| block.0[2] <= 2 && block.0[3] <= 2));
+        assert!(blocks.iter().any(|block| block.0[4] > 0));
+    }
+
+    #[test]
+    fn large_values() {
+        let postings = (0..100u32)
+            .map(|i| Posting(DocId(i * 40_000_000), u32::MAX - i))
+            .collect::<Vec<_>>();
+        roundtrip(&postings);
+    }
+
+    #[test]
+    fn biased() {
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        buffer.set_base(Posting(DocId(1_000_000), 0));
+        buffer.push_back(Posting(DocId(1_000_000), 3));
+        buffer.push_back(Posting(DocId(1_000_005), 7));
+        let block = PForCompressor::force_compress(&mut buffer);
+        assert_eq!(buffer.count(), 0);
+        let mut target = BiasedRingBuffer::<Posting>::new();
+        target.set_base(Posting(DocId(1_000_000), 0));
+        PForCompressor::decompress(block, &mut target).unwrap();
+        assert_eq!(target.pop_front().unwrap().tf(), 3);
+        assert_eq!(target.pop_front(), Some(Posting(DocId(1_000_005), 7)));
+        assert_eq!(target.pop_front(), None);
+    }
+}
@@ -0,0 +1,348 @@
+use error::{Error, Result};
+use utils::ring_buffer::BiasedRingBuffer;
+use index::posting::{Posting, DocId};
+use page_manager::{BLOCKSIZE, Block};
+use compressor::{Compressor, PAYLOAD_START};
+
+/// Offset of the control bytes: After the codec id and the posting count
+const CONTROL_START: usize = PAYLOAD_START + 1;
+
+/// Most postings a block can hold: every value takes at least a byte and two
+/// control bits
+const MAX_POSTINGS: usize = (BLOCKSIZE - CONTROL_START) * 4 / 10;
+
+/// Decoded values are written in groups of four
+const MAX_VALUES: usize = (MAX_POSTINGS * 2).div_ceil(4) * 4;
+
+/// Stream variable bytes.
+///
+/// Separates the lengths of the values from their bytes, so that four values
+/// can be decoded with a single shuffle.
+///
+/// Block layout: `id | count | control bytes | data bytes`.
+/// The values are the gaps between the doc ids of the postings followed by
+/// their term frequencies. Every control byte holds the byte lengths - 1 of
+/// four values, two bits each, starting with the lowest bits. The data bytes
+/// hold the values little endian without their leading zero bytes.
+/// The gap of the first posting is taken to the bias of the block.
+///
+/// On x86_64 with SSSE3 decoding uses SIMD instructions, otherwise a scalar
+/// fallback.
+pub struct StreamVByteCompressor;
+
+impl StreamVByteCompressor {
+    /// Returns the number of postings in a block. Fails if the block claims
+    /// more postings or data bytes than fit, as decoding it would read and
+    /// write out of bounds
+    fn checked_count(block: &Block) -> Result<usize> {
+        let block = &block.0;
+        let count = block[PAYLOAD_START] as usize;
+        if count > MAX_POSTINGS {
+            return Err(Error::InvalidBlock(Self::ID));
+        }
+        let data_size = (0..count * 2)
+            .map(|i| ((block[CONTROL_START + i / 4] >> (2 * (i % 4))) & 3) as usize + 1)
+            .sum::<usize>();
+        if CONTROL_START + count.div_ceil(2) + data_size > BLOCKSIZE {
+            return Err(Error::InvalidBlock(Self::ID));
+        }
+        Ok(count)
+    }
+
+    /// Decodes the gaps and term frequencies of a block into prefix summed
+    /// doc ids and term frequencies. The block has to pass `checked_count`
+    fn decode(block: &Block, values: &mut [u32; MAX_VALUES]) {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("ssse3") {
+                unsafe { ssse3::decode(block, values) };
+                return;
+            }
+        }
+        decode_scalar(block, values)
+    }
+}
+
+impl Compressor for StreamVByteCompressor {
+    const ID: u8 = 4;
+
+    fn fitting(data: &BiasedRingBuffer<Posting>) -> (usize, bool) {
+        let mut probe = data.clone();
+        let mut data_size = 0;
+        let mut count = 0usize;
+        let mut last = 0;
+        while let Some(posting) = probe.pop_front_biased() {
+            let posting_size = byte_len((posting.0).0 - last) + byte_len(posting.1);
+            // Two control bits per value, so one control byte per two postings
+            if CONTROL_START + (count + 1).div_ceil(2) + data_size + posting_size > BLOCKSIZE {
+                return (count, true);
+            }
+            data_size += posting_size;
+            count += 1;
+            last = (posting.0).0;
+        }
+        (count, count == MAX_POSTINGS)
+    }
+
+    fn write_block(data: &mut BiasedRingBuffer<Posting>, count: usize) -> Block {
+        let mut values = [0u32; MAX_VALUES];
+        let mut last = 0;
+        for i in 0..count {
+            let posting = data.pop_front_biased().unwrap();
+            values[i] = (posting.0).0 - last;
+            values[count + i] = posting.1;
+            last = (posting.0).0;
+        }
+        let mut block = [0u8; BLOCKSIZE];
+        block[0] = Self::ID;
+        block[PAYLOAD_START] = count as u8;
+        let mut offset = CONTROL_START + count.div_ceil(2);
+        for (i, value) in values[..count * 2].iter().enumerate() {
+            let len = byte_len(*value);
+            block[CONTROL_START + i / 4] |= ((len - 1) << (2 * (i % 4))) as u8;
+            block[offset..offset + len].copy_from_slice(&value.to_le_bytes()[..len]);
+            offset += len;
+        }
+        Block(block)
+    }
+
+    fn decompress(data: Block, target: &mut BiasedRingBuffer<Posting>) -> Result<()> {
+        let count = Self::checked_count(&data)?;
+        let mut values = [0u32; MAX_VALUES];
+        Self::decode(&data, &mut values);
+        for i in 0..count {
+            target.push_back_biased(Posting(DocId(values[i]), values[count + i]));
+        }
+        Ok(())
+    }
+}
+
+/// Number of bytes `value` takes without leading zero bytes, at least one
+fn byte_len(value: u32) -> usize {
+    match value {
+        0..=0xff => 1,
+        0x100..=0xffff => 2,
+        0x1_0000..=0xff_ffff => 3,
+        _ => 4,
+    }
+}
+
+fn decode_scalar(block: &Block, values: &mut [u32; MAX_VALUES]) {
+    let block = &block.0;
+    let count = block[PAYLOAD_START] as usize;
+    let mut offset = CONTROL_START + count.div_ceil(2);
+    for (i, value) in values[..count * 2].iter_mut().enumerate() {
+        let len = ((block[CONTROL_START + i / 4] >> (2 * (i % 4))) & 3) as usize + 1;
+        let mut bytes = [0u8; 4];
+        bytes[..len].copy_from_slice(&block[offset..offset + len]);
+        *value = u32::from_le_bytes(bytes);
+        offset += len;
+    }
+    let mut last = 0;
+    for gap in &mut values[..count] {
+        last += *gap;
+        *gap = last;
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod ssse3 {
+    use std::arch::x86_64::*;
+
+    use page_manager::{BLOCKSIZE, Block};
+    use compressor::PAYLOAD_START;
+    use super::{CONTROL_START, MAX_VALUES};
+
+    /// Shuffle masks that move the data bytes of four values into four u32,
+    /// indexed by their control byte. 0x80 clears a byte
+    const SHUFFLE: [[u8; 16]; 256] = shuffle_masks();
+
+    /// Number of data bytes of four values, indexed by their control byte
+    const LENGTHS: [u8; 256] = lengths();
+
+    const fn shuffle_masks() -> [[u8; 16]; 256] {
+        let mut masks = [[0x80u8; 16]; 256];
+        let mut control = 0;
+        while control < 256 {
+            let mut source = 0;
+            let mut value = 0;
+            while value < 4 {
+                let len = ((control >> (2 * value)) & 3) + 1;
+                let mut byte = 0;
+                while byte < len {
+                    masks[control][value * 4 + byte] = source as u8;
+                    source += 1;
+                    byte += 1;
+                }
+                value += 1;
+            }
+            control += 1;
+        }
+        masks
+    }
+
+    const fn lengths() -> [u8; 256] {
+        let mut lengths = [0u8; 256];
+        let mut control = 0;
+        while control < 256 {
+            let mut value = 0;
+            while value < 4 {
+                lengths[control] += (((control >> (2 * value)) & 3) + 1) as u8;
+                value += 1;
+            }
+            control += 1;
+        }
+        lengths
+    }
+
+    /// Same as `decode_scalar`, four values at a time
+    #[target_feature(enable = "ssse3")]
+    pub unsafe fn decode(block: &Block, values: &mut [u32; MAX_VALUES]) {
+        // Loads read 16 bytes, possibly past the end of the block
+        let mut padded = [0u8; BLOCKSIZE + 16];
+        padded[..BLOCKSIZE].copy_from_slice(&block.0);
+        let count = padded[PAYLOAD_START] as usize;
+        let mut offset = CONTROL_START + count.div_ceil(2);
+        for group in 0..(count * 2).div_ceil(4) {
+            let control = padded[CONTROL_START + group] as usize;
+            let data = _mm_loadu_si128(padded[offset..].as_ptr() as *const __m128i);
+            let mask = _mm_loadu_si128(SHUFFLE[control].as_ptr() as *const __m128i);
+            let decoded = _mm_shuffle_epi8(data, mask);
+            _mm_storeu_si128(values[group * 4..].as_mut_ptr() as *mut __m128i, decoded);
+            offset += LENGTHS[control] as usize;
+        }
+        // Prefix sum of the gaps. Lanes after the gaps are garbage
+        let mut carry = _mm_setzero_si128();
+        for group in 0..count.div_ceil(4) {
+            let lanes = values[group * 4..].as_mut_ptr() as *mut __m128i;
+            let mut sum = _mm_loadu_si128(lanes);
+            sum = _mm_add_epi32(sum, _mm_slli_si128(sum, 4));
+            sum = _mm_add_epi32(sum, _mm_slli_si128(sum, 8));
+            sum = _mm_add_epi32(sum, carry);
+            carry = _mm_shuffle_epi32(sum, 0xff);
+            // Do not overwrite the term frequencies after the gaps
+            let mut summed = [0u32; 4];
+            _mm_storeu_si128(summed.as_mut_ptr() as *mut __m128i, sum);
+            let end = (count - group * 4).min(4);
+            values[group * 4..group * 4 + end].copy_from_slice(&summed[..end]);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use utils::ring_buffer::BiasedRingBuffer;
+    use index::posting::{DocId, Posting};
+    use compressor::Compressor;
+
+    use error::Error;
+
+    use super::{StreamVByteCompressor, MAX_POSTINGS, MAX_VALUES, CONTROL_START, decode_scalar};
+
+    fn postings() -> Vec<Posting> {
+        (0..100u32)
+            .scan(0, |doc, i| {
+                *doc += [1, 300, 70_000, 20_000_000][i as usize % 4] + i;
+                Some(Posting(DocId(*doc), [1, 1000, 2][i as usize % 3]))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn roundtrip() {
+        let postings = postings();
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        let mut target = BiasedRingBuffer::<Posting>::new();
+        let mut decompressed = Vec::new();
+        for chunk in postings.chunks(16) {
+            for posting in chunk {
+                buffer.push_back(*posting);
+            }
+            while let Some(block) = StreamVByteCompressor::compress(&mut buffer) {
+                StreamVByteCompressor::decompress(block, &mut target).unwrap();
+                while let Some(posting) = target.pop_front() {
+                    decompressed.push(posting);
+                }
+            }
+        }
+        StreamVByteCompressor::decompress(StreamVByteCompressor::force_compress(&mut buffer),
+                                          &mut target).unwrap();
+        while let Some(posting) = target.pop_front() {
+            decompressed.push(posting);
+        }
+        assert_eq!(decompressed, postings);
+        assert!(decompressed.iter().zip(&postings).all(|(a, b)| a.tf() == b.tf()));
+    }
+
+    #[test]
+    fn dense() {
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        for i in 0..40 {
+            buffer.push_back(Posting(DocId(i), 1));
+        }
+        assert!(StreamVByteCompressor::compress(&mut buffer).is_some());
+        assert_eq!(buffer.count(), 40 - MAX_POSTINGS);
+        assert_eq!(StreamVByteCompressor::compress(&mut buffer), None);
+    }
+
+    #[test]
+    fn simd_same_as_scalar() {
+        let postings = postings();
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        for start in 0..postings.len() - 40 {
+            for posting in &postings[start..start + 40] {
+                buffer.push_back(*posting);
+            }
+            buffer.set_base(postings[start]);
+            let block = StreamVByteCompressor::force_compress(&mut buffer);
+            buffer.flush();
+            let mut scalar = [0u32; MAX_VALUES];
+            decode_scalar(&block, &mut scalar);
+            let mut decoded = [0u32; MAX_VALUES];
+            StreamVByteCompressor::decode(&block, &mut decoded);
+            let count = block.0[1] as usize * 2;
+            assert_eq!(&decoded[..count], &scalar[..count]);
+        }
+    }
+
+    #[test]
+    fn biased() {
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        buffer.set_base(Posting(DocId(1_000_000), 0));
+        buffer.push_back(Posting(DocId(1_000_000), 3));
+        buffer.push_back(Posting(DocId(1_000_300), 7));
+        let block = StreamVByteCompressor::force_compress(&mut buffer);
+        // Id, count, lengths - 1 of 0, 300, 3 and 7, then their bytes
+        assert_eq!(&block.0[..8], &[StreamVByteCompressor::ID, 2, 0b00_00_01_00, 0, 44, 1, 3, 7]);
+        let mut target = BiasedRingBuffer::<Posting>::new();
+        target.set_base(Posting(DocId(1_000_000), 0));
+        StreamVByteCompressor::decompress(block, &mut target).unwrap();
+        assert_eq!(target.pop_front().unwrap().tf(), 3);
+        assert_eq!(target.pop_front(), Some(Posting(DocId(1_000_300), 7)));
+        assert_eq!(target.pop_front(), None);
+    }
+    #[test]
+    fn forged() {
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        for i in 0..MAX_POSTINGS as u32 {
+            buffer.push_back(Posting(DocId(i), 1));
+        }
+        let block = StreamVByteCompressor::force_compress(&mut buffer);
+        let mut target = BiasedRingBuffer::<Posting>::new();
+        // More postings than a block can hold
+        for count in [MAX_POSTINGS + 1, 25, 255] {
+            let mut forged = block;
+            forged.0[1] = count as u8;
+            assert!(matches!(StreamVByteCompressor::decompress(forged, &mut target),
+                             Err(Error::InvalidBlock(StreamVByteCompressor::ID))));
+        }
+        // Values longer than the data bytes left
+        let mut forged = block;
+        forged.0[CONTROL_START] = 0xff;
+        assert!(StreamVByteCompressor::decompress(forged, &mut target).is_err());
+        assert_eq!(target.count(), 0);
+        StreamVByteCompressor::decompress(block, &mut target).unwrap();
+        assert_eq!(target.count(), MAX_POSTINGS);
+    }
+}
@@ -0,0 +1,178 @@
+use error::Result;
+use utils::ring_buffer::BiasedRingBuffer;
+use index::posting::{Posting, DocId};
+use page_manager::{BLOCKSIZE, Block};
+use compressor::{Compressor, PAYLOAD_START};
+
+/// Packs as many postings into a block as fit.
+///
+/// The first byte after the codec id holds the number of postings in the
+/// block. Each posting follows as the gap to the previous doc id and its term
+/// frequency, both as variable-byte integers: 7 bits per byte, least
+/// significant first, the high bit set on every byte but the last.
+/// The gap of the first posting is taken to the bias of the block.
+pub struct VByteCompressor;
+
+impl Compressor for VByteCompressor {
+    const ID: u8 = 2;
+
+    fn fitting(data: &BiasedRingBuffer<Posting>) -> (usize, bool) {
+        let mut probe = data.clone();
+        let mut size = PAYLOAD_START + 1;
+        let mut count = 0;
+        let mut last = 0;
+        while let Some(posting) = probe.pop_front_biased() {
+            let posting_size = encoded_len((posting.0).0 - last) + encoded_len(posting.1);
+            if size + posting_size > BLOCKSIZE {
+                return (count, true);
+            }
+            size += posting_size;
+            count += 1;
+            last = (posting.0).0;
+        }
+        (count, size == BLOCKSIZE)
+    }
+
+    fn write_block(data: &mut BiasedRingBuffer<Posting>, count: usize) -> Block {
+        let mut block = [0u8; BLOCKSIZE];
+        block[0] = Self::ID;
+        block[PAYLOAD_START] = count as u8;
+        let mut offset = PAYLOAD_START + 1;
+        let mut last = 0;
+        for _ in 0..count {
+            let posting = data.pop_front_biased().unwrap();
+            offset += write_vbyte(&mut block[offset..], (posting.0).0 - last);
+            offset += write_vbyte(&mut block[offset..], posting.1);
+            last = (posting.0).0;
+        }
+        Block(block)
+    }
+
+    fn decompress(data: Block, target: &mut BiasedRingBuffer<Posting>) -> Result<()> {
+        let count = data.0[PAYLOAD_START];
+        let mut offset = PAYLOAD_START + 1;
+        let mut last = 0;
+        for _ in 0..count {
+            let (gap, read) = read_vbyte(&data.0[offset..]);
+            offset += read;
+            let (tf, read) = read_vbyte(&data.0[offset..]);
+            offset += read;
+            last += gap;
+            target.push_back_biased(Posting(DocId(last), tf));
+        }
+        Ok(())
+    }
+}
+
+/// Number of bytes `value` takes as variable-byte integer
+pub fn encoded_len(value: u32) -> usize {
+    match value {
+        0..=0x7f => 1,
+        0x80..=0x3fff => 2,
+        0x4000..=0x1f_ffff => 3,
+        0x20_0000..=0xfff_ffff => 4,
+        _ => 5,
+    }
+}
+
+/// Writes `value` to the start of `target`. Returns the number of bytes written
+pub fn write_vbyte(target: &mut [u8], mut value: u32) -> usize {
+    let mut written = 0;
+    while value >= 0x80 {
+        target[written] = (value as u8 & 0x7f) | 0x80;
+        value >>= 7;
+        written += 1;
+    }
+    target[written] = value as u8;
+    written + 1
+}
+
+/// Reads a value from the start of `source`. Returns it and the number of
+/// bytes read
+pub fn read_vbyte(source: &[u8]) -> (u32, usize) {
+    let mut value = 0;
+    for (i, byte) in source.iter().enumerate() {
+        value |= ((byte & 0x7f) as u32) << (7 * i);
+        if byte & 0x80 == 0 {
+            return (value, i + 1);
+        }
+    }
+    (value, source.len())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use utils::ring_buffer::BiasedRingBuffer;
+    use index::posting::{DocId, Posting};
+    use compressor::Compressor;
+
+    use super::{VByteCompressor, encoded_len, read_vbyte, write_vbyte};
+
+    #[test]
+    fn vbyte() {
+        for value in &[0, 1, 127, 128, 300, 16383, 16384, 1 << 21, 1 << 28, u32::MAX] {
+            let mut bytes = [0u8; 5];
+            let written = write_vbyte(&mut bytes, *value);
+            assert_eq!(written, encoded_len(*value));
+            assert_eq!(read_vbyte(&bytes), (*value, written));
+        }
+    }
+
+    #[test]
+    fn compress() {
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        assert_eq!(VByteCompressor::compress(&mut buffer), None);
+        // Small gaps and term frequencies take two bytes per posting
+        for i in 0..40 {
+            buffer.push_back(Posting(DocId(i), 1));
+        }
+        assert!(VByteCompressor::compress(&mut buffer).is_some());
+        assert_eq!(buffer.count(), 40 - 31);
+        assert_eq!(VByteCompressor::compress(&mut buffer), None);
+    }
+
+    #[test]
+    fn decompress() {
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        let postings = (0..40u32)
+            .map(|i| Posting(DocId(i * i * 1000), i * 37 + 1))
+            .collect::<Vec<_>>();
+        for posting in &postings {
+            buffer.push_back(*posting);
+        }
+        let mut decompressed = Vec::new();
+        let mut target = BiasedRingBuffer::<Posting>::new();
+        while let Some(block) = VByteCompressor::compress(&mut buffer) {
+            VByteCompressor::decompress(block, &mut target).unwrap();
+            while let Some(posting) = target.pop_front() {
+                decompressed.push(posting);
+            }
+        }
+        VByteCompressor::decompress(VByteCompressor::force_compress(&mut buffer), &mut target)
+            .unwrap();
+        while let Some(posting) = target.pop_front() {
+            decompressed.push(posting);
+        }
+        assert_eq!(buffer.count(), 0);
+        assert_eq!(decompressed, postings);
+        assert!(decompressed.iter().zip(&postings).all(|(a, b)| a.tf() == b.tf()));
+    }
+
+    #[test]
+    fn biased() {
+        let mut buffer = BiasedRingBuffer::<Posting>::new();
+        buffer.set_base(Posting(DocId(1_000_000), 0));
+        buffer.push_back(Posting(DocId(1_000_000), 3));
+        buffer.push_back(Posting(DocId(1_000_005), 7));
+        let block = VByteCompressor::force_compress(&mut buffer);
+        // Id and count, then gaps and term frequencies relative to the bias
+        assert_eq!(&block.0[..6], &[VByteCompressor::ID, 2, 0, 3, 5, 7]);
+        let mut target = BiasedRingBuffer::<Posting>::new();
+        target.set_base(Posting(DocId(1_000_000), 0));
+        VByteCompressor::decompress(block, &mut target).unwrap();
+        assert_eq!(target.pop_front().unwrap().tf(), 3);
+        assert_eq!(target.pop_front(), Some(Posting(DocId(1_000_005), 7)));
+        assert_eq!(target.pop_front(), None);
+    }
+}
@@ -0,0 +1,90 @@
+//! The error type of perlin-core.
+//!
+//! Everything that writes pages or touches the disk returns a `Result`.
+//! Iterating over postings does not: A page that cannot be read ends the
+//! iteration early and the iterator keeps the error, see
+//! `PostingIterator::error`. Use `Index::verify` to check all pages up front.
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+use page_manager::{PageId, CorruptPage};
+use index::posting::DocId;
+
+pub type Result<T> = result::Result<T, Error>;
+
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing failed, e.g. because the disk is full
+    Io(io::Error),
+    /// A page read does not match its checksum
+    Corruption(CorruptPage),
+    /// Doc ids have to increase with every document. `doc_id` did not
+    InvalidDocId { doc_id: DocId, last_doc_id: DocId },
+    /// A page id that was never handed out or was already flushed
+    UnknownPage(PageId),
+    /// A block that the codec with this id cannot decode
+    InvalidBlock(u8),
+    /// A block starts with the id of no known codec
+    UnknownCodec(u8),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "I/O error: {}", err),
+            Error::Corruption(ref corrupt) => corrupt.fmt(f),
+            Error::InvalidDocId { doc_id, last_doc_id } => {
+                write!(f,
+                       "Doc id {} is not greater than the last doc id {}",
+                       doc_id.0,
+                       last_doc_id.0)
+            }
+            Error::UnknownPage(page_id) => write!(f, "Unknown page {}", page_id.0),
+            Error::InvalidBlock(codec) => write!(f, "Invalid block of codec {}", codec),
+            Error::UnknownCodec(codec) => write!(f, "Block of unknown codec {}", codec),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            Error::Corruption(ref corrupt) => Some(corrupt),
+            _ => None,
+        }
+    }
+}
+
+/// I/O errors cannot be cloned. Their clones keep the kind and message only
+impl Clone for Error {
+    fn clone(&self) -> Self {
+        match *self {
+            Error::Io(ref err) => Error::Io(io::Error::new(err.kind(), err.to_string())),
+            Error::Corruption(corrupt) => Error::Corruption(corrupt),
+            Error::InvalidDocId { doc_id, last_doc_id } => {
+                Error::InvalidDocId {
+                    doc_id,
+                    last_doc_id,
+                }
+            }
+            Error::UnknownPage(page_id) => Error::UnknownPage(page_id),
+            Error::InvalidBlock(codec) => Error::InvalidBlock(codec),
+            Error::UnknownCodec(codec) => Error::UnknownCodec(codec),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<CorruptPage> for Error {
+    fn from(corrupt: CorruptPage) -> Self {
+        Error::Corruption(corrupt)
+    }
+}
@@ -0,0 +1,413 @@
+//! Roaring bitmap representation of dense listings.
+//!
+//! Doc ids are grouped into containers by their upper 16 bits. A container
+//! with few postings stores the lower 16 bits of their doc ids as a sorted
+//! array. A container with many postings stores a bitmap of all 65536
+//! possible doc ids instead, so seeking to a doc id is a lookup of its bit.
+//! The term frequencies follow with a fixed width.
+//!
+//! See `Index::optimize`.
+use std::io::{self, Read, Write};
+
+use error::{Error, Result};
+use utils::persistence::Persistent;
+use utils::seeking_iterator::SeekingIterator;
+use page_manager::{Pages, RamPageCache, PageCache, PageStore, BlockManager};
+use index::posting::{Posting, DocId};
+use index::bits::{BitWriter, BitReader, low_mask, store_blocks};
+
+/// Number of lower doc id bits stored in a container
+const CONTAINER_BITS: u32 = 16;
+
+/// Containers with more postings store a bitmap. Both take 8 KiB then
+const MAX_ARRAY_LEN: u32 = 4096;
+
+/// Number of u64 words of a bitmap container
+const BITMAP_WORDS: u32 = (1 << CONTAINER_BITS) / 64;
+
+/// Listings are dense if at least every `DENSITY`th document of their range
+/// contains the term. Like a single bitmap container is worth it
+const DENSITY: u64 = ((1 << CONTAINER_BITS) / MAX_ARRAY_LEN) as u64;
+
+#[derive(Debug, Clone, Copy)]
+struct Container {
+    // Upper bits of the doc ids
+    key: u32,
+    len: u32,
+    // Index of the first posting in the listing
+    rank: u32,
+    // First word of the array or bitmap
+    start: u32,
+}
+
+impl Container {
+    fn is_bitmap(&self) -> bool {
+        self.len > MAX_ARRAY_LEN
+    }
+}
+
+impl Persistent for Container {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.key.write_to(target)?;
+        self.len.write_to(target)?;
+        self.rank.write_to(target)?;
+        self.start.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok(Container {
+            key: u32::read_from(source)?,
+            len: u32::read_from(source)?,
+            rank: u32::read_from(source)?,
+            start: u32::read_from(source)?,
+        })
+    }
+}
+
+/// A static listing as roaring bitmap, stored on pages.
+///
+/// The pages hold the containers followed by the term frequencies, which
+/// start with a new block.
+#[derive(Clone, Debug)]
+pub struct Bitmap {
+    pages: Pages,
+    len: u32,
+    tf_width: u32,
+    // First block of the term frequencies
+    tf_start: u32,
+    containers: Vec<Container>,
+}
+
+impl Bitmap {
+    /// Are sorted postings dense enough to be stored as bitmap?
+    pub fn is_dense(postings: &[Posting]) -> bool {
+        match (postings.first(), postings.last()) {
+            (Some(first), Some(last)) => {
+                let range = (last.doc_id().0 - first.doc_id().0) as u64 + 1;
+                range <= postings.len() as u64 * DENSITY
+            }
+            _ => false,
+        }
+    }
+
+    /// Encodes sorted postings and stores them on pages
+    pub fn new<S: PageStore>(postings: &[Posting],
+                             page_cache: &mut RamPageCache<S>)
+                             -> Result<Self> {
+        let max_tf = postings.iter().map(|posting| posting.tf()).max().unwrap_or(0);
+        let tf_width = 32 - max_tf.leading_zeros();
+
+        let mut data = BitWriter::new();
+        let mut tfs = BitWriter::new();
+        let mut containers = Vec::new();
+        let mut rank = 0;
+        for group in postings.chunk_by(|a, b| a.doc_id().0 >> CONTAINER_BITS ==
+                                              b.doc_id().0 >> CONTAINER_BITS) {
+            let container = Container {
+                key: group[0].doc_id().0 >> CONTAINER_BITS,
+                len: group.len() as u32,
+                rank,
+                start: (data.len() / 64) as u32,
+            };
+            let lows = group.iter()
+                .map(|posting| posting.doc_id().0 & low_mask(CONTAINER_BITS) as u32);
+            if container.is_bitmap() {
+                let mut bitmap = vec![0u64; BITMAP_WORDS as usize];
+                for low in lows {
+                    bitmap[low as usize / 64] |= 1 << (low % 64);
+                }
+                for word in bitmap {
+                    data.write(word, 64);
+                }
+            } else {
+                for low in lows {
+                    data.write(low as u64, CONTAINER_BITS);
+                }
+                data.align();
+            }
+            for posting in group {
+                tfs.write(posting.tf() as u64, tf_width);
+            }
+            containers.push(container);
+            rank += group.len() as u32;
+        }
+
+        let data_blocks = data.blocks();
+        let pages = store_blocks(data_blocks.iter().chain(&tfs.blocks()), page_cache)?;
+        Ok(Bitmap {
+            pages,
+            len: postings.len() as u32,
+            tf_width,
+            tf_start: data_blocks.len() as u32,
+            containers,
+        })
+    }
+
+    pub fn decoder<'a>(&'a self, cache: &'a dyn PageCache) -> BitmapDecoder<'a> {
+        BitmapDecoder {
+            bitmap: self,
+            data: BitReader::new(cache, &self.pages, 0),
+            tfs: BitReader::new(cache, &self.pages, self.tf_start),
+            container: 0,
+            offset: 0,
+            bit: 0,
+        }
+    }
+
+    /// Reads all pages to check that they are intact
+    pub fn verify(&self, cache: &dyn PageCache) -> Result<()> {
+        self.pages.verify(cache)
+    }
+
+    /// Returns the pages to the page cache.
+    /// The listing must not be used afterwards.
+    pub fn release<S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>) -> Result<()> {
+        for page_id in &self.pages.0 {
+            page_cache.delete_page(*page_id)?;
+        }
+        if let Some(unfull_page) = self.pages.take_unfull() {
+            page_cache.delete_unfull(unfull_page.page_id())?;
+        }
+        Ok(())
+    }
+}
+
+impl Persistent for Bitmap {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.pages.write_to(target)?;
+        self.len.write_to(target)?;
+        self.tf_width.write_to(target)?;
+        self.tf_start.write_to(target)?;
+        self.containers.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok(Bitmap {
+            pages: Pages::read_from(source)?,
+            len: u32::read_from(source)?,
+            tf_width: u32::read_from(source)?,
+            tf_start: u32::read_from(source)?,
+            containers: Vec::read_from(source)?,
+        })
+    }
+}
+
+/// Decodes the postings of a bitmap listing
+#[derive(Clone, Debug)]
+pub struct BitmapDecoder<'a> {
+    bitmap: &'a Bitmap,
+    data: BitReader<'a>,
+    tfs: BitReader<'a>,
+    // Index of the current container
+    container: usize,
+    // Number of postings of the current container already returned
+    offset: u32,
+    // Bit of a bitmap container to look for the next posting from
+    bit: u32,
+}
+
+impl<'a> BitmapDecoder<'a> {
+    /// The error that ended the iteration early, if any
+    pub fn error(&self) -> Option<&Error> {
+        self.data.error().or(self.tfs.error())
+    }
+
+    /// Lower bits of the next posting in a bitmap container
+    fn next_bit(&mut self, container: &Container) -> u32 {
+        let mut word_index = self.bit / 64;
+        let mut word = self.data.word((container.start + word_index) as usize) &
+                       (!0u64 << (self.bit % 64));
+        while word == 0 && self.data.error().is_none() {
+            word_index += 1;
+            word = self.data.word((container.start + word_index) as usize);
+        }
+        let bit = word_index * 64 + word.trailing_zeros();
+        self.bit = bit + 1;
+        bit
+    }
+
+    /// Lower bits of a posting in an array container
+    fn array_value(&mut self, container: &Container, offset: u32) -> u32 {
+        let bit = container.start as u64 * 64 + (offset * CONTAINER_BITS) as u64;
+        self.data.read(bit, CONTAINER_BITS) as u32
+    }
+
+    /// Number of postings from bit `from` to bit `to` of a bitmap container
+    fn count_ones(&mut self, container: &Container, from: u32, to: u32) -> u32 {
+        let (first, last) = (from / 64, to / 64);
+        let mut count = 0;
+        for word_index in first..last + 1 {
+            let mut word = self.data.word((container.start + word_index) as usize);
+            if word_index == first {
+                word &= !0u64 << (from % 64);
+            }
+            if word_index == last {
+                word &= low_mask(to % 64);
+            }
+            count += word.count_ones();
+        }
+        count
+    }
+}
+
+impl<'a> Iterator for BitmapDecoder<'a> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Posting> {
+        loop {
+            let container = *self.bitmap.containers.get(self.container)?;
+            if self.offset < container.len {
+                let low = if container.is_bitmap() {
+                    self.next_bit(&container)
+                } else {
+                    self.array_value(&container, self.offset)
+                };
+                let index = (container.rank + self.offset) as u64;
+                let tf_width = self.bitmap.tf_width;
+                let tf = self.tfs.read(index * tf_width as u64, tf_width) as u32;
+                if self.error().is_some() {
+                    self.container = self.bitmap.containers.len();
+                    return None;
+                }
+                self.offset += 1;
+                return Some(Posting(DocId(container.key << CONTAINER_BITS | low), tf));
+            }
+            self.container += 1;
+            self.offset = 0;
+            self.bit = 0;
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = match self.bitmap.containers.get(self.container) {
+            Some(container) => (self.bitmap.len - container.rank - self.offset) as usize,
+            None => 0,
+        };
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> SeekingIterator for BitmapDecoder<'a> {
+    type Item = Posting;
+
+    fn next_seek(&mut self, other: &Posting) -> Option<Posting> {
+        let key = other.doc_id().0 >> CONTAINER_BITS;
+        let skipped = self.bitmap.containers[self.container..]
+            .partition_point(|container| container.key < key);
+        if skipped > 0 {
+            self.container += skipped;
+            self.offset = 0;
+            self.bit = 0;
+        }
+        let container = *self.bitmap.containers.get(self.container)?;
+        if container.key == key {
+            let low = other.doc_id().0 & low_mask(CONTAINER_BITS) as u32;
+            if container.is_bitmap() {
+                // Jump right to the bit of the doc id
+                if low > self.bit {
+                    self.offset += self.count_ones(&container, self.bit, low);
+                    self.bit = low;
+                }
+            } else {
+                while self.offset < container.len &&
+                      self.array_value(&container, self.offset) < low {
+                    self.offset += 1;
+                }
+            }
+        }
+        loop {
+            let posting = self.next()?;
+            if posting.doc_id() >= other.doc_id() {
+                return Some(posting);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use test_utils::create_test_dir;
+
+    use super::Bitmap;
+    use utils::persistence::Persistent;
+    use utils::seeking_iterator::SeekingIterator;
+    use index::posting::{Posting, DocId};
+    use page_manager::{FsPageManager, RamPageCache};
+
+    fn new_cache(name: &str) -> RamPageCache {
+        let path = &create_test_dir(format!("bitmap/{}", name).as_str());
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        RamPageCache::new(pmgr)
+    }
+
+    /// Dense at first, sparse in the middle and dense again
+    fn postings() -> Vec<Posting> {
+        (0..100_000u32)
+            .filter(|i| i % 3 != 0)
+            .chain((100_000..300_000).step_by(97))
+            .chain(300_000..310_000)
+            .map(|i| Posting(DocId(i), i % 5 + 1))
+            .collect()
+    }
+
+    #[test]
+    fn decode() {
+        let mut cache = new_cache("decode");
+        let postings = postings();
+        let bitmap = Bitmap::new(&postings, &mut cache).unwrap();
+        assert!(bitmap.containers.iter().any(|container| container.is_bitmap()));
+        assert!(bitmap.containers.iter().any(|container| !container.is_bitmap()));
+        let decoder = bitmap.decoder(&cache);
+        assert_eq!(decoder.size_hint(), (postings.len(), Some(postings.len())));
+        let decoded = decoder.collect::<Vec<_>>();
+        assert_eq!(decoded, postings);
+        assert!(decoded.iter().zip(&postings).all(|(a, b)| a.tf() == b.tf()));
+    }
+
+    #[test]
+    fn seek() {
+        let mut cache = new_cache("seek");
+        let postings = postings();
+        let bitmap = Bitmap::new(&postings, &mut cache).unwrap();
+        let mut decoder = bitmap.decoder(&cache);
+        // Targets are further apart than postings
+        for target in (0..320_000).step_by(101) {
+            let index = postings.partition_point(|p| p.doc_id().0 < target);
+            assert_eq!(decoder.next_seek(&Posting(DocId(target), 0)), postings.get(index).cloned());
+            assert_eq!(decoder.size_hint().0, postings.len().saturating_sub(index + 1));
+        }
+        let mut decoder = bitmap.decoder(&cache);
+        assert_eq!(decoder.next_seek(&Posting(DocId(3), 0)), Some(Posting(DocId(4), 5)));
+        assert_eq!(decoder.next(), Some(Posting(DocId(5), 1)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(0), 0)), Some(Posting(DocId(7), 3)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(100_001), 0)),
+                   Some(Posting(DocId(100_097), 3)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(309_999), 0)),
+                   Some(Posting(DocId(309_999), 5)));
+        assert_eq!(decoder.next(), None);
+    }
+
+    #[test]
+    fn is_dense() {
+        let dense = (1000..2000).map(|i| Posting(DocId(i * 16), 1)).collect::<Vec<_>>();
+        assert!(Bitmap::is_dense(&dense));
+        let sparse = (1000..2000).map(|i| Posting(DocId(i * 17), 1)).collect::<Vec<_>>();
+        assert!(!Bitmap::is_dense(&sparse));
+        assert!(!Bitmap::is_dense(&[]));
+    }
+
+    #[test]
+    fn persistent() {
+        let mut cache = new_cache("persistent");
+        let postings = postings();
+        let bitmap = Bitmap::new(&postings, &mut cache).unwrap();
+        let mut bytes = Vec::new();
+        bitmap.write_to(&mut bytes).unwrap();
+        let mut restored = Bitmap::read_from(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(restored.decoder(&cache).collect::<Vec<_>>(), postings);
+        restored.release(&mut cache).unwrap();
+    }
+}
@@ -0,0 +1,201 @@
+//! Bit vectors stored in blocks, used by the static listing representations.
+//!
+//! Bits are collected in u64 words, which are written to blocks little
+//! endian. Every region of bits starts with a new block.
+use error::{Error, Result};
+use page_manager::{Pages, Block, BlockIter, BlockId, RamPageCache, PageCache, PageStore,
+                   BlockManager, BLOCKSIZE};
+
+/// Number of u64 words stored in a block
+pub const WORDS_PER_BLOCK: usize = BLOCKSIZE / 8;
+
+/// Stores blocks on pages, filling them like `Listing` does
+pub fn store_blocks<'a, I, S>(blocks: I, page_cache: &mut RamPageCache<S>) -> Result<Pages>
+    where I: IntoIterator<Item = &'a Block>,
+          S: PageStore
+{
+    let mut pages = Pages::new();
+    let mut current_page = None;
+    let mut block_counter = BlockId::first();
+    for block in blocks {
+        if block_counter == BlockId::first() {
+            current_page = Some(page_cache.store_block(*block));
+        } else {
+            page_cache.store_in_place(current_page.unwrap(), block_counter, *block)?;
+        }
+        if block_counter == BlockId::last() {
+            pages.push(page_cache.flush_page(current_page.take().unwrap())?);
+        }
+        block_counter.inc();
+    }
+    if let Some(unfull_page) = current_page {
+        pages.add_unfull(page_cache.flush_unfull(unfull_page, block_counter)?);
+    }
+    Ok(pages)
+}
+
+/// The lowest `width` bits set. `width` must be below 64
+pub fn low_mask(width: u32) -> u64 {
+    (1u64 << width) - 1
+}
+
+/// Collects bits in u64 words
+pub struct BitWriter {
+    words: Vec<u64>,
+    len: u64,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        BitWriter {
+            words: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Appends `value`, which has to fit into `width` bits
+    pub fn write(&mut self, value: u64, width: u32) {
+        let (word, offset) = ((self.len / 64) as usize, self.len % 64);
+        self.len += width as u64;
+        self.reserve();
+        if width > 0 {
+            self.words[word] |= value << offset;
+            if offset + width as u64 > 64 {
+                self.words[word + 1] |= value >> (64 - offset);
+            }
+        }
+    }
+
+    /// Sets the bit at `bit`, appending zeros before it
+    pub fn set(&mut self, bit: u64) {
+        self.len = bit + 1;
+        self.reserve();
+        self.words[(bit / 64) as usize] |= 1 << (bit % 64);
+    }
+
+    /// Number of bits written
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Appends zeros up to the next word
+    pub fn align(&mut self) {
+        self.len = self.len.div_ceil(64) * 64;
+    }
+
+    /// Makes room for all bits up to `len`
+    fn reserve(&mut self) {
+        let words = self.len.div_ceil(64) as usize;
+        if words > self.words.len() {
+            self.words.resize(words, 0);
+        }
+    }
+
+    /// The words written, in blocks
+    pub fn blocks(&self) -> Vec<Block> {
+        self.words
+            .chunks(WORDS_PER_BLOCK)
+            .map(|words| {
+                let mut block = [0u8; BLOCKSIZE];
+                for (bytes, word) in block.chunks_mut(8).zip(words) {
+                    bytes.copy_from_slice(&word.to_le_bytes());
+                }
+                Block(block)
+            })
+            .collect()
+    }
+}
+
+/// Reads the bits of blocks starting at a block of some pages.
+/// Words have to be requested in ascending order
+#[derive(Clone, Debug)]
+pub struct BitReader<'a> {
+    blocks: BlockIter<'a>,
+    // Index of the decoded block and of the next block `blocks` yields
+    block_index: usize,
+    next_block: usize,
+    words: [u64; WORDS_PER_BLOCK],
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(cache: &'a dyn PageCache, pages: &Pages, start: u32) -> Self {
+        let mut blocks = BlockIter::new(cache, pages.clone());
+        blocks.skip_blocks(start as usize);
+        BitReader {
+            blocks,
+            block_index: usize::MAX,
+            next_block: 0,
+            words: [0; WORDS_PER_BLOCK],
+        }
+    }
+
+    /// The error of the page that could not be read. Words from then on
+    /// are zero
+    pub fn error(&self) -> Option<&Error> {
+        self.blocks.error()
+    }
+
+    /// Returns the word at `index` words after the start
+    pub fn word(&mut self, index: usize) -> u64 {
+        let block_index = index / WORDS_PER_BLOCK;
+        if block_index != self.block_index {
+            self.blocks.skip_blocks(block_index - self.next_block);
+            let block = self.blocks.next().unwrap_or(Block([0; BLOCKSIZE]));
+            for (word, bytes) in self.words.iter_mut().zip(block.0.chunks(8)) {
+                let mut word_bytes = [0u8; 8];
+                word_bytes.copy_from_slice(bytes);
+                *word = u64::from_le_bytes(word_bytes);
+            }
+            self.block_index = block_index;
+            self.next_block = block_index + 1;
+        }
+        self.words[index % WORDS_PER_BLOCK]
+    }
+
+    /// Reads `width` bits starting at `bit`
+    pub fn read(&mut self, bit: u64, width: u32) -> u64 {
+        if width == 0 {
+            return 0;
+        }
+        let (word, offset) = ((bit / 64) as usize, bit % 64);
+        let mut value = self.word(word) >> offset;
+        if offset + width as u64 > 64 {
+            value |= self.word(word + 1) << (64 - offset);
+        }
+        value & low_mask(width)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use test_utils::create_test_dir;
+
+    use super::{BitWriter, BitReader, store_blocks};
+    use page_manager::{FsPageManager, RamPageCache};
+
+    #[test]
+    fn write_and_read() {
+        let path = &create_test_dir("bits/write_and_read");
+        let mut cache = RamPageCache::new(FsPageManager::new(&path.join("pages.bin")).unwrap());
+        let mut writer = BitWriter::new();
+        // Values cross word and block boundaries
+        for i in 0..1000u64 {
+            writer.write(i * 31 % (1 << 13), 13);
+        }
+        writer.set(20_000);
+        assert_eq!(writer.len(), 20_001);
+        writer.align();
+        writer.write(5, 3);
+        assert_eq!(writer.len(), 20_032 + 3);
+        let pages = store_blocks(&writer.blocks(), &mut cache).unwrap();
+        let mut reader = BitReader::new(&cache, &pages, 0);
+        for i in 0..1000u64 {
+            assert_eq!(reader.read(i * 13, 13), i * 31 % (1 << 13));
+        }
+        assert_eq!(reader.read(20_000, 1), 1);
+        assert_eq!(reader.word(313), 5);
+        // Past the end everything is zero
+        assert_eq!(reader.word(1000), 0);
+    }
+}
@@ -5,7 +5,7 @@ use std::fmt::{Formatter, Error, Debug, Display};
 use index::Index;
 use index::listing::Listing;
 
-impl<T: Hash + Eq> Debug for Index<T> {
+impl<T: Hash + Eq, TStore> Debug for Index<T, TStore> {
 
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         if f.alternate() {
@@ -18,22 +18,26 @@ impl<T: Hash + Eq> Debug for Index<T> {
 }
 
 
-impl<T: Hash + Eq> Display for Index<T> {
+impl<T: Hash + Eq, TStore> Display for Index<T, TStore> {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
         display(self, f)
     }
 }
 
-fn display<T: Hash + Eq>(index: &Index<T>, f: &mut Formatter) -> Result<(), Error> {
+fn display<T: Hash + Eq, TStore>(index: &Index<T, TStore>, f: &mut Formatter) -> Result<(), Error> {
     writeln!(f, "Index with {} Documents; Last DocId is {:?}", index.doc_count, index.last_doc_id)
 }
 
-fn debug<T: Hash + Eq>(index: &Index<T>, f: &mut Formatter) -> Result<(), Error> {
+fn debug<T: Hash + Eq, TStore>(index: &Index<T, TStore>,
+                               f: &mut Formatter)
+                               -> Result<(), Error> {
     writeln!(f, "Index with {} Documents; Last DocId is {:?}", index.doc_count, index.last_doc_id)?;
     writeln!(f, "\tIt has {} listings!", index.listings.len())?;
     writeln!(f, "\tThe listings heap size is {}!", index.listings.len() * mem::size_of::<Listing>()) 
 }
 
-fn debug_verbose<T: Hash + Eq>(index: &Index<T>, f: &mut Formatter) -> Result<(), Error> {
+fn debug_verbose<T: Hash + Eq, TStore>(index: &Index<T, TStore>,
+                                       f: &mut Formatter)
+                                       -> Result<(), Error> {
     writeln!(f, "Index with {} Documents; Last DocId is {:?}", index.doc_count, index.last_doc_id)
 }
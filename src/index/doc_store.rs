@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+use utils::persistence::Persistent;
+use index::posting::DocId;
+use index::vocabulary::TermId;
+
+/// Forward index of the documents.
+/// Stores for every document the sorted list of terms it contains together
+/// with their term frequencies.
+///
+/// This allows deleting and updating documents without scanning every
+/// listing of the index.
+/// It also keeps track of the document lengths for scoring.
+#[derive(Clone, Debug, Default)]
+pub struct DocStore {
+    docs: BTreeMap<DocId, Vec<(TermId, u32)>>,
+    // Number of terms of each document. Derived from docs
+    lengths: BTreeMap<DocId, u32>,
+    total_length: u64,
+}
+
+impl DocStore {
+    pub fn new() -> Self {
+        DocStore::default()
+    }
+
+    /// Sets the terms and term frequencies of a document.
+    /// `terms` must be sorted by term and deduplicated.
+    pub fn insert(&mut self, doc_id: DocId, terms: Vec<(TermId, u32)>) {
+        debug_assert!(terms.windows(2).all(|w| w[0].0 < w[1].0));
+        self.remove(doc_id);
+        let length = terms.iter().map(|&(_, tf)| tf).sum();
+        self.set_length(doc_id, length);
+        self.docs.insert(doc_id, terms);
+    }
+
+    /// Adds a single term with a term frequency of 1 to a document
+    pub fn add_term(&mut self, doc_id: DocId, term_id: TermId) {
+        let terms = self.docs.entry(doc_id).or_default();
+        if let Err(index) = terms.binary_search_by_key(&term_id, |&(term_id, _)| term_id) {
+            terms.insert(index, (term_id, 1));
+            let length = self.lengths.get(&doc_id).cloned().unwrap_or(0);
+            self.set_length(doc_id, length + 1);
+        }
+    }
+
+    /// Returns the terms of a document and how often they occur.
+    /// The terms are the same as the listings a document appears in
+    pub fn get_terms(&self, doc_id: DocId) -> Option<&[(TermId, u32)]> {
+        self.docs.get(&doc_id).map(|terms| terms as &[(TermId, u32)])
+    }
+
+    pub fn remove(&mut self, doc_id: DocId) -> Option<Vec<(TermId, u32)>> {
+        if let Some(length) = self.lengths.remove(&doc_id) {
+            self.total_length -= length as u64;
+        }
+        self.docs.remove(&doc_id)
+    }
+
+    pub fn contains(&self, doc_id: DocId) -> bool {
+        self.docs.contains_key(&doc_id)
+    }
+
+    /// The number of terms in a document, counting repetitions
+    pub fn doc_length(&self, doc_id: DocId) -> Option<u32> {
+        self.lengths.get(&doc_id).cloned()
+    }
+
+    /// The average number of terms in a document
+    pub fn avg_doc_length(&self) -> f32 {
+        if self.docs.is_empty() {
+            return 0.0;
+        }
+        self.total_length as f32 / self.docs.len() as f32
+    }
+
+    fn set_length(&mut self, doc_id: DocId, length: u32) {
+        if let Some(old) = self.lengths.insert(doc_id, length) {
+            self.total_length -= old as u64;
+        }
+        self.total_length += length as u64;
+    }
+}
+
+/// Only the terms are stored. Lengths are derived when reading
+impl Persistent for DocStore {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.docs.len().write_to(target)?;
+        for (doc_id, terms) in &self.docs {
+            doc_id.write_to(target)?;
+            terms.write_to(target)?;
+        }
+        Ok(())
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        let len = usize::read_from(source)?;
+        let mut store = DocStore::new();
+        for _ in 0..len {
+            let doc_id = DocId::read_from(source)?;
+            store.insert(doc_id, Vec::read_from(source)?);
+        }
+        Ok(store)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::DocStore;
+    use utils::persistence::Persistent;
+    use index::posting::DocId;
+    use index::vocabulary::TermId;
+
+    #[test]
+    fn basic() {
+        let mut store = DocStore::new();
+        store.insert(DocId(0), vec![(TermId(1), 2), (TermId(5), 1)]);
+        assert!(store.contains(DocId(0)));
+        assert!(!store.contains(DocId(1)));
+        assert_eq!(store.get_terms(DocId(0)),
+                   Some(&[(TermId(1), 2u32), (TermId(5), 1)] as &[(TermId, u32)]));
+        assert_eq!(store.remove(DocId(0)), Some(vec![(TermId(1), 2), (TermId(5), 1)]));
+        assert_eq!(store.get_terms(DocId(0)), None);
+    }
+
+    #[test]
+    fn add_term() {
+        let mut store = DocStore::new();
+        store.add_term(DocId(3), TermId(10));
+        store.add_term(DocId(3), TermId(2));
+        store.add_term(DocId(3), TermId(10));
+        assert_eq!(store.get_terms(DocId(3)),
+                   Some(&[(TermId(2), 1u32), (TermId(10), 1)] as &[(TermId, u32)]));
+    }
+
+    #[test]
+    fn lengths() {
+        let mut store = DocStore::new();
+        assert_eq!(store.avg_doc_length(), 0.0);
+        store.insert(DocId(0), vec![(TermId(1), 2), (TermId(5), 1)]);
+        store.add_term(DocId(1), TermId(1));
+        assert_eq!(store.doc_length(DocId(0)), Some(3));
+        assert_eq!(store.doc_length(DocId(1)), Some(1));
+        assert_eq!(store.avg_doc_length(), 2.0);
+        store.insert(DocId(0), vec![(TermId(1), 7)]);
+        assert_eq!(store.avg_doc_length(), 4.0);
+        store.remove(DocId(0));
+        assert_eq!(store.doc_length(DocId(0)), None);
+        assert_eq!(store.avg_doc_length(), 1.0);
+    }
+
+    #[test]
+    fn persistent() {
+        let mut store = DocStore::new();
+        store.insert(DocId(0), vec![(TermId(1), 1), (TermId(5), 3)]);
+        store.insert(DocId(7), vec![(TermId(2), 1)]);
+        let mut bytes = Vec::new();
+        store.write_to(&mut bytes).unwrap();
+        let restored = DocStore::read_from(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(restored.get_terms(DocId(0)), store.get_terms(DocId(0)));
+        assert_eq!(restored.get_terms(DocId(7)), store.get_terms(DocId(7)));
+        assert_eq!(restored.doc_length(DocId(0)), Some(4));
+    }
+}
@@ -0,0 +1,343 @@
+//! Elias-Fano representation of static listings.
+//!
+//! A doc id is split into its lower bits, stored with a fixed width, and its
+//! upper bits, stored in unary: for the i-th posting with upper bits h, bit
+//! h + i of the upper bit vector is set. The term frequencies follow with a
+//! fixed width, too.
+//!
+//! Seeking to a doc id means skipping a number of zeros in the upper bits.
+//! The listing samples where every `SAMPLE_RATE`th zero is, so only the bits
+//! from the closest sample on are scanned.
+//!
+//! See `Index::optimize`.
+use std::io::{self, Read, Write};
+
+use error::{Error, Result};
+use utils::persistence::Persistent;
+use utils::seeking_iterator::SeekingIterator;
+use page_manager::{Pages, RamPageCache, PageCache, PageStore, BlockManager};
+use index::posting::{Posting, DocId};
+use index::bits::{BitWriter, BitReader, low_mask, store_blocks};
+
+/// Number of zeros in the upper bits from one sample to the next
+const SAMPLE_RATE: u32 = 256;
+
+/// A static listing in Elias-Fano representation, stored on pages.
+///
+/// The pages hold the lower bits, the upper bits and the term frequencies,
+/// each starting with a new block.
+#[derive(Clone, Debug)]
+pub struct EliasFano {
+    pages: Pages,
+    len: u32,
+    lower_width: u32,
+    tf_width: u32,
+    // First block of the upper bits and of the term frequencies
+    upper_start: u32,
+    tf_start: u32,
+    // Upper bits of the last doc id
+    max_high: u32,
+    // Index of the first posting with upper bits >= k * SAMPLE_RATE for every k
+    samples: Vec<u32>,
+}
+
+impl EliasFano {
+    /// Encodes sorted postings and stores them on pages
+    pub fn new<S: PageStore>(postings: &[Posting],
+                             page_cache: &mut RamPageCache<S>)
+                             -> Result<Self> {
+        let len = postings.len() as u64;
+        let universe = postings.last().map_or(0, |last| last.doc_id().0 as u64 + 1);
+        let lower_width = if universe > len {
+            63 - (universe / len).leading_zeros()
+        } else {
+            0
+        };
+        let max_tf = postings.iter().map(|posting| posting.tf()).max().unwrap_or(0);
+        let tf_width = 32 - max_tf.leading_zeros();
+
+        let mut lower = BitWriter::new();
+        let mut upper = BitWriter::new();
+        let mut tfs = BitWriter::new();
+        let mut samples = Vec::new();
+        let mut max_high = 0;
+        for (i, posting) in postings.iter().enumerate() {
+            let doc_id = posting.doc_id().0;
+            lower.write(doc_id as u64 & low_mask(lower_width), lower_width);
+            max_high = (doc_id as u64 >> lower_width) as u32;
+            while samples.len() as u64 * SAMPLE_RATE as u64 <= max_high as u64 {
+                samples.push(i as u32);
+            }
+            upper.set(max_high as u64 + i as u64);
+            tfs.write(posting.tf() as u64, tf_width);
+        }
+
+        let lower_blocks = lower.blocks();
+        let upper_blocks = upper.blocks();
+        let tf_blocks = tfs.blocks();
+        let blocks = lower_blocks.iter().chain(&upper_blocks).chain(&tf_blocks);
+        let pages = store_blocks(blocks, page_cache)?;
+
+        Ok(EliasFano {
+            pages,
+            len: len as u32,
+            lower_width,
+            tf_width,
+            upper_start: lower_blocks.len() as u32,
+            tf_start: (lower_blocks.len() + upper_blocks.len()) as u32,
+            max_high,
+            samples,
+        })
+    }
+
+    pub fn decoder<'a>(&'a self, cache: &'a dyn PageCache) -> EliasFanoDecoder<'a> {
+        EliasFanoDecoder {
+            elias_fano: self,
+            lower: BitReader::new(cache, &self.pages, 0),
+            upper: BitReader::new(cache, &self.pages, self.upper_start),
+            tfs: BitReader::new(cache, &self.pages, self.tf_start),
+            index: 0,
+            position: 0,
+        }
+    }
+
+    /// Reads all pages to check that they are intact
+    pub fn verify(&self, cache: &dyn PageCache) -> Result<()> {
+        self.pages.verify(cache)
+    }
+
+    /// Returns the pages to the page cache.
+    /// The listing must not be used afterwards.
+    pub fn release<S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>) -> Result<()> {
+        for page_id in &self.pages.0 {
+            page_cache.delete_page(*page_id)?;
+        }
+        if let Some(unfull_page) = self.pages.take_unfull() {
+            page_cache.delete_unfull(unfull_page.page_id())?;
+        }
+        Ok(())
+    }
+}
+
+impl Persistent for EliasFano {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.pages.write_to(target)?;
+        self.len.write_to(target)?;
+        self.lower_width.write_to(target)?;
+        self.tf_width.write_to(target)?;
+        self.upper_start.write_to(target)?;
+        self.tf_start.write_to(target)?;
+        self.max_high.write_to(target)?;
+        self.samples.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok(EliasFano {
+            pages: Pages::read_from(source)?,
+            len: u32::read_from(source)?,
+            lower_width: u32::read_from(source)?,
+            tf_width: u32::read_from(source)?,
+            upper_start: u32::read_from(source)?,
+            tf_start: u32::read_from(source)?,
+            max_high: u32::read_from(source)?,
+            samples: Vec::read_from(source)?,
+        })
+    }
+}
+
+/// Decodes the postings of an Elias-Fano listing
+#[derive(Clone, Debug)]
+pub struct EliasFanoDecoder<'a> {
+    elias_fano: &'a EliasFano,
+    lower: BitReader<'a>,
+    upper: BitReader<'a>,
+    tfs: BitReader<'a>,
+    // Index of the next posting
+    index: u32,
+    // Upper bit to look for the next posting from
+    position: u64,
+}
+
+impl<'a> EliasFanoDecoder<'a> {
+    /// The error that ended the iteration early, if any
+    pub fn error(&self) -> Option<&Error> {
+        self.lower.error().or(self.upper.error()).or(self.tfs.error())
+    }
+
+    /// Moves behind the next `zeros` zeros of the upper bits
+    fn skip_zeros(&mut self, mut zeros: u64) {
+        while zeros > 0 {
+            let offset = self.position % 64;
+            let available = 64 - offset;
+            let word = !self.upper.word((self.position / 64) as usize) >> offset;
+            let count = word.count_ones() as u64;
+            if count < zeros {
+                zeros -= count;
+                self.index += (available - count) as u32;
+                self.position += available;
+            } else {
+                // Clear the zeros before the last one to skip
+                let mut word = word;
+                for _ in 1..zeros {
+                    word &= word - 1;
+                }
+                let bit = word.trailing_zeros() as u64;
+                self.index += (bit + 1 - zeros) as u32;
+                self.position += bit + 1;
+                zeros = 0;
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for EliasFanoDecoder<'a> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Posting> {
+        let elias_fano = self.elias_fano;
+        if self.index >= elias_fano.len {
+            return None;
+        }
+        let mut word_index = (self.position / 64) as usize;
+        let mut word = self.upper.word(word_index) & (!0u64 << (self.position % 64));
+        while word == 0 && self.upper.error().is_none() {
+            word_index += 1;
+            word = self.upper.word(word_index);
+        }
+        let one = word_index as u64 * 64 + word.trailing_zeros() as u64;
+        let high = one - self.index as u64;
+        let index = self.index as u64;
+        let low = self.lower.read(index * elias_fano.lower_width as u64, elias_fano.lower_width);
+        let tf = self.tfs.read(index * elias_fano.tf_width as u64, elias_fano.tf_width);
+        if self.error().is_some() {
+            self.index = elias_fano.len;
+            return None;
+        }
+        self.index += 1;
+        self.position = one + 1;
+        Some(Posting(DocId(((high << elias_fano.lower_width) | low) as u32), tf as u32))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = (self.elias_fano.len - self.index) as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'a> SeekingIterator for EliasFanoDecoder<'a> {
+    type Item = Posting;
+
+    fn next_seek(&mut self, other: &Posting) -> Option<Posting> {
+        let elias_fano = self.elias_fano;
+        let high = (other.doc_id().0 as u64 >> elias_fano.lower_width) as u32;
+        if high > elias_fano.max_high {
+            self.index = elias_fano.len;
+            return None;
+        }
+        // Postings with these upper bits start behind the high-th zero
+        if high as u64 > self.position - self.index as u64 {
+            let sample = high / SAMPLE_RATE;
+            let index = elias_fano.samples[sample as usize];
+            let position = index as u64 + sample as u64 * SAMPLE_RATE as u64;
+            if position > self.position {
+                self.index = index;
+                self.position = position;
+            }
+            let zeros = high as u64 - (self.position - self.index as u64);
+            self.skip_zeros(zeros);
+        }
+        loop {
+            let posting = self.next()?;
+            if posting.doc_id() >= other.doc_id() {
+                return Some(posting);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use test_utils::create_test_dir;
+
+    use super::EliasFano;
+    use utils::persistence::Persistent;
+    use utils::seeking_iterator::SeekingIterator;
+    use index::posting::{Posting, DocId};
+    use page_manager::{FsPageManager, RamPageCache};
+
+    fn new_cache(name: &str) -> RamPageCache {
+        let path = &create_test_dir(format!("elias_fano/{}", name).as_str());
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        RamPageCache::new(pmgr)
+    }
+
+    fn postings() -> Vec<Posting> {
+        (0..20_000u32)
+            .scan(0, |doc, i| {
+                *doc += [1, 3, 17, 1000, 2][i as usize % 5];
+                Some(Posting(DocId(*doc), i % 7 + 1))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decode() {
+        let mut cache = new_cache("decode");
+        let postings = postings();
+        let elias_fano = EliasFano::new(&postings, &mut cache).unwrap();
+        let decoder = elias_fano.decoder(&cache);
+        assert_eq!(decoder.size_hint(), (postings.len(), Some(postings.len())));
+        let decoded = decoder.collect::<Vec<_>>();
+        assert_eq!(decoded, postings);
+        assert!(decoded.iter().zip(&postings).all(|(a, b)| a.tf() == b.tf()));
+    }
+
+    #[test]
+    fn seek() {
+        let mut cache = new_cache("seek");
+        let postings = postings();
+        let elias_fano = EliasFano::new(&postings, &mut cache).unwrap();
+        let mut decoder = elias_fano.decoder(&cache);
+        for target in (0..postings.last().unwrap().doc_id().0 + 10).step_by(1277) {
+            let expected = postings.iter().find(|p| p.doc_id().0 >= target).cloned();
+            assert_eq!(decoder.next_seek(&Posting(DocId(target), 0)), expected);
+        }
+        let mut decoder = elias_fano.decoder(&cache);
+        assert_eq!(decoder.next_seek(&postings[1000]), Some(postings[1000]));
+        assert_eq!(decoder.next(), Some(postings[1001]));
+        // Seeking backwards continues with the next posting
+        assert_eq!(decoder.next_seek(&postings[0]), Some(postings[1002]));
+        assert_eq!(decoder.next_seek(&Posting(DocId::none(), 0)), None);
+        assert_eq!(decoder.next(), None);
+    }
+
+    #[test]
+    fn small() {
+        let mut cache = new_cache("small");
+        for postings in &[vec![],
+                          vec![Posting(DocId(0), 1)],
+                          vec![Posting(DocId(5), 3)],
+                          (0..100).map(|i| Posting(DocId(i), 1)).collect(),
+                          vec![Posting(DocId(0), 1), Posting(DocId(u32::MAX - 1), 1)]] {
+            let elias_fano = EliasFano::new(postings, &mut cache).unwrap();
+            assert_eq!(&elias_fano.decoder(&cache).collect::<Vec<_>>(), postings);
+            let mut decoder = elias_fano.decoder(&cache);
+            assert_eq!(decoder.next_seek(&Posting(DocId(1), 0)),
+                       postings.iter().find(|p| p.doc_id().0 >= 1).cloned());
+        }
+    }
+
+    #[test]
+    fn persistent() {
+        let mut cache = new_cache("persistent");
+        let postings = postings();
+        let elias_fano = EliasFano::new(&postings, &mut cache).unwrap();
+        let mut bytes = Vec::new();
+        elias_fano.write_to(&mut bytes).unwrap();
+        let mut restored = EliasFano::read_from(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(restored.decoder(&cache).collect::<Vec<_>>(), postings);
+        restored.release(&mut cache).unwrap();
+    }
+}
@@ -1,23 +1,72 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use error::Result;
 use utils::ring_buffer::BiasedRingBuffer;
+use utils::persistence::Persistent;
+
+use compressor::BestCompressor;
+
+use page_manager::{Pages, PageId, Block, BlockIter, BlockId, RamPageCache, PageCache, PageStore,
+                   BlockManager};
+
+use index::posting::{Posting, PostingDecoder, PostingIterator};
+use index::elias_fano::EliasFano;
+use index::bitmap::Bitmap;
 
-use compressor::{Compressor, NaiveCompressor};
+/// Listings with fewer postings are not worth a static representation
+const MIN_FROZEN_LEN: usize = 1024;
 
-use page_manager::{Pages, PageId, Block, BlockIter, BlockId, RamPageCache, PageCache, BlockManager};
+/// Number of postings summarized by a bias and maximum term frequency in a
+/// frozen listing
+const FROZEN_BLOCK_LEN: usize = 64;
 
-use index::posting::{Posting, DocId, PostingDecoder};
+/// Static representations of a listing, see `Listing::optimize`
+#[derive(Clone, Debug)]
+enum Frozen {
+    EliasFano(EliasFano),
+    Bitmap(Bitmap),
+}
+
+impl Persistent for Frozen {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        match *self {
+            Frozen::EliasFano(ref elias_fano) => {
+                0u8.write_to(target)?;
+                elias_fano.write_to(target)
+            }
+            Frozen::Bitmap(ref bitmap) => {
+                1u8.write_to(target)?;
+                bitmap.write_to(target)
+            }
+        }
+    }
 
-pub type UsedCompressor = NaiveCompressor;
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        match u8::read_from(source)? {
+            0 => Ok(Frozen::EliasFano(EliasFano::read_from(source)?)),
+            1 => Ok(Frozen::Bitmap(Bitmap::read_from(source)?)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "unknown listing representation")),
+        }
+    }
+}
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Listing {
     pages: Pages,
     current_page: Option<PageId>,
     block_biases: Vec<Posting>,
+    // Highest term frequency in each block. Parallel to block_biases
+    block_max_tf: Vec<u32>,
     block_counter: BlockId,
     block_start: Posting,
     block_end: Posting,
     posting_buffer: BiasedRingBuffer<Posting>,
-    size: u32
+    // Term frequencies of the postings in posting_buffer
+    tf_buffer: VecDeque<u32>,
+    size: u32,
+    // Set if the listing was optimized. Then it has no blocks
+    frozen: Option<Frozen>,
 }
 
 impl Listing {
@@ -26,11 +75,14 @@ impl Listing {
             pages: Pages::new(),
             current_page: None,
             block_biases: Vec::new(),
+            block_max_tf: Vec::new(),
             block_counter: BlockId::first(),
             posting_buffer: BiasedRingBuffer::new(),
-            block_start: Posting(DocId(0)),
-            block_end: Posting(DocId(0)),
+            tf_buffer: VecDeque::new(),
+            block_start: Posting::default(),
+            block_end: Posting::default(),
             size: 0,
+            frozen: None,
         }
     }
 
@@ -38,55 +90,207 @@ impl Listing {
         self.size as usize
     }
 
-    pub fn add(&mut self, postings: &[Posting], page_cache: &mut RamPageCache) {
+    /// Returns true if every posting is stored in a page
+    pub fn is_committed(&self) -> bool {
+        self.posting_buffer.is_empty() && self.current_page.is_none()
+    }
+
+    /// The posting that was added last to this listing
+    pub fn last(&self) -> Option<Posting> {
+        if self.size > 0 {
+            Some(self.block_end)
+        } else {
+            None
+        }
+    }
+
+    /// The first posting of every block, or a lower bound of it
+    pub fn block_biases(&self) -> &[Posting] {
+        &self.block_biases
+    }
+
+    /// The highest term frequency of every block
+    pub fn block_max_tf(&self) -> &[u32] {
+        &self.block_max_tf
+    }
+
+    /// The highest term frequency of all committed postings
+    pub fn max_tf(&self) -> u32 {
+        self.block_max_tf.iter().cloned().max().unwrap_or(0)
+    }
+
+    pub fn add<S: PageStore>(&mut self,
+                             postings: &[Posting],
+                             page_cache: &mut RamPageCache<S>)
+                             -> Result<()> {
+        if self.frozen.is_some() {
+            self.thaw(page_cache)?;
+        }
         // Check if we previously commited an unfull page
         // in that case it has to be unraveld
         if self.pages.unfull().is_some() {
-            self.unravel_unfull(page_cache)
+            self.unravel_unfull(page_cache)?;
         }
         for (i, posting) in postings.iter().enumerate() {
             // Don't allow duplicate postings for documents
             // If this test would not be here, term x could have multiple entries for one
             // doc id
             // Like X: DocId(0) DocId(0) DocId(1)
-            if !self.posting_buffer.is_empty() && self.block_end == *posting {
+            if !self.posting_buffer.is_empty() && self.block_end.doc_id() == posting.doc_id() {
                 continue;
             }
             self.size += 1;
             // set the new block end
             self.block_end = *posting;
             self.posting_buffer.push_back(*posting);
+            self.tf_buffer.push_back(posting.tf());
             if i % 16 == 0 {
                 // Check if we can compress and ship a block every 16 items
-                self.compress_and_ship(page_cache, false);
+                self.compress_and_ship(page_cache, false)?;
             }
         }
-        self.compress_and_ship(page_cache, false);
+        self.compress_and_ship(page_cache, false)
     }
 
-    pub fn commit(&mut self, page_cache: &mut RamPageCache) {
-        self.compress_and_ship(page_cache, true);
+    pub fn commit<S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>) -> Result<()> {
+        self.compress_and_ship(page_cache, true)?;
         // Currentpage has to be unfull. Or None
-        if let Some(unfull_page) = self.current_page.take() {
-            self.pages.add_unfull(page_cache.flush_unfull(unfull_page, self.block_counter));
+        if let Some(unfull_page) = self.current_page {
+            self.pages.add_unfull(page_cache.flush_unfull(unfull_page, self.block_counter)?);
+            self.current_page = None;
             self.block_counter = BlockId::first();
         }
+        Ok(())
+    }
+
+    /// Decodes all postings of this listing, lets `f` modify them and
+    /// encodes the result again.
+    /// The pages of the old postings are released.
+    pub fn rewrite<F, S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>, f: F) -> Result<()>
+        where F: FnOnce(&mut Vec<Posting>)
+    {
+        self.commit(page_cache)?;
+        let mut postings = self.read_postings(page_cache)?;
+        f(&mut postings);
+        self.release(page_cache)?;
+        *self = Listing::new();
+        self.add(&postings, page_cache)?;
+        self.commit(page_cache)
+    }
+
+    /// Stores a long listing in a static representation, which seeks
+    /// without decoding whole blocks: A roaring bitmap if the listing is
+    /// dense, Elias-Fano otherwise. Adding to it afterwards turns it back
+    /// into blocks.
+    ///
+    /// Biases and maximum term frequencies are kept for every 64 postings.
+    pub fn optimize<S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>) -> Result<()> {
+        if self.frozen.is_some() || self.len() < MIN_FROZEN_LEN {
+            return Ok(());
+        }
+        self.commit(page_cache)?;
+        let postings = self.read_postings(page_cache)?;
+        self.release(page_cache)?;
+        let (block_start, block_end) = (self.block_start, self.block_end);
+        *self = Listing::new();
+        for (i, chunk) in postings.chunks(FROZEN_BLOCK_LEN).enumerate() {
+            if i > 0 {
+                self.block_biases.push(chunk[0]);
+            } else {
+                self.block_biases.push(Posting::default());
+            }
+            self.block_max_tf.push(chunk.iter().map(|posting| posting.tf()).max().unwrap());
+        }
+        self.block_start = block_start;
+        self.block_end = block_end;
+        self.size = postings.len() as u32;
+        self.posting_buffer.set_base(block_start);
+        self.frozen = Some(if Bitmap::is_dense(&postings) {
+            Frozen::Bitmap(Bitmap::new(&postings, page_cache)?)
+        } else {
+            Frozen::EliasFano(EliasFano::new(&postings, page_cache)?)
+        });
+        Ok(())
+    }
+
+    /// Turns a frozen listing back into blocks
+    fn thaw<S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>) -> Result<()> {
+        let postings = self.read_postings(page_cache)?;
+        self.release(page_cache)?;
+        *self = Listing::new();
+        self.add(&postings, page_cache)?;
+        self.commit(page_cache)
+    }
+
+    /// Returns the pages of this listing to the page cache.
+    /// The listing must not be used afterwards.
+    fn release<S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>) -> Result<()> {
+        match self.frozen {
+            Some(Frozen::EliasFano(ref mut elias_fano)) => elias_fano.release(page_cache)?,
+            Some(Frozen::Bitmap(ref mut bitmap)) => bitmap.release(page_cache)?,
+            None => {}
+        }
+        for page_id in &self.pages.0 {
+            page_cache.delete_page(*page_id)?;
+        }
+        if let Some(unfull_page) = self.pages.take_unfull() {
+            page_cache.delete_unfull(unfull_page.page_id())?;
+        }
+        Ok(())
+    }
+
+    /// Iterates over the postings in whichever representation they are stored
+    pub fn postings<'a>(&'a self, cache: &'a dyn PageCache) -> PostingIterator<'a> {
+        match self.frozen {
+            Some(Frozen::EliasFano(ref elias_fano)) => {
+                PostingIterator::EliasFano(elias_fano.decoder(cache))
+            }
+            Some(Frozen::Bitmap(ref bitmap)) => PostingIterator::Bitmap(bitmap.decoder(cache)),
+            None => PostingIterator::Decoder(self.posting_decoder(cache)),
+        }
+    }
+
+    /// Decodes all postings. Fails if a page cannot be read
+    fn read_postings(&self, cache: &dyn PageCache) -> Result<Vec<Posting>> {
+        let mut decoder = self.postings(cache);
+        let postings = decoder.by_ref().collect();
+        decoder.error().map_or(Ok(postings), |err| Err(err.clone()))
+    }
+
+    /// Reads all pages of this listing to check that they are intact
+    pub fn verify(&self, cache: &dyn PageCache) -> Result<()> {
+        match self.frozen {
+            Some(Frozen::EliasFano(ref elias_fano)) => elias_fano.verify(cache)?,
+            Some(Frozen::Bitmap(ref bitmap)) => bitmap.verify(cache)?,
+            None => {}
+        }
+        self.pages.verify(cache)
     }
 
-    /// Construct a posting decoder for this listing
-    pub fn posting_decoder<'a>(&'a self, cache: &'a RamPageCache) -> PostingDecoder<'a> {
+    /// Construct a posting decoder for the blocks of this listing.
+    /// Optimized listings have no blocks, see `postings`
+    pub fn posting_decoder<'a>(&'a self, cache: &'a dyn PageCache) -> PostingDecoder<'a> {
         let block_iter = BlockIter::new(cache, self.pages.clone());
         PostingDecoder::new(block_iter, &self.block_biases, self.size)
     }
 
-    fn compress_and_ship(&mut self, page_cache: &mut RamPageCache, force: bool) {
-        while let Some(block) = UsedCompressor::compress(&mut self.posting_buffer) {
-            self.ship(page_cache, block);
+    fn compress_and_ship<S: PageStore>(&mut self,
+                                       page_cache: &mut RamPageCache<S>,
+                                       force: bool)
+                                       -> Result<()> {
+        loop {
+            let count = self.posting_buffer.count();
+            match BestCompressor::compress(&mut self.posting_buffer) {
+                Some(block) => self.ship(page_cache, block, count)?,
+                None => break,
+            }
         }
-        if force && self.posting_buffer.count() > 0 {
-            let block = UsedCompressor::force_compress(&mut self.posting_buffer);
-            self.ship(page_cache, block);
+        let count = self.posting_buffer.count();
+        if force && count > 0 {
+            let block = BestCompressor::force_compress(&mut self.posting_buffer);
+            self.ship(page_cache, block, count)?;
         }
+        Ok(())
     }
 
     /// This method is used when a previously commited listing is added to.
@@ -96,30 +300,42 @@ impl Listing {
     /// way
     ///
     /// Use with care...
-    fn unravel_unfull(&mut self, page_cache: &mut RamPageCache) {
+    fn unravel_unfull<S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>) -> Result<()> {
         // This only makes sense if there is a commited unfull page
         // Otherwise we ran into a very unpleasant bug! Scream around loudly!
         assert!(self.current_page.is_none());
-        if let Some(unfull_page) = self.pages.take_unfull() {
+        if let Some(unfull_page) = self.pages.unfull() {
+            // Get the block count of the unfull page
+            let block_count = (unfull_page.to().0 - unfull_page.from().0) as usize;
+            let first_block = self.block_biases.len() - block_count;
             // Build the postings
             let postings = {
-                // Get the block count of the unfull page
-                let block_count = unfull_page.to().0 - unfull_page.from().0;
                 // build the block iter
                 let block_iter = BlockIter::new(page_cache, Pages(vec![], Some(unfull_page)));
-                // Set postings_buffer old base
-                self.posting_buffer.set_base(self.block_biases[self.block_biases.len() - block_count as usize]);
                 // Decode the postings through a decoder
-                PostingDecoder::new(block_iter,
-                                    &self.block_biases[self.block_biases.len() -
-                                     block_count as usize..], self.size)
-                        .collect::<Vec<_>>()
+                let mut decoder =
+                    PostingDecoder::new(block_iter, &self.block_biases[first_block..], self.size);
+                let postings = decoder.by_ref().collect::<Vec<_>>();
+                // Nothing changed yet. The listing stays as it is
+                if let Some(err) = decoder.error() {
+                    return Err(err.clone());
+                }
+                postings
             };
+            self.pages.take_unfull();
+            // Rewind to the state before the blocks of the unfull page were shipped:
+            // Their biases are dropped and their postings are not counted twice
+            self.block_start = self.block_biases[first_block];
+            self.block_biases.truncate(first_block);
+            self.block_max_tf.truncate(first_block);
+            self.size -= postings.len() as u32;
+            self.posting_buffer.set_base(self.block_start);
             self.block_counter = BlockId::first();
-            self.add(&postings, page_cache);
+            self.add(&postings, page_cache)?;
             // Previous unfull page can now be deleted!
-            page_cache.delete_unfull(unfull_page.page_id());
+            page_cache.delete_unfull(unfull_page.page_id())?;
         }
+        Ok(())
     }
 
     /// This method does three things:
@@ -128,23 +344,38 @@ impl Listing {
     /// 2. It checks if that page is full and then tells the ramcache to flush
     /// it
     /// 3. It defines the bounds of the block (e.g. With what docid does a
-    /// block start) and stores these
-    fn ship(&mut self, page_cache: &mut RamPageCache, block: Block) {
+    /// block start) and its highest term frequency and stores these
+    ///
+    /// `count` is the number of buffered postings before the block was
+    /// compressed
+    fn ship<S: PageStore>(&mut self,
+                          page_cache: &mut RamPageCache<S>,
+                          block: Block,
+                          count: usize)
+                          -> Result<()> {
         // If the block is on a new page
-        if self.block_counter == BlockId::first() {
+        let page_id = if self.block_counter == BlockId::first() {
             // Push it on a new page and store the page
-            self.current_page = Some(page_cache.store_block(block));
+            page_cache.store_block(block)
         } else {
             // Otherwise store it on an existing page
-            page_cache.store_in_place(self.current_page.unwrap(), self.block_counter, block)
-        }
-        // Save with what doc_id the block just stored block starts
-        self.block_biases.push(self.block_start);
+            let page_id = self.current_page.unwrap();
+            page_cache.store_in_place(page_id, self.block_counter, block)?;
+            page_id
+        };
         // We just wrote the last block of a page. Flush it!
+        // Nothing of the listing changes before, so it stays intact if that fails
         if self.block_counter == BlockId::last() {
             // Store page, turn current_page to none
-            self.pages.push(page_cache.flush_page(self.current_page.take().unwrap()));
+            self.pages.push(page_cache.flush_page(page_id)?);
+            self.current_page = None;
+        } else {
+            self.current_page = Some(page_id);
         }
+        // Save with what doc_id the block just stored block starts
+        self.block_biases.push(self.block_start);
+        let compressed = count - self.posting_buffer.count();
+        self.block_max_tf.push(self.tf_buffer.drain(..compressed).max().unwrap_or(0));
         // Count up the block
         self.block_counter.inc();
 
@@ -157,24 +388,53 @@ impl Listing {
         }
 
         self.posting_buffer.set_base(self.block_start);
+        Ok(())
     }
 }
 
+/// Only committed listings can be persisted.
+/// Their state is fully described by their pages, biases and bounds.
+impl Persistent for Listing {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        debug_assert!(self.current_page.is_none() && self.posting_buffer.is_empty());
+        self.pages.write_to(target)?;
+        self.block_biases.write_to(target)?;
+        self.block_max_tf.write_to(target)?;
+        self.block_start.write_to(target)?;
+        self.block_end.write_to(target)?;
+        self.size.write_to(target)?;
+        self.frozen.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        let mut listing = Listing::new();
+        listing.pages = Pages::read_from(source)?;
+        listing.block_biases = Vec::read_from(source)?;
+        listing.block_max_tf = Vec::read_from(source)?;
+        listing.block_start = Posting::read_from(source)?;
+        listing.block_end = Posting::read_from(source)?;
+        listing.size = u32::read_from(source)?;
+        listing.frozen = Option::read_from(source)?;
+        listing.posting_buffer.set_base(listing.block_start);
+        Ok(listing)
+    }
+}
 
 #[cfg(test)]
 mod tests {
 
-    use super::Listing;
+    use super::{Listing, Frozen};
 
     use test_utils::create_test_dir;
 
+    use error::Error;
     use index::posting::{Posting, DocId};
     use page_manager::{FsPageManager, RamPageCache};
 
 
     fn new_cache(name: &str) -> RamPageCache {
         let path = &create_test_dir(format!("listing/{}", name).as_str());
-        let pmgr = FsPageManager::new(&path.join("pages.bin"));
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
         RamPageCache::new(pmgr)
     }
 
@@ -182,7 +442,7 @@ mod tests {
     fn basic_add() {
         let mut cache = new_cache("basic_add");
         let mut listing = Listing::new();
-        listing.add(&[Posting(DocId(0))], &mut cache);
+        listing.add(&[Posting(DocId(0), 1)], &mut cache).unwrap();
         assert_eq!(listing.pages.len(), 0);
         assert_eq!(listing.posting_buffer.count(), 1);
     }
@@ -191,10 +451,10 @@ mod tests {
     fn commit() {
         let mut cache = new_cache("commit");
         let mut listing = Listing::new();
-        listing.add(&[Posting(DocId(0))], &mut cache);
+        listing.add(&[Posting(DocId(0), 1)], &mut cache).unwrap();
         assert_eq!(listing.pages.len(), 0);
         assert_eq!(listing.posting_buffer.count(), 1);
-        listing.commit(&mut cache);
+        listing.commit(&mut cache).unwrap();
         assert_eq!(listing.pages.len(), 1);
         assert_eq!(listing.posting_buffer.count(), 0);
     }
@@ -204,15 +464,15 @@ mod tests {
     fn add() {
         let mut cache = new_cache("add");
         let mut listing = Listing::new();
-        listing.add(&[Posting(DocId(0))], &mut cache);
+        listing.add(&[Posting(DocId(0), 1)], &mut cache).unwrap();
         assert_eq!(listing.pages.len(), 0);
         assert_eq!(listing.posting_buffer.count(), 1);
         for i in 0..100 {
-            listing.add(&[Posting(DocId(i))], &mut cache);
+            listing.add(&[Posting(DocId(i), 1)], &mut cache).unwrap();
         }
         assert_eq!(listing.pages.len(), 0);
         assert!(listing.posting_buffer.count() > 0);
-        listing.commit(&mut cache);
+        listing.commit(&mut cache).unwrap();
         assert_eq!(listing.posting_buffer.count(), 0);
     }
 
@@ -220,15 +480,15 @@ mod tests {
     fn add_much() {
         let mut cache = new_cache("add_much");
         let mut listing = Listing::new();
-        listing.add(&[Posting(DocId(0))], &mut cache);
+        listing.add(&[Posting(DocId(0), 1)], &mut cache).unwrap();
         assert_eq!(listing.pages.len(), 0);
         assert_eq!(listing.posting_buffer.count(), 1);
         for i in 0..10001 {
-            listing.add(&[Posting(DocId(i))], &mut cache);
+            listing.add(&[Posting(DocId(i), 1)], &mut cache).unwrap();
         }
         assert!(listing.pages.len() > 0);
         assert!(listing.posting_buffer.count() > 0);
-        listing.commit(&mut cache);
+        listing.commit(&mut cache).unwrap();
         assert_eq!(listing.posting_buffer.count(), 0);
     }
 
@@ -237,11 +497,11 @@ mod tests {
         let mut cache = new_cache("multiple_listings");
         let mut listings = (0..100).map(|_| Listing::new()).collect::<Vec<_>>();
         for i in 0..50000 {
-            listings[i % 100].add(&[Posting(DocId(i as u32))], &mut cache);
+            listings[i % 100].add(&[Posting(DocId(i as u32), 1)], &mut cache).unwrap();
         }
         for listing in listings.iter_mut() {
             assert!(listing.posting_buffer.count() > 0);
-            listing.commit(&mut cache);
+            listing.commit(&mut cache).unwrap();
         }
         for listing in listings {
             assert_eq!(listing.posting_buffer.count(), 0);
@@ -253,17 +513,174 @@ mod tests {
         let mut cache = new_cache("biases");
         let mut listing = Listing::new();
 
-        listing.add(&[Posting(DocId(1))], &mut cache);
-        assert_eq!(listing.block_start, Posting(DocId(0)));
-        assert_eq!(listing.block_end, Posting(DocId(1)));
-        listing.commit(&mut cache);
-        assert_eq!(listing.block_start, Posting(DocId(1)));
-        assert_eq!(listing.block_end, Posting(DocId(1)));
-        listing.add(&[Posting(DocId(10))], &mut cache);
-        assert_eq!(listing.block_end, Posting(DocId(10)));
-        listing.commit(&mut cache);
-        assert_eq!(listing.block_start, Posting(DocId(10)));
-        assert_eq!(listing.block_biases,
-                   vec![Posting(DocId(0)), Posting(DocId(1))]);
+        listing.add(&[Posting(DocId(1), 1)], &mut cache).unwrap();
+        assert_eq!(listing.block_start, Posting::default());
+        assert_eq!(listing.block_end, Posting(DocId(1), 1));
+        listing.commit(&mut cache).unwrap();
+        assert_eq!(listing.block_start, Posting(DocId(1), 1));
+        assert_eq!(listing.block_end, Posting(DocId(1), 1));
+        listing.add(&[Posting(DocId(10), 1)], &mut cache).unwrap();
+        assert_eq!(listing.block_end, Posting(DocId(10), 1));
+        listing.commit(&mut cache).unwrap();
+        assert_eq!(listing.block_start, Posting(DocId(10), 1));
+        // The unfull block was unraveled and shipped again with its old bias
+        assert_eq!(listing.block_biases, vec![Posting::default()]);
+    }
+
+    #[test]
+    fn missing_biases() {
+        let mut cache = new_cache("missing_biases");
+        let mut listing = Listing::new();
+        for i in 0..1000 {
+            listing.add(&[Posting(DocId(i * 100), 1)], &mut cache).unwrap();
+        }
+        listing.commit(&mut cache).unwrap();
+        // Like a listing read from a corrupt manifest
+        listing.block_biases.truncate(1);
+        let mut decoder = listing.posting_decoder(&cache);
+        assert!(decoder.by_ref().count() < 1000);
+        assert!(matches!(decoder.error(), Some(&Error::InvalidBlock(_))));
+    }
+
+    #[test]
+    fn block_max_tf() {
+        let mut cache = new_cache("block_max_tf");
+        let mut listing = Listing::new();
+        for i in 0..100 {
+            listing.add(&[Posting(DocId(i), i % 10 + 1)], &mut cache).unwrap();
+            if i == 42 {
+                // Unraveling keeps block maxima and blocks in step
+                listing.commit(&mut cache).unwrap();
+            }
+        }
+        listing.commit(&mut cache).unwrap();
+        assert_eq!(listing.block_max_tf().len(), listing.block_biases().len());
+        assert_eq!(listing.max_tf(), 10);
+        let block_max_tf = listing.block_max_tf();
+        for posting in listing.posting_decoder(&cache) {
+            // A posting equal to a bias might be the last one of the previous block
+            let block = listing.block_biases()
+                .partition_point(|bias| bias.doc_id() <= posting.doc_id()) - 1;
+            let max_tf = block_max_tf[block].max(block_max_tf[block.saturating_sub(1)]);
+            assert!(posting.tf() <= max_tf);
+        }
+        assert!(block_max_tf.iter().all(|max_tf| *max_tf > 0));
+    }
+
+    #[test]
+    fn add_after_commit() {
+        let mut cache = new_cache("add_after_commit");
+        let mut listing = Listing::new();
+        for i in 0..20 {
+            listing.add(&[Posting(DocId(i * 3), 1)], &mut cache).unwrap();
+        }
+        listing.commit(&mut cache).unwrap();
+        for i in 20..90 {
+            listing.add(&[Posting(DocId(i * 3), 1)], &mut cache).unwrap();
+        }
+        listing.commit(&mut cache).unwrap();
+        for i in 90..95 {
+            listing.add(&[Posting(DocId(i * 3), 1)], &mut cache).unwrap();
+        }
+        listing.commit(&mut cache).unwrap();
+        assert_eq!(listing.len(), 95);
+        assert_eq!(listing.posting_decoder(&cache).collect::<Vec<_>>(),
+                   (0..95).map(|i| Posting(DocId(i * 3), 1)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn rewrite() {
+        let mut cache = new_cache("rewrite");
+        let mut listing = Listing::new();
+        for i in 0..1000 {
+            listing.add(&[Posting(DocId(i), 1)], &mut cache).unwrap();
+        }
+        listing.rewrite(&mut cache, |postings| postings.retain(|p| p.doc_id().0 % 2 == 0)).unwrap();
+        assert_eq!(listing.len(), 500);
+        assert_eq!(listing.posting_decoder(&cache).collect::<Vec<_>>(),
+                   (0..500).map(|i| Posting(DocId(i * 2), 1)).collect::<Vec<_>>());
+        listing.add(&[Posting(DocId(1000), 1)], &mut cache).unwrap();
+        listing.commit(&mut cache).unwrap();
+        assert_eq!(listing.posting_decoder(&cache).last(), Some(Posting(DocId(1000), 1)));
+    }
+
+    #[test]
+    fn optimize() {
+        let mut cache = new_cache("optimize");
+        let mut listing = Listing::new();
+        let postings = (0..5000).map(|i| Posting(DocId(i * 30), i % 4 + 1)).collect::<Vec<_>>();
+        listing.add(&postings, &mut cache).unwrap();
+        let block_max_tf = listing.block_max_tf().len();
+        listing.optimize(&mut cache).unwrap();
+        assert!(matches!(listing.frozen, Some(Frozen::EliasFano(_))));
+        assert!(listing.pages.is_empty());
+        assert_eq!(listing.len(), 5000);
+        assert_eq!(listing.last(), Some(Posting(DocId(4999 * 30), 4)));
+        assert_eq!(listing.max_tf(), 4);
+        assert_ne!(listing.block_max_tf().len(), block_max_tf);
+        assert_eq!(listing.postings(&cache).collect::<Vec<_>>(), postings);
+        // Adding turns it back into blocks
+        listing.add(&[Posting(DocId(150_000), 1)], &mut cache).unwrap();
+        listing.commit(&mut cache).unwrap();
+        assert!(listing.frozen.is_none());
+        assert_eq!(listing.len(), 5001);
+        assert_eq!(listing.posting_decoder(&cache).nth(5000), Some(Posting(DocId(150_000), 1)));
+    }
+
+    #[test]
+    fn optimize_dense() {
+        let mut cache = new_cache("optimize_dense");
+        let mut listing = Listing::new();
+        let postings = (0..5000).map(|i| Posting(DocId(i * 3), i % 4 + 1)).collect::<Vec<_>>();
+        listing.add(&postings, &mut cache).unwrap();
+        listing.optimize(&mut cache).unwrap();
+        assert!(matches!(listing.frozen, Some(Frozen::Bitmap(_))));
+        assert_eq!(listing.postings(&cache).collect::<Vec<_>>(), postings);
+        listing.add(&[Posting(DocId(15_000), 1)], &mut cache).unwrap();
+        listing.commit(&mut cache).unwrap();
+        assert!(listing.frozen.is_none());
+        assert_eq!(listing.posting_decoder(&cache).count(), 5001);
+    }
+
+    #[test]
+    fn optimize_short() {
+        let mut cache = new_cache("optimize_short");
+        let mut listing = Listing::new();
+        listing.add(&[Posting(DocId(0), 1)], &mut cache).unwrap();
+        listing.optimize(&mut cache).unwrap();
+        assert!(listing.frozen.is_none());
+    }
+
+    #[test]
+    fn persistent() {
+        use std::io::Cursor;
+        use utils::persistence::Persistent;
+
+        let mut cache = new_cache("persistent");
+        let mut listing = Listing::new();
+        for i in 0..100 {
+            listing.add(&[Posting(DocId(i), 1)], &mut cache).unwrap();
+        }
+        listing.commit(&mut cache).unwrap();
+        let mut bytes = Vec::new();
+        listing.write_to(&mut bytes).unwrap();
+        let mut restored = Listing::read_from(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(restored.len(), 100);
+        assert_eq!(restored.posting_decoder(&cache).collect::<Vec<_>>(),
+                   (0..100).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
+        restored.add(&[Posting(DocId(100), 1)], &mut cache).unwrap();
+        restored.commit(&mut cache).unwrap();
+        assert_eq!(restored.posting_decoder(&cache).collect::<Vec<_>>(),
+                   (0..101).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
+
+        for i in 101..2000 {
+            restored.add(&[Posting(DocId(i), 1)], &mut cache).unwrap();
+        }
+        restored.optimize(&mut cache).unwrap();
+        let mut bytes = Vec::new();
+        restored.write_to(&mut bytes).unwrap();
+        let restored = Listing::read_from(&mut Cursor::new(bytes)).unwrap();
+        assert_eq!(restored.postings(&cache).collect::<Vec<_>>(),
+                   (0..2000).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
     }
 }
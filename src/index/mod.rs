@@ -1,24 +1,54 @@
 use std::hash::Hash;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::sync::Arc;
 
-use page_manager::RamPageCache;
+use error::{Error, Result};
+use page_manager::{RamPageCache, PageStore, PageCache, FsPageManager, CacheStats};
 use index::listing::Listing;
+use index::position_listing::PositionListing;
+use index::doc_store::DocStore;
 use index::posting::{DocId, Posting, PostingIterator};
+use index::positions::PositionalIterator;
 use index::vocabulary::{Vocabulary, TermId, SharedVocabulary, TermIterator};
 
 pub mod vocabulary;
 pub mod posting;
+pub mod positions;
+pub mod scoring;
+pub use index::writer::IndexWriter;
+pub use index::reader::IndexReader;
 mod listing;
+mod elias_fano;
+mod bits;
+mod bitmap;
+mod position_listing;
+mod doc_store;
 mod debug_impl;
+mod persistence;
+mod top_k;
+mod writer;
+mod reader;
 
 /// Central struct of perlin
 /// Stores and manages an index with its listings and vocabulary
-pub struct Index<TTerm: Hash + Eq> {
-    page_manager: RamPageCache,
-    listings: BTreeMap<TermId, Listing>,
+///
+/// The pages of the listings are kept in a `PageStore`. By default in a
+/// file, see `Index::open`.
+pub struct Index<TTerm: Hash + Eq, TStore = FsPageManager> {
+    page_manager: RamPageCache<TStore>,
+    // Shared with the snapshots of readers. Copied on write
+    listings: BTreeMap<TermId, Arc<Listing>>,
+    // Only positional indices store positions
+    positions: Option<BTreeMap<TermId, Arc<PositionListing>>>,
     vocabulary: SharedVocabulary<TTerm>,
+    doc_store: DocStore,
+    deleted_docs: BTreeSet<DocId>,
     last_doc_id: DocId,
     doc_count: usize,
+    dir: Option<PathBuf>,
+    // Snapshot as of the last commit
+    reader: IndexReader<TTerm, TStore>,
 }
 
 /// The inverse document frequency defined by
@@ -33,91 +63,485 @@ impl InverseDocumentFrequency {
 }
 
 
-impl<TTerm> Index<TTerm>
-    where TTerm: Hash + Ord
+impl<TTerm, TStore> Index<TTerm, TStore>
+    where TTerm: Hash + Ord,
+          TStore: PageStore
 {
-    pub fn new(page_manager: RamPageCache, vocabulary: SharedVocabulary<TTerm>) -> Self {
+    pub fn new(page_manager: RamPageCache<TStore>, vocabulary: SharedVocabulary<TTerm>) -> Self {
+        Index::with_positions(page_manager, vocabulary, None)
+    }
+
+    /// Creates an index that also stores the positions of the terms in the
+    /// documents. This is needed for phrase and proximity queries.
+    ///
+    /// Positions are stored in their own blocks. So iterating over postings
+    /// is as fast as in an index without positions.
+    pub fn new_positional(page_manager: RamPageCache<TStore>,
+                          vocabulary: SharedVocabulary<TTerm>)
+                          -> Self {
+        Index::with_positions(page_manager, vocabulary, Some(BTreeMap::new()))
+    }
+
+    fn with_positions(mut page_manager: RamPageCache<TStore>,
+                      vocabulary: SharedVocabulary<TTerm>,
+                      positions: Option<BTreeMap<TermId, Arc<PositionListing>>>)
+                      -> Self {
+        let reader = reader::snapshot(&BTreeMap::new(),
+                                      &positions,
+                                      &vocabulary,
+                                      &BTreeSet::new(),
+                                      0,
+                                      &mut page_manager);
         Index {
             page_manager: page_manager,
             listings: BTreeMap::new(),
+            positions,
             vocabulary: vocabulary,
+            doc_store: DocStore::new(),
+            deleted_docs: BTreeSet::new(),
             last_doc_id: DocId::none(),
             doc_count: 0,
+            dir: None,
+            reader,
         }
+    }
 
+    /// Does this index store positions?
+    pub fn is_positional(&self) -> bool {
+        self.positions.is_some()
     }
 
-    pub fn index_term(&mut self, doc_id: DocId, term: TTerm) {
-        // Assert one critical assumption about the doc_id:
+    /// Counters of the page cache and store of this index
+    pub fn cache_stats(&self) -> CacheStats {
+        self.page_manager.stats()
+    }
+
+    /// Indexes a single term of a document.
+    ///
+    /// The position of the term is unknown here. So positional indices do
+    /// not store positions for terms indexed this way.
+    ///
+    /// Fails with `Error::InvalidDocId` if `doc_id` is smaller than the
+    /// last indexed doc id.
+    pub fn index_term(&mut self, doc_id: DocId, term: TTerm) -> Result<()> {
+        // Enforce one critical assumption about the doc_id:
         // It must not be smaller than any previous doc_ids!
-        // If it is, fail hard before something bad happens!
-        assert!(doc_id >= self.last_doc_id || self.last_doc_id == DocId::none());
+        if doc_id < self.last_doc_id && self.last_doc_id != DocId::none() {
+            return Err(Error::InvalidDocId {
+                doc_id,
+                last_doc_id: self.last_doc_id,
+            });
+        }
         self.last_doc_id = doc_id;
         // Resolve term
         let term_id = self.vocabulary.get_or_add(term);
+        self.doc_store.add_term(doc_id, term_id);
         if let Some(listing) = self.listings.get_mut(&term_id) {
-            listing.add(&[Posting(doc_id)], &mut self.page_manager);
-            return;
+            return Arc::make_mut(listing).add(&[Posting(doc_id, 1)], &mut self.page_manager);
         }
         let mut new_listing = Listing::new();
-        new_listing.add(&[Posting(doc_id)], &mut self.page_manager);
-        self.listings.insert(term_id, new_listing);
+        new_listing.add(&[Posting(doc_id, 1)], &mut self.page_manager)?;
+        self.listings.insert(term_id, Arc::new(new_listing));
+        Ok(())
     }
 
     /// Index a single document. If this should be retrievable right away, a
     /// call to commit is needed afterwards
     ///
-    /// You may overwrite the assigned doc id
+    /// You may overwrite the assigned doc id. Doc ids have to increase
+    /// strictly with every document, otherwise `Error::InvalidDocId` is
+    /// returned and nothing is indexed.
+    ///
+    /// Like `update_document`, indexing is atomic: If it fails, no listing
+    /// keeps a posting of the document.
     pub fn index_document<TIter>(&mut self,
                                  document: TIter,
                                  overwrite_doc_id: Option<DocId>)
-                                 -> DocId
+                                 -> Result<DocId>
         where TIter: Iterator<Item = TTerm>
     {
         // check if user wants to overwrite doc id.
-        // If so, assert, that the one assumption about doc_ids is enforced:
+        // If so, check that the one assumption about doc_ids is enforced:
         // They are strictly monotonically increasing.
-        // If this is not the case: fail hard before something bad happens!
         let doc_id = if let Some(doc_id) = overwrite_doc_id {
-            assert!(doc_id > self.last_doc_id || self.last_doc_id == DocId::none());
-            self.last_doc_id = doc_id;
+            if doc_id <= self.last_doc_id && self.last_doc_id != DocId::none() {
+                return Err(Error::InvalidDocId {
+                    doc_id,
+                    last_doc_id: self.last_doc_id,
+                });
+            }
             doc_id
         } else {
-            self.last_doc_id.inc();
-            self.last_doc_id
+            let mut doc_id = self.last_doc_id;
+            doc_id.inc();
+            doc_id
         };
-        self.doc_count += 1;
-        let mut buff = Vec::new();
-        for term in document {
-            let term_id = self.vocabulary.get_or_add(term);
-            buff.push(term_id);
+        let occurrences = self.resolve_terms(document);
+        // The listings of the document's terms are put back if indexing fails
+        let term_ids = occurrences.iter().map(|o| o.0).collect::<BTreeSet<_>>();
+        let saved = self.save_listings(&term_ids);
+        self.page_manager.begin();
+        if let Err(err) = self.add_postings(doc_id, &occurrences) {
+            self.restore_listings(saved);
+            self.page_manager.rollback_batch()?;
+            return Err(err);
         }
-        buff.sort();
-        buff.dedup();
-        for term_id in buff {
+        // Only count the document once all its postings are added
+        self.last_doc_id = doc_id;
+        self.doc_count += 1;
+        self.doc_store.insert(doc_id, count_terms(&occurrences));
+        self.page_manager.commit_batch()?;
+        Ok(doc_id)
+    }
+
+    /// Adds the postings and positions of a new document to the listings
+    fn add_postings(&mut self, doc_id: DocId, occurrences: &[(TermId, u32)]) -> Result<()> {
+        for occurrences in occurrences.chunk_by(|a, b| a.0 == b.0) {
+            let term_id = occurrences[0].0;
+            if let Some(ref mut positions) = self.positions {
+                let term_positions = occurrences.iter().map(|o| o.1).collect::<Vec<_>>();
+                let listing = positions.entry(term_id)
+                    .or_insert_with(|| Arc::new(PositionListing::new()));
+                Arc::make_mut(listing).add(doc_id, &term_positions, &mut self.page_manager)?;
+            }
+            let posting = Posting(doc_id, occurrences.len() as u32);
             // get or add listing
             if let Some(listing) = self.listings.get_mut(&term_id) {
-                listing.add(&[Posting(doc_id)], &mut self.page_manager);
+                Arc::make_mut(listing).add(&[posting], &mut self.page_manager)?;
                 continue;
             };
             let mut new_listing = Listing::new();
-            new_listing.add(&[Posting(doc_id)], &mut self.page_manager);
-            self.listings.insert(term_id, new_listing);
+            new_listing.add(&[posting], &mut self.page_manager)?;
+            self.listings.insert(term_id, Arc::new(new_listing));
         }
-        doc_id
+        Ok(())
+    }
+
+    /// Replaces the terms of an already indexed document.
+    ///
+    /// Postings of terms the document no longer contains are removed.
+    /// Postings for new terms are inserted at the right position of their
+    /// listings and changed term frequencies are rewritten.
+    /// Positional indices rewrite the positions of every term of the
+    /// document. The document keeps its `DocId`.
+    ///
+    /// The update is atomic: If it fails, the document and all listings
+    /// are left as they were.
+    ///
+    /// Returns false if the document is unknown or deleted.
+    pub fn update_document<TIter>(&mut self, doc_id: DocId, document: TIter) -> Result<bool>
+        where TIter: Iterator<Item = TTerm>
+    {
+        if self.deleted_docs.contains(&doc_id) {
+            return Ok(false);
+        }
+        let old_terms = match self.doc_store.get_terms(doc_id) {
+            Some(terms) => terms.to_vec(),
+            None => return Ok(false),
+        };
+        let occurrences = self.resolve_terms(document);
+        // The listings of all terms involved are put back if the update fails.
+        // Pages they use are kept by the batch until then
+        let term_ids = old_terms.iter()
+            .map(|t| t.0)
+            .chain(occurrences.iter().map(|o| o.0))
+            .collect::<BTreeSet<_>>();
+        let saved = self.save_listings(&term_ids);
+        self.page_manager.begin();
+        match self.replace_terms(doc_id, &old_terms, &occurrences) {
+            Ok(new_terms) => {
+                self.doc_store.insert(doc_id, new_terms);
+                self.page_manager.commit_batch()?;
+                Ok(true)
+            }
+            Err(err) => {
+                self.restore_listings(saved);
+                self.page_manager.rollback_batch()?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns the listings of `term_ids`, to be put back by
+    /// `restore_listings`. Pages they still construct are restored by
+    /// rolling back a batch begun afterwards
+    fn save_listings(&self, term_ids: &BTreeSet<TermId>) -> Vec<SavedListings> {
+        term_ids.iter()
+            .map(|term_id| {
+                let positions = self.positions.as_ref().and_then(|p| p.get(term_id));
+                (*term_id, self.listings.get(term_id).cloned(), positions.cloned())
+            })
+            .collect()
+    }
+
+    /// Puts back listings returned by `save_listings`
+    fn restore_listings(&mut self, saved: Vec<SavedListings>) {
+        for (term_id, listing, positions) in saved {
+            match listing {
+                Some(listing) => self.listings.insert(term_id, listing),
+                None => self.listings.remove(&term_id),
+            };
+            if let Some(ref mut all_positions) = self.positions {
+                match positions {
+                    Some(listing) => all_positions.insert(term_id, listing),
+                    None => all_positions.remove(&term_id),
+                };
+            }
+        }
+    }
+
+    /// Replaces the postings and positions of a document. Returns its new
+    /// terms with their frequencies
+    fn replace_terms(&mut self,
+                     doc_id: DocId,
+                     old_terms: &[(TermId, u32)],
+                     occurrences: &[(TermId, u32)])
+                     -> Result<Vec<(TermId, u32)>> {
+        self.update_positions(doc_id, old_terms, occurrences)?;
+        let new_terms = count_terms(occurrences);
+        // Remove stale postings
+        for &(term_id, _) in old_terms.iter().filter(|t| term_frequency(&new_terms, t.0).is_none()) {
+            let emptied = {
+                let listing = Arc::make_mut(self.listings.get_mut(&term_id).unwrap());
+                listing.rewrite(&mut self.page_manager,
+                                |postings| postings.retain(|p| p.doc_id() != doc_id))?;
+                listing.len() == 0
+            };
+            if emptied {
+                self.listings.remove(&term_id);
+            }
+        }
+        for &(term_id, tf) in &new_terms {
+            let posting = Posting(doc_id, tf);
+            match term_frequency(old_terms, term_id) {
+                // Unchanged
+                Some(old_tf) if old_tf == tf => continue,
+                // Only the term frequency changed
+                Some(_) => {
+                    let listing = Arc::make_mut(self.listings.get_mut(&term_id).unwrap());
+                    listing.rewrite(&mut self.page_manager, |postings| {
+                        if let Ok(index) = postings.binary_search_by_key(&doc_id, Posting::doc_id) {
+                            postings[index] = posting;
+                        }
+                    })?;
+                    continue;
+                }
+                // New term for this document
+                None => {}
+            }
+            if let Some(listing) = self.listings.get_mut(&term_id) {
+                let listing = Arc::make_mut(listing);
+                if listing.last().is_none_or(|last| last.doc_id() < doc_id) {
+                    // Posting belongs to the end of the listing. Just add it
+                    listing.add(&[posting], &mut self.page_manager)?;
+                } else {
+                    listing.rewrite(&mut self.page_manager, |postings| {
+                        let index = postings.binary_search_by_key(&doc_id, Posting::doc_id);
+                        if let Err(index) = index {
+                            postings.insert(index, posting);
+                        }
+                    })?;
+                }
+                continue;
+            }
+            let mut new_listing = Listing::new();
+            new_listing.add(&[posting], &mut self.page_manager)?;
+            self.listings.insert(term_id, Arc::new(new_listing));
+        }
+        Ok(new_terms)
+    }
+
+    /// Replaces the positions of a document in a positional index
+    fn update_positions(&mut self,
+                        doc_id: DocId,
+                        old_terms: &[(TermId, u32)],
+                        occurrences: &[(TermId, u32)])
+                        -> Result<()> {
+        let positions = match self.positions {
+            Some(ref mut positions) => positions,
+            None => return Ok(()),
+        };
+        // Remove stale entries
+        for &(term_id, _) in old_terms {
+            if occurrences.binary_search_by_key(&term_id, |o| o.0).is_ok() {
+                continue;
+            }
+            let emptied = match positions.get_mut(&term_id) {
+                Some(listing) => {
+                    let listing = Arc::make_mut(listing);
+                    listing.rewrite(&mut self.page_manager,
+                                    |entries| entries.retain(|e| e.0 != doc_id))?;
+                    listing.is_empty()
+                }
+                None => false,
+            };
+            if emptied {
+                positions.remove(&term_id);
+            }
+        }
+        // Replace or insert the new ones
+        for occurrences in occurrences.chunk_by(|a, b| a.0 == b.0) {
+            let term_positions = occurrences.iter().map(|o| o.1).collect::<Vec<_>>();
+            let listing = positions.entry(occurrences[0].0)
+                .or_insert_with(|| Arc::new(PositionListing::new()));
+            let listing = Arc::make_mut(listing);
+            if listing.last().is_none_or(|last| last < doc_id) {
+                listing.add(doc_id, &term_positions, &mut self.page_manager)?;
+            } else {
+                listing.rewrite(&mut self.page_manager, |entries| {
+                    match entries.binary_search_by_key(&doc_id, |e| e.0) {
+                        Ok(index) => entries[index].1 = term_positions,
+                        Err(index) => entries.insert(index, (doc_id, term_positions)),
+                    }
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves the terms of a document to TermIds.
+    /// Returns them together with their positions in the document, sorted
+    /// by TermId and position.
+    fn resolve_terms<TIter>(&mut self, document: TIter) -> Vec<(TermId, u32)>
+        where TIter: Iterator<Item = TTerm>
+    {
+        let mut buff = Vec::new();
+        for (position, term) in document.enumerate() {
+            let term_id = self.vocabulary.get_or_add(term);
+            buff.push((term_id, position as u32));
+        }
+        buff.sort();
+        buff
     }
 
     /// Commits listings to page manager and makes them retrievable
     /// If this method is not called before querying you will not be happy!
+    ///
+    /// Publishes a new snapshot for `reader`. This does not make the
+    /// listings durable. See `persist`.
     // TODO: Find a way if we can make this a compile-time error or warning
     // The Rocket framework has a similar capability for managed variables.
-    pub fn commit(&mut self) {
+    pub fn commit(&mut self) -> Result<()> {
+        self.commit_listings()?;
+        self.publish()
+    }
+
+    /// Stores the listings changed since the last commit without publishing
+    /// them
+    fn commit_listings(&mut self) -> Result<()> {
         // We iterate over the listings in reverse here because listing.commit() causes
         // a remove in the ram_page_manager.construction cache which is a Vec.
         // Vec.remove is O(n-i).
-        for listing in self.listings.iter_mut().rev() {
-            listing.1.commit(&mut self.page_manager);
+        // Committed listings are skipped, so that snapshots keep sharing them
+        for listing in self.listings.values_mut().rev().filter(|l| !l.is_committed()) {
+            Arc::make_mut(listing).commit(&mut self.page_manager)?;
+        }
+        if let Some(ref mut positions) = self.positions {
+            for listing in positions.values_mut().rev().filter(|l| !l.is_committed()) {
+                Arc::make_mut(listing).commit(&mut self.page_manager)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores long listings in a static representation: Dense ones as
+    /// roaring bitmaps, others in Elias-Fano representation.
+    ///
+    /// Meant for indices that are done growing: Seeking in these listings
+    /// does not decode whole blocks. Adding to an optimized listing turns it
+    /// back into blocks.
+    pub fn optimize(&mut self) -> Result<()> {
+        self.commit()?;
+        for listing in self.listings.values_mut() {
+            Arc::make_mut(listing).optimize(&mut self.page_manager)?;
+        }
+        self.publish()
+    }
+
+    /// Deletes a document from the index.
+    ///
+    /// The document is tombstoned: Its postings are skipped by
+    /// `query_atom` and `query_term` right away but still occupy space.
+    /// Call `purge` to remove them from the listings.
+    ///
+    /// Returns false if the document was never indexed or is already deleted.
+    pub fn delete_document(&mut self, doc_id: DocId) -> bool {
+        if !self.doc_store.contains(doc_id) {
+            return false;
+        }
+        if self.deleted_docs.insert(doc_id) {
+            self.doc_count -= 1;
+            return true;
+        }
+        false
+    }
+
+    /// Removes the postings of all deleted documents from the listings.
+    ///
+    /// Every listing containing a deleted document is decoded, filtered and
+    /// encoded again. Its old pages are released to the page manager.
+    pub fn purge(&mut self) -> Result<()> {
+        if self.deleted_docs.is_empty() {
+            return Ok(());
+        }
+        // Collect the listings the deleted documents appear in
+        let mut affected = BTreeSet::new();
+        for doc_id in &self.deleted_docs {
+            if let Some(terms) = self.doc_store.get_terms(*doc_id) {
+                affected.extend(terms.iter().map(|&(term_id, _)| term_id));
+            }
         }
+        for term_id in affected {
+            let emptied = {
+                let deleted_docs = &self.deleted_docs;
+                let listing = Arc::make_mut(self.listings.get_mut(&term_id).unwrap());
+                listing.rewrite(&mut self.page_manager, |postings| {
+                    postings.retain(|p| !deleted_docs.contains(&p.doc_id()))
+                })?;
+                listing.len() == 0
+            };
+            if emptied {
+                self.listings.remove(&term_id);
+            }
+            if let Some(ref mut positions) = self.positions {
+                let emptied = match positions.get_mut(&term_id) {
+                    Some(listing) => {
+                        let deleted_docs = &self.deleted_docs;
+                        let listing = Arc::make_mut(listing);
+                        listing.rewrite(&mut self.page_manager, |entries| {
+                            entries.retain(|e| !deleted_docs.contains(&e.0))
+                        })?;
+                        listing.is_empty()
+                    }
+                    None => false,
+                };
+                if emptied {
+                    positions.remove(&term_id);
+                }
+            }
+        }
+        for doc_id in &self.deleted_docs {
+            self.doc_store.remove(*doc_id);
+        }
+        self.deleted_docs.clear();
+        self.publish()
+    }
+
+    /// Reads every committed page of the index to check that it is intact.
+    ///
+    /// Queries stop early at pages that cannot be read and keep the error,
+    /// see `PostingIterator::error`. Call this after opening an index to
+    /// find corruption before querying.
+    pub fn verify(&self) -> Result<()> {
+        for listing in self.listings.values() {
+            listing.verify(&self.page_manager)?;
+        }
+        if let Some(ref positions) = self.positions {
+            for listing in positions.values() {
+                listing.verify(&self.page_manager)?;
+            }
+        }
+        Ok(())
     }
 
     /// Get the TermId for a certain Term
@@ -132,9 +556,7 @@ impl<TTerm> Index<TTerm>
             if let Some(listing) = self.listings.get(&term_id) {
                 // Got listing for term.
                 // Might not be the case for a shared vocabulary!
-                let decoder = listing.posting_decoder(&self.page_manager);
-                let idf = InverseDocumentFrequency::from(self.doc_count, decoder.len());
-                return (idf, PostingIterator::Decoder(decoder));
+                return self.posting_iterator(listing);
             }
         }
         // Term not found, return an empty iterator!
@@ -144,15 +566,37 @@ impl<TTerm> Index<TTerm>
     /// Get all DocumentIds and its inverse document frequency of a single TermId
     pub fn query_term(&self, term_id: &TermId) -> (InverseDocumentFrequency, PostingIterator) {
         if let Some(listing) = self.listings.get(term_id) {
-            let decoder = listing.posting_decoder(&self.page_manager);
-            let idf = InverseDocumentFrequency::from(self.doc_count, decoder.len());
-            return (idf, PostingIterator::Decoder(decoder));
+            return self.posting_iterator(listing);
         }
         // Unkown term id. Return an empty Iterator
         (InverseDocumentFrequency(0.0), PostingIterator::Empty)
     }
 
+    /// Get the postings of a single term together with its positions in the
+    /// documents.
+    ///
+    /// Returns None if this index does not store positions
+    pub fn query_positional(&self, atom: &TTerm) -> Option<PositionalIterator<'_>> {
+        let positions = self.positions.as_ref()?;
+        if let Some(term_id) = self.vocabulary.get(atom) {
+            if let (Some(listing), Some(position_listing)) = (self.listings.get(&term_id),
+                                                              positions.get(&term_id)) {
+                let (_, postings) = self.posting_iterator(listing);
+                let decoder = position_listing.position_decoder(&self.page_manager);
+                return Some(PositionalIterator::new(postings, decoder));
+            }
+        }
+        Some(PositionalIterator::empty())
+    }
+
+    fn posting_iterator<'a>(&'a self,
+                            listing: &'a Listing)
+                            -> (InverseDocumentFrequency, PostingIterator<'a>) {
+        posting_iterator(listing, &self.page_manager, self.doc_count, &self.deleted_docs)
+    }
+
     /// In how many documents does this term occur?
+    /// Deleted documents count until they are purged
     pub fn term_df(&self, term_id: &TermId) -> usize {
         if let Some(listing) = self.listings.get(term_id) {
             return listing.len();
@@ -162,8 +606,62 @@ impl<TTerm> Index<TTerm>
     }
 }
 
-impl<TTerm> Index<TTerm>
+/// A listing and position listing of a term as saved before a change
+type SavedListings = (TermId, Option<Arc<Listing>>, Option<Arc<PositionListing>>);
+
+/// The postings of `listing` without the deleted documents
+fn posting_iterator<'a>(listing: &'a Listing,
+                        cache: &'a dyn PageCache,
+                        doc_count: usize,
+                        deleted_docs: &'a BTreeSet<DocId>)
+                        -> (InverseDocumentFrequency, PostingIterator<'a>) {
+    let postings = listing.postings(cache);
+    // The listing still counts deleted documents
+    let idf = InverseDocumentFrequency::from(doc_count + deleted_docs.len(), listing.len());
+    if deleted_docs.is_empty() {
+        (idf, postings)
+    } else {
+        // Skip tombstoned documents until they are purged
+        (idf, PostingIterator::Filtered(Box::new(postings), deleted_docs))
+    }
+}
+
+/// Counts how often each term occurs in a list of occurrences sorted by TermId
+fn count_terms(occurrences: &[(TermId, u32)]) -> Vec<(TermId, u32)> {
+    occurrences.chunk_by(|a, b| a.0 == b.0)
+        .map(|occurrences| (occurrences[0].0, occurrences.len() as u32))
+        .collect()
+}
+
+impl<TTerm, TStore> Index<TTerm, TStore>
+    where TTerm: Hash + Eq,
+          TStore: PageStore
+{
+    /// Replaces the snapshot handed out by `reader` with the current state.
+    /// All listings must be committed
+    fn publish(&mut self) -> Result<()> {
+        self.reader = reader::snapshot(&self.listings,
+                                       &self.positions,
+                                       &self.vocabulary,
+                                       &self.deleted_docs,
+                                       self.doc_count,
+                                       &mut self.page_manager);
+        // Pages only the old snapshot needed can be reused now,
+        // unless a reader still holds it
+        self.page_manager.reclaim()
+    }
+}
+
+/// Looks up the term frequency of a term in a sorted list of terms
+fn term_frequency(terms: &[(TermId, u32)], term_id: TermId) -> Option<u32> {
+    terms.binary_search_by_key(&term_id, |&(term_id, _)| term_id)
+        .ok()
+        .map(|index| terms[index].1)
+}
+
+impl<TTerm, TStore> Index<TTerm, TStore>
     where TTerm: Ord + Hash,
+          TStore: PageStore,
           SharedVocabulary<TTerm>: for<'r> TermIterator<'r, TTerm>
 {
     pub fn iterate_terms(&self) -> <SharedVocabulary<TTerm> as TermIterator<TTerm>>::TIter {
@@ -175,16 +673,19 @@ impl<TTerm> Index<TTerm>
 
 #[cfg(test)]
 mod tests {
-    use test_utils::create_test_dir;
+    use std::sync::atomic::Ordering;
+
+    use test_utils::{create_test_dir, FailingStore};
 
     use super::Index;
+    use error::Error;
     use index::posting::{Posting, DocId};
-    use index::vocabulary::SharedVocabulary;
+    use index::vocabulary::{SharedVocabulary, Vocabulary};
     use page_manager::{FsPageManager, RamPageCache};
 
     fn new_index(name: &str) -> Index<usize> {
         let path = &create_test_dir(format!("index/{}", name).as_str());
-        let pmgr = FsPageManager::new(&path.join("pages.bin"));
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
         Index::<usize>::new(RamPageCache::new(pmgr), SharedVocabulary::new())
     }
 
@@ -192,67 +693,67 @@ mod tests {
     fn basic_indexing() {
         let mut index = new_index("basic_indexing");
 
-        assert_eq!(index.index_document((0..2000), None), DocId(0));
-        assert_eq!(index.index_document((2000..4000), None), DocId(1));
-        assert_eq!(index.index_document((500..600), None), DocId(2));
-        index.commit();
+        assert_eq!(index.index_document((0..2000), None).unwrap(), DocId(0));
+        assert_eq!(index.index_document((2000..4000), None).unwrap(), DocId(1));
+        assert_eq!(index.index_document((500..600), None).unwrap(), DocId(2));
+        index.commit().unwrap();
 
         assert_eq!((index.query_atom(&0).1).collect::<Vec<_>>(),
-                   vec![Posting(DocId(0))]);
+                   vec![Posting(DocId(0), 1)]);
     }
 
     #[test]
     fn term_indexing() {
         let mut index = new_index("term_indexing");
-        index.index_term(DocId(0), 100);
-        index.index_term(DocId(0), 200);
-        index.index_term(DocId(1), 100);
-        index.index_term(DocId(1), 150);
-        index.commit();
+        index.index_term(DocId(0), 100).unwrap();
+        index.index_term(DocId(0), 200).unwrap();
+        index.index_term(DocId(1), 100).unwrap();
+        index.index_term(DocId(1), 150).unwrap();
+        index.commit().unwrap();
 
         assert_eq!((index.query_atom(&100).1).collect::<Vec<_>>(),
-                   vec![Posting(DocId(0)), Posting(DocId(1))]);
+                   vec![Posting(DocId(0), 1), Posting(DocId(1), 1)]);
         assert_eq!((index.query_atom(&150).1).collect::<Vec<_>>(),
-                   vec![Posting(DocId(1))]);
+                   vec![Posting(DocId(1), 1)]);
     }
 
     #[test]
     fn extended_indexing() {
         let mut index = new_index("extended_indexing");
         for i in 0..200 {
-            assert_eq!(index.index_document((i..i + 200), None), DocId(i as u32));
+            assert_eq!(index.index_document((i..i + 200), None).unwrap(), DocId(i as u32));
         }
-        index.commit();
+        index.commit().unwrap();
 
         assert_eq!((index.query_atom(&0).1).collect::<Vec<_>>(),
-                   vec![Posting(DocId(0))]);
+                   vec![Posting(DocId(0), 1)]);
         assert_eq!((index.query_atom(&99).1).collect::<Vec<_>>(),
-                   (0..100).map(|i| Posting(DocId(i))).collect::<Vec<_>>());
+                   (0..100).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
     }
 
     #[test]
     fn mutable_index() {
         let mut index = new_index("mutable_index");
         for i in 0..200 {
-            assert_eq!(index.index_document((i..i + 200), None), DocId(i as u32));
+            assert_eq!(index.index_document((i..i + 200), None).unwrap(), DocId(i as u32));
         }
-        index.commit();
+        index.commit().unwrap();
 
         assert_eq!((index.query_atom(&0).1).collect::<Vec<_>>(),
-                   vec![Posting(DocId(0))]);
+                   vec![Posting(DocId(0), 1)]);
         assert_eq!((index.query_atom(&99).1).collect::<Vec<_>>(),
-                   (0..100).map(|i| Posting(DocId(i))).collect::<Vec<_>>());
-        assert_eq!(index.index_document(0..400, None), DocId(200));
-        index.commit();
+                   (0..100).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
+        assert_eq!(index.index_document(0..400, None).unwrap(), DocId(200));
+        index.commit().unwrap();
         assert_eq!((index.query_atom(&0).1).collect::<Vec<_>>(),
-                   vec![Posting(DocId(0)), Posting(DocId(200))]);
+                   vec![Posting(DocId(0), 1), Posting(DocId(200), 1)]);
     }
 
     #[test]
     fn shared_vocabulary() {
         let path = &create_test_dir("index/shared_vocabulary");
-        let pmgr1 = FsPageManager::new(&path.join("pages1.bin"));
-        let pmgr2 = FsPageManager::new(&path.join("pages2.bin"));
+        let pmgr1 = FsPageManager::new(&path.join("pages1.bin")).unwrap();
+        let pmgr2 = FsPageManager::new(&path.join("pages2.bin")).unwrap();
         let vocab = SharedVocabulary::new();
 
         let mut index1 = Index::<usize>::new(RamPageCache::new(pmgr1), vocab.clone());
@@ -261,39 +762,310 @@ mod tests {
         for i in 0..200 {
             if i % 2 == 0 {
                 assert_eq!(index1.index_document((i..i + 200).filter(|i| i % 2 == 0),
-                                                 Some(DocId(i as u32))),
+                                                 Some(DocId(i as u32))).unwrap(),
                            DocId(i as u32));
             } else {
                 assert_eq!(index2.index_document((i..i + 200).filter(|i| i % 2 != 0),
-                                                 Some(DocId(i as u32))),
+                                                 Some(DocId(i as u32))).unwrap(),
                            DocId(i as u32));
             }
         }
-        index1.commit();
-        index2.commit();
+        index1.commit().unwrap();
+        index2.commit().unwrap();
+        // Both indices assign the same ids
+        for term in 0..400 {
+            assert_eq!(index1.get_term_id(&term), index2.get_term_id(&term));
+        }
+        assert_eq!(vocab.get(&99), index2.get_term_id(&99));
 
         assert_eq!((index1.query_atom(&99).1).collect::<Vec<_>>(), vec![]);
         assert_eq!((index2.query_atom(&99).1).collect::<Vec<_>>(),
-                   (0..100).filter(|i| i % 2 != 0).map(|i| Posting(DocId(i))).collect::<Vec<_>>());
+                   (0..100).filter(|i| i % 2 != 0).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
 
         assert_eq!((index1.query_atom(&200).1).collect::<Vec<_>>(),
-                   (1..200).filter(|i| i % 2 == 0).map(|i| Posting(DocId(i))).collect::<Vec<_>>());
+                   (1..200).filter(|i| i % 2 == 0).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
         assert_eq!((index2.query_atom(&200).1).collect::<Vec<_>>(), vec![]);
     }
 
     #[test]
-    #[should_panic]
     fn wrong_overwritten_doc_id() {
         let mut index = new_index("wrong_overwritten_doc_id");
-        index.index_document(0..10, Some(DocId(10)));
-        index.index_document(0..10, Some(DocId(5)));
+        index.index_document(0..10, Some(DocId(10))).unwrap();
+        match index.index_document(0..10, Some(DocId(5))) {
+            Err(Error::InvalidDocId { doc_id, last_doc_id }) => {
+                assert_eq!((doc_id, last_doc_id), (DocId(5), DocId(10)));
+            }
+            other => panic!("Expected InvalidDocId, got {:?}", other),
+        }
+        assert!(index.index_term(DocId(9), 0).is_err());
+        // Nothing was indexed by the failed calls
+        index.commit().unwrap();
+        assert_eq!((index.query_atom(&0).1).collect::<Vec<_>>(),
+                   vec![Posting(DocId(10), 1)]);
+    }
+
+    #[test]
+    fn delete_document() {
+        let mut index = new_index("delete_document");
+        for i in 0..200 {
+            assert_eq!(index.index_document(i..i + 200, None).unwrap(), DocId(i as u32));
+        }
+        index.commit().unwrap();
+        assert!(index.delete_document(DocId(0)));
+        assert!(index.delete_document(DocId(50)));
+        assert!(!index.delete_document(DocId(50)));
+        assert!(!index.delete_document(DocId(200)));
+        assert_eq!(index.doc_count, 198);
+        assert_eq!((index.query_atom(&0).1).collect::<Vec<_>>(), vec![]);
+        assert_eq!((index.query_atom(&99).1).collect::<Vec<_>>(),
+                   (1..100).filter(|i| *i != 50).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn purge() {
+        let mut index = new_index("purge");
+        for i in 0..200 {
+            assert_eq!(index.index_document(i..i + 200, None).unwrap(), DocId(i as u32));
+        }
+        index.delete_document(DocId(0));
+        index.delete_document(DocId(50));
+        index.delete_document(DocId(199));
+        index.purge().unwrap();
+        assert_eq!(index.term_df(&index.get_term_id(&0).unwrap()), 0);
+        assert_eq!(index.term_df(&index.get_term_id(&99).unwrap()), 98);
+        assert_eq!((index.query_atom(&0).1).collect::<Vec<_>>(), vec![]);
+        assert_eq!((index.query_atom(&99).1).collect::<Vec<_>>(),
+                   (1..100).filter(|i| *i != 50).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
+        assert_eq!((index.query_atom(&250).1).collect::<Vec<_>>(),
+                   (51..199).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
+        // Indexing goes on as usual
+        assert_eq!(index.index_document(0..10, None).unwrap(), DocId(200));
+        index.commit().unwrap();
+        assert_eq!((index.query_atom(&0).1).collect::<Vec<_>>(),
+                   vec![Posting(DocId(200), 1)]);
+    }
+
+    #[test]
+    fn update_document() {
+        let mut index = new_index("update_document");
+        for i in 0..200 {
+            assert_eq!(index.index_document(i..i + 200, None).unwrap(), DocId(i as u32));
+        }
+        index.commit().unwrap();
+        // Doc 0 contains 0..200. Afterwards it contains 100..300
+        assert!(index.update_document(DocId(0), 100..300).unwrap());
+        assert!(!index.update_document(DocId(200), 0..10).unwrap());
+        index.commit().unwrap();
+        assert_eq!((index.query_atom(&0).1).collect::<Vec<_>>(), vec![]);
+        assert_eq!(index.term_df(&index.get_term_id(&99).unwrap()), 99);
+        assert_eq!((index.query_atom(&250).1).collect::<Vec<_>>(),
+                   Some(0).into_iter().chain(51..200).map(|i| Posting(DocId(i), 1))
+                       .collect::<Vec<_>>());
+        assert_eq!((index.query_atom(&150).1).collect::<Vec<_>>(),
+                   (0..151).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
+        // Unknown terms create new listings
+        assert!(index.update_document(DocId(10), 1000..1001).unwrap());
+        index.commit().unwrap();
+        assert_eq!((index.query_atom(&1000).1).collect::<Vec<_>>(),
+                   vec![Posting(DocId(10), 1)]);
+        assert_eq!((index.query_atom(&10).1).collect::<Vec<_>>(),
+                   (1..10).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
+        assert_eq!(index.doc_count, 200);
+    }
+
+    #[test]
+    fn index_document_fails() {
+        let (store, writes_left) = FailingStore::new();
+        let mut index = Index::<usize, FailingStore>::new_positional(RamPageCache::new(store),
+                                                                     SharedVocabulary::new());
+        for _ in 0..3000 {
+            index.index_document(0..50, None).unwrap();
+        }
+        index.commit().unwrap();
+        writes_left.store(0, Ordering::SeqCst);
+        let mut indexed = 3000;
+        while index.index_document(0..50, None).is_ok() {
+            indexed += 1;
+        }
+        writes_left.store(usize::MAX, Ordering::SeqCst);
+        // The failed document is not counted and left no postings behind
+        assert_eq!(index.doc_count, indexed);
+        assert!(index.doc_store.get_terms(DocId(indexed as u32)).is_none());
+        index.commit().unwrap();
+        index.verify().unwrap();
+        let expected = (0..indexed as u32).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>();
+        for term in 0..50 {
+            let term_id = index.get_term_id(&term).unwrap();
+            assert_eq!((index.query_term(&term_id).1).collect::<Vec<_>>(), expected);
+            assert_eq!(index.term_df(&term_id), indexed);
+            let positions = index.query_positional(&term).unwrap().positions(DocId(0));
+            assert_eq!(positions, Some(vec![term as u32]));
+        }
+        // The doc id is handed out again
+        assert_eq!(index.index_document(0..50, None).unwrap(), DocId(indexed as u32));
+    }
+
+    #[test]
+    fn update_document_fails() {
+        let (store, writes_left) = FailingStore::new();
+        let mut index = Index::new_positional(RamPageCache::new(store), SharedVocabulary::new());
+        for i in 0..3000 {
+            index.index_document((0..3).chain(i % 10 + 3..i % 10 + 6), None).unwrap();
+        }
+        index.commit().unwrap();
+        let postings = |index: &Index<usize, FailingStore>| {
+            (0..25).map(|t| (index.query_atom(&t).1).collect::<Vec<_>>()).collect::<Vec<_>>()
+        };
+        let before = postings(&index);
+        let positions = index.query_positional(&5).unwrap().positions(DocId(2));
+        // Fail at every write the update does, until it succeeds
+        let mut writes = 0;
+        loop {
+            writes_left.store(writes, Ordering::SeqCst);
+            if index.update_document(DocId(2), (20..25).chain(0..2)).is_ok() {
+                break;
+            }
+            writes_left.store(usize::MAX, Ordering::SeqCst);
+            index.verify().unwrap();
+            assert_eq!(postings(&index), before);
+            assert_eq!(index.query_positional(&5).unwrap().positions(DocId(2)), positions);
+            assert_eq!(index.doc_store.get_terms(DocId(2)).unwrap().len(), 6);
+            writes += 1;
+        }
+        assert!(writes > 0);
+        writes_left.store(usize::MAX, Ordering::SeqCst);
+        index.commit().unwrap();
+        index.verify().unwrap();
+        assert_eq!((index.query_atom(&2).1).count(), 2999);
+        assert_eq!((index.query_atom(&20).1).collect::<Vec<_>>(), vec![Posting(DocId(2), 1)]);
+        assert_eq!(index.query_positional(&0).unwrap().positions(DocId(2)), Some(vec![5]));
+    }
+
+    #[test]
+    fn term_frequencies() {
+        let mut index = new_index("term_frequencies");
+        index.index_document(vec![1, 2, 1, 3, 1].into_iter(), None).unwrap();
+        index.index_document(vec![2, 2].into_iter(), None).unwrap();
+        index.commit().unwrap();
+        assert_eq!((index.query_atom(&1).1).map(|p| p.tf()).collect::<Vec<_>>(), vec![3]);
+        assert_eq!((index.query_atom(&2).1).map(|p| p.tf()).collect::<Vec<_>>(), vec![1, 2]);
+        // Changing only the term frequency rewrites the posting
+        assert!(index.update_document(DocId(0), vec![1, 2, 2, 3].into_iter()).unwrap());
+        index.commit().unwrap();
+        assert_eq!((index.query_atom(&1).1).map(|p| p.tf()).collect::<Vec<_>>(), vec![1]);
+        assert_eq!((index.query_atom(&2).1).map(|p| p.tf()).collect::<Vec<_>>(), vec![2, 2]);
+        assert_eq!((index.query_atom(&3).1).collect::<Vec<_>>(), vec![Posting(DocId(0), 1)]);
+    }
+
+    #[test]
+    fn positions() {
+        let path = &create_test_dir("index/positions");
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        let mut index = Index::<usize>::new_positional(RamPageCache::new(pmgr), SharedVocabulary::new());
+        for i in 0..100 {
+            index.index_document(vec![i, 1000, i, 1001].into_iter(), None).unwrap();
+        }
+        index.commit().unwrap();
+        let mut iter = index.query_positional(&1000).unwrap();
+        assert_eq!(iter.positions(DocId(0)), Some(vec![1]));
+        assert_eq!(iter.positions(DocId(50)), Some(vec![1]));
+        assert_eq!(index.query_positional(&7).unwrap().positions(DocId(7)), Some(vec![0, 2]));
+        // Positions follow updates and purges
+        assert!(index.update_document(DocId(50), vec![1001, 1000].into_iter()).unwrap());
+        index.delete_document(DocId(60));
+        index.purge().unwrap();
+        index.commit().unwrap();
+        let mut iter = index.query_positional(&1000).unwrap();
+        assert_eq!(iter.positions(DocId(50)), Some(vec![1]));
+        assert_eq!(iter.positions(DocId(60)), None);
+        assert_eq!(iter.positions(DocId(61)), Some(vec![1]));
+        let mut iter = index.query_positional(&1001).unwrap();
+        assert_eq!(iter.positions(DocId(50)), Some(vec![0]));
+        assert_eq!(index.query_positional(&50).unwrap().positions(DocId(50)), None);
+    }
+
+    #[test]
+    fn update_deleted_document() {
+        let mut index = new_index("update_deleted_document");
+        index.index_document(0..10, None).unwrap();
+        index.index_document(0..10, None).unwrap();
+        assert!(index.delete_document(DocId(0)));
+        assert!(!index.update_document(DocId(0), 0..20).unwrap());
+        index.purge().unwrap();
+        assert!(!index.delete_document(DocId(0)));
+        assert!(index.update_document(DocId(1), 5..20).unwrap());
+        index.commit().unwrap();
+        assert_eq!((index.query_atom(&15).1).collect::<Vec<_>>(),
+                   vec![Posting(DocId(1), 1)]);
+        assert_eq!((index.query_atom(&0).1).collect::<Vec<_>>(), vec![]);
+    }
+
+    #[test]
+    fn optimize() {
+        use utils::seeking_iterator::SeekingIterator;
+
+        let mut index = new_index("optimize");
+        for i in 0..3000 {
+            index.index_document(0..(i % 7 + 1), None).unwrap();
+        }
+        index.optimize().unwrap();
+        let expected = (0..3000).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>();
+        assert_eq!((index.query_atom(&0).1).collect::<Vec<_>>(), expected);
+        let mut postings = index.query_atom(&4).1;
+        assert_eq!(postings.next_seek(&Posting(DocId(1001), 0)), Some(Posting(DocId(1005), 1)));
+        assert_eq!(postings.next(), Some(Posting(DocId(1006), 1)));
+        // Optimized listings are filtered and purged like any other
+        index.delete_document(DocId(0));
+        assert_eq!((index.query_atom(&0).1).count(), 2999);
+        index.purge().unwrap();
+        index.optimize().unwrap();
+        assert_eq!((index.query_atom(&0).1).collect::<Vec<_>>(), &expected[1..]);
+        // And turned back into blocks when added to
+        index.index_document(0..1, None).unwrap();
+        index.commit().unwrap();
+        assert_eq!((index.query_atom(&0).1).last(), Some(Posting(DocId(3000), 1)));
+    }
+
+    #[test]
+    fn in_memory() {
+        use page_manager::MemPageStore;
+
+        let mut index = Index::new(RamPageCache::new(MemPageStore::new()), SharedVocabulary::new());
+        for i in 0..200 {
+            assert_eq!(index.index_document(i..i + 200, None).unwrap(), DocId(i as u32));
+        }
+        index.commit().unwrap();
+        assert_eq!((index.query_atom(&99).1).collect::<Vec<_>>(),
+                   (0..100).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
+        index.delete_document(DocId(0));
+        index.purge().unwrap();
+        index.optimize().unwrap();
+        assert_eq!((index.query_atom(&0).1).count(), 0);
+        assert_eq!((index.query_atom(&199).1).count(), 199);
+    }
+
+    #[test]
+    fn cache_stats() {
+        let mut index = new_index("cache_stats");
+        index.index_document(0..100, None).unwrap();
+        index.index_document(0..100, None).unwrap();
+        index.commit().unwrap();
+        let stats = index.cache_stats();
+        assert!(stats.store.pages_written > 0);
+        assert!(stats.store.bytes_on_disk > 0);
+        index.query_atom(&0).1.count();
+        index.query_atom(&0).1.count();
+        let after = index.cache_stats();
+        assert_eq!(after.misses - stats.misses, 1);
+        assert_eq!(after.hits - stats.hits, 1);
+        assert_eq!(after.store.pages_read - stats.store.pages_read, 1);
     }
 
     #[test]
     fn iterate_terms() {
         let mut index = new_index("iterate_terms");
-        index.index_document(0..10, Some(DocId(0)));
-        let mut terms = index.iterate_terms().map(|(term, _)| term.clone()).collect::<Vec<_>>();
+        index.index_document(0..10, Some(DocId(0))).unwrap();
+        let mut terms = index.iterate_terms().map(|(term, _)| term).collect::<Vec<_>>();
         terms.sort();
         assert_eq!(terms, vec![0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
     }
@@ -301,13 +1073,13 @@ mod tests {
     #[test]
     fn query_term_id() {
         let mut index = new_index("query_term_id");
-        index.index_document(0..10, Some(DocId(0)));
-        index.index_document(1..10, Some(DocId(1)));
-        index.commit();
+        index.index_document(0..10, Some(DocId(0))).unwrap();
+        index.index_document(1..10, Some(DocId(1))).unwrap();
+        index.commit().unwrap();
         for (term, term_id) in index.iterate_terms() {
-            if *term == 0 {
-                assert_eq!((index.query_term(term_id).1).collect::<Vec<_>>(),
-                           vec![Posting(DocId(0))]);
+            if term == 0 {
+                assert_eq!((index.query_term(&term_id).1).collect::<Vec<_>>(),
+                           vec![Posting(DocId(0), 1)]);
             }
         }
     }
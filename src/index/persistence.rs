@@ -0,0 +1,398 @@
+//! Persisting an `Index` to a directory and reopening it.
+//!
+//! A persisted index consists of three files:
+//! `pages.bin` holds the pages written by the `FsPageManager`, each followed
+//! by its checksum.
+//! `index.manifest` holds everything else: the state of the page manager,
+//! the vocabulary, the doc counters and the metadata of every listing and
+//! position listing.
+//! `pages.journal` holds the original content of pages overwritten since the
+//! manifest was written. On open they are restored, so the index is exactly
+//! as it was when `persist` last completed. Everything indexed afterwards
+//! is discarded.
+use std::hash::Hash;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use error::{Error, Result};
+use utils::persistence::Persistent;
+use page_manager::{FsPageManager, RamPageCache};
+use index::Index;
+use index::listing::Listing;
+use index::position_listing::PositionListing;
+use index::doc_store::DocStore;
+use index::posting::DocId;
+use index::vocabulary::{SharedVocabulary, TermId};
+
+const PAGES: &str = "pages.bin";
+const MANIFEST: &str = "index.manifest";
+const MANIFEST_TMP: &str = "index.manifest.tmp";
+
+const MAGIC: &[u8; 4] = b"PRLN";
+const VERSION: u32 = 1;
+
+impl<TTerm> Index<TTerm>
+    where TTerm: Hash + Ord + Persistent
+{
+    /// Opens the index stored in `dir`.
+    ///
+    /// If `dir` does not contain a persisted index a new, empty one is
+    /// created there. Call `persist` to write it to disk.
+    ///
+    /// The index gets a vocabulary of its own. Indices that shared their
+    /// vocabulary have to be opened with `open_with_vocabulary` to share it
+    /// again.
+    pub fn open(dir: &Path) -> Result<Self> {
+        Self::open_with(dir, false, SharedVocabulary::new())
+    }
+
+    /// Like `open`, but a newly created index stores positions.
+    /// See `Index::new_positional`.
+    pub fn open_positional(dir: &Path) -> Result<Self> {
+        Self::open_with(dir, true, SharedVocabulary::new())
+    }
+
+    /// Like `open`, but the index uses `vocabulary`, like indices created
+    /// with `Index::new` from clones of one vocabulary.
+    ///
+    /// The terms of the persisted index are added to `vocabulary`. Fails
+    /// if the two assign a term different `TermId`s, e.g. because they were
+    /// not shared when the index was persisted.
+    pub fn open_with_vocabulary(dir: &Path, vocabulary: SharedVocabulary<TTerm>) -> Result<Self> {
+        Self::open_with(dir, false, vocabulary)
+    }
+
+    fn open_with(dir: &Path,
+                 positional: bool,
+                 vocabulary: SharedVocabulary<TTerm>)
+                 -> Result<Self> {
+        fs::create_dir_all(dir)?;
+        let manifest_path = dir.join(MANIFEST);
+        if !manifest_path.exists() {
+            let store = FsPageManager::new(&dir.join(PAGES))?;
+            let mut index = if positional {
+                Index::new_positional(RamPageCache::new(store), vocabulary)
+            } else {
+                Index::new(RamPageCache::new(store), vocabulary)
+            };
+            index.dir = Some(dir.to_path_buf());
+            return Ok(index);
+        }
+        let mut manifest = BufReader::new(File::open(manifest_path)?);
+        let mut magic = [0u8; 4];
+        manifest.read_exact(&mut magic)?;
+        if &magic != MAGIC || u32::read_from(&mut manifest)? != VERSION {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidData,
+                                                "not a perlin index manifest")));
+        }
+        let last_doc_id = DocId::read_from(&mut manifest)?;
+        let doc_count = usize::read_from(&mut manifest)?;
+        let deleted_docs = Vec::<DocId>::read_from(&mut manifest)?;
+        let store = FsPageManager::open(&dir.join(PAGES), &mut manifest)?;
+        vocabulary.merge(SharedVocabulary::read_from(&mut manifest)?)?;
+        let doc_store = DocStore::read_from(&mut manifest)?;
+        let listing_count = usize::read_from(&mut manifest)?;
+        let mut listings = BTreeMap::new();
+        for _ in 0..listing_count {
+            let term_id = TermId::read_from(&mut manifest)?;
+            listings.insert(term_id, Arc::new(Listing::read_from(&mut manifest)?));
+        }
+        let positions = if bool::read_from(&mut manifest)? {
+            let listing_count = usize::read_from(&mut manifest)?;
+            let mut positions = BTreeMap::new();
+            for _ in 0..listing_count {
+                let term_id = TermId::read_from(&mut manifest)?;
+                positions.insert(term_id, Arc::new(PositionListing::read_from(&mut manifest)?));
+            }
+            Some(positions)
+        } else {
+            None
+        };
+        let mut index = Index::new(RamPageCache::new(store), vocabulary);
+        index.listings = listings;
+        index.positions = positions;
+        index.doc_store = doc_store;
+        index.deleted_docs = deleted_docs.into_iter().collect::<BTreeSet<_>>();
+        index.last_doc_id = last_doc_id;
+        index.doc_count = doc_count;
+        index.dir = Some(dir.to_path_buf());
+        index.publish()?;
+        Ok(index)
+    }
+
+    /// Commits the index and writes its manifest to the directory it was
+    /// opened from.
+    ///
+    /// The manifest is written to a temporary file first and then renamed.
+    /// So a crash while persisting leaves the previous manifest intact, and
+    /// `open` rolls the pages back to it.
+    ///
+    /// Pages freed while other `IndexReader`s are alive are not recorded
+    /// as free. Once the index is opened again, they are not reused.
+    pub fn persist(&mut self) -> Result<()> {
+        let dir = match self.dir {
+            Some(ref dir) => dir.clone(),
+            None => {
+                return Err(Error::Io(io::Error::other("index was not opened from a directory")))
+            }
+        };
+        self.commit()?;
+        self.page_manager.store().sync()?;
+        {
+            let file = File::create(dir.join(MANIFEST_TMP))?;
+            let mut manifest = BufWriter::new(&file);
+            manifest.write_all(MAGIC)?;
+            VERSION.write_to(&mut manifest)?;
+            self.last_doc_id.write_to(&mut manifest)?;
+            self.doc_count.write_to(&mut manifest)?;
+            self.deleted_docs.iter().cloned().collect::<Vec<_>>().write_to(&mut manifest)?;
+            self.page_manager.store().write_state(&mut manifest)?;
+            self.vocabulary.write_to(&mut manifest)?;
+            self.doc_store.write_to(&mut manifest)?;
+            self.listings.len().write_to(&mut manifest)?;
+            for (term_id, listing) in &self.listings {
+                term_id.write_to(&mut manifest)?;
+                listing.write_to(&mut manifest)?;
+            }
+            self.positions.is_some().write_to(&mut manifest)?;
+            if let Some(ref positions) = self.positions {
+                positions.len().write_to(&mut manifest)?;
+                for (term_id, listing) in positions {
+                    term_id.write_to(&mut manifest)?;
+                    listing.write_to(&mut manifest)?;
+                }
+            }
+            manifest.flush()?;
+            file.sync_all()?;
+        }
+        fs::rename(dir.join(MANIFEST_TMP), dir.join(MANIFEST))?;
+        // Make the rename durable before the journal for it is dropped
+        File::open(&dir)?.sync_all()?;
+        self.page_manager.store_mut().checkpoint()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use test_utils::create_test_dir;
+
+    use index::Index;
+    use index::posting::{Posting, DocId};
+    use page_manager::{FsPageManager, RamPageCache};
+    use index::vocabulary::SharedVocabulary;
+
+    #[test]
+    fn persist_and_open() {
+        let path = &create_test_dir("persistence/persist_and_open");
+        let _ = fs::remove_file(path.join("index.manifest"));
+        {
+            let mut index = Index::<u32>::open(path).unwrap();
+            for i in 0..200 {
+                assert_eq!(index.index_document(i..i + 200, None).unwrap(), DocId(i));
+            }
+            index.persist().unwrap();
+        }
+        let index = Index::<u32>::open(path).unwrap();
+        assert_eq!(index.doc_count, 200);
+        assert_eq!((index.query_atom(&0).1).collect::<Vec<_>>(),
+                   vec![Posting(DocId(0), 1)]);
+        assert_eq!((index.query_atom(&99).1).collect::<Vec<_>>(),
+                   (0..100).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>());
+        assert_eq!((index.query_atom(&398).1).collect::<Vec<_>>(),
+                   vec![Posting(DocId(199), 1)]);
+    }
+
+    #[test]
+    fn continue_indexing() {
+        let path = &create_test_dir("persistence/continue_indexing");
+        let _ = fs::remove_file(path.join("index.manifest"));
+        {
+            let mut index = Index::<String>::open(path).unwrap();
+            index.index_document(vec!["a".to_string(), "b".to_string()].into_iter(), None).unwrap();
+            index.persist().unwrap();
+        }
+        {
+            let mut index = Index::<String>::open(path).unwrap();
+            assert_eq!(index.index_document(vec!["b".to_string(), "c".to_string()].into_iter(),
+                                            None).unwrap(),
+                       DocId(1));
+            index.persist().unwrap();
+        }
+        let index = Index::<String>::open(path).unwrap();
+        assert_eq!((index.query_atom(&"a".to_string()).1).collect::<Vec<_>>(),
+                   vec![Posting(DocId(0), 1)]);
+        assert_eq!((index.query_atom(&"b".to_string()).1).collect::<Vec<_>>(),
+                   vec![Posting(DocId(0), 1), Posting(DocId(1), 1)]);
+        assert_eq!((index.query_atom(&"c".to_string()).1).collect::<Vec<_>>(),
+                   vec![Posting(DocId(1), 1)]);
+    }
+
+    #[test]
+    fn shared_vocabulary() {
+        let path = &create_test_dir("persistence/shared_vocabulary");
+        for name in &["a", "b", "c"] {
+            let _ = fs::remove_file(path.join(name).join("index.manifest"));
+        }
+        {
+            let vocabulary = SharedVocabulary::new();
+            let mut a = Index::<u32>::open_with_vocabulary(&path.join("a"), vocabulary.clone())
+                .unwrap();
+            let mut b = Index::<u32>::open_with_vocabulary(&path.join("b"), vocabulary).unwrap();
+            a.index_document(0..10, None).unwrap();
+            b.index_document(5..20, None).unwrap();
+            a.persist().unwrap();
+            b.persist().unwrap();
+        }
+        let vocabulary = SharedVocabulary::new();
+        let mut a = Index::<u32>::open_with_vocabulary(&path.join("a"), vocabulary.clone())
+            .unwrap();
+        let mut b = Index::<u32>::open_with_vocabulary(&path.join("b"), vocabulary.clone())
+            .unwrap();
+        // New terms get one id in both indices
+        a.index_document(20..30, None).unwrap();
+        b.index_document(25..30, None).unwrap();
+        for term in 0..30 {
+            assert_eq!(a.get_term_id(&term), b.get_term_id(&term));
+        }
+        // An index that assigned ids on its own does not fit in
+        {
+            let mut c = Index::<u32>::open(&path.join("c")).unwrap();
+            c.index_document((0..10).rev(), None).unwrap();
+            c.persist().unwrap();
+        }
+        assert!(Index::<u32>::open_with_vocabulary(&path.join("c"), vocabulary).is_err());
+    }
+
+    #[test]
+    fn persist_deleted_docs() {
+        let path = &create_test_dir("persistence/persist_deleted_docs");
+        let _ = fs::remove_file(path.join("index.manifest"));
+        {
+            let mut index = Index::<u32>::open(path).unwrap();
+            index.index_document(0..10, None).unwrap();
+            index.index_document(0..10, None).unwrap();
+            index.delete_document(DocId(0));
+            index.persist().unwrap();
+        }
+        let mut index = Index::<u32>::open(path).unwrap();
+        assert_eq!(index.doc_count, 1);
+        assert_eq!((index.query_atom(&5).1).collect::<Vec<_>>(),
+                   vec![Posting(DocId(1), 1)]);
+        // The forward index survives as well
+        assert!(index.update_document(DocId(1), 20..30).unwrap());
+        index.purge().unwrap();
+        index.commit().unwrap();
+        assert_eq!(index.term_df(&index.get_term_id(&5).unwrap()), 0);
+        assert_eq!((index.query_atom(&25).1).collect::<Vec<_>>(),
+                   vec![Posting(DocId(1), 1)]);
+    }
+
+    #[test]
+    fn persist_positions() {
+        let path = &create_test_dir("persistence/persist_positions");
+        let _ = fs::remove_file(path.join("index.manifest"));
+        {
+            let mut index = Index::<u32>::open_positional(path).unwrap();
+            for i in 0..100 {
+                index.index_document((0..i % 10).chain(0..5), None).unwrap();
+            }
+            index.persist().unwrap();
+        }
+        // The positional mode is read from the manifest
+        let index = Index::<u32>::open(path).unwrap();
+        assert!(index.is_positional());
+        let mut iter = index.query_positional(&3).unwrap();
+        assert_eq!(iter.next(), Some(Posting(DocId(0), 1)));
+        assert_eq!(iter.positions(DocId(0)), Some(vec![3]));
+        assert_eq!(iter.positions(DocId(5)), Some(vec![3, 8]));
+        assert_eq!(iter.positions(DocId(99)), Some(vec![3, 12]));
+    }
+
+    #[test]
+    fn crash_after_persist() {
+        let path = &create_test_dir("persistence/crash_after_persist");
+        let _ = fs::remove_file(path.join("index.manifest"));
+        // Sparse doc ids compress badly, so terms 0 to 2 fill whole pages
+        let doc_id = |i: u32| DocId(i * 1000 + i * i % 997);
+        let expected = |index: &Index<u32>| {
+            (0..10).map(|t| (index.query_atom(&t).1).collect::<Vec<_>>()).collect::<Vec<_>>()
+        };
+        let persisted;
+        {
+            let mut index = Index::<u32>::open(path).unwrap();
+            for i in 0..5000 {
+                index.index_document((0..3).chain(3 + i % 5..5 + i % 5), Some(doc_id(i)))
+                    .unwrap();
+            }
+            index.persist().unwrap();
+            persisted = expected(&index);
+            // Rewriting the listings reuses their pages
+            for i in 0..50 {
+                index.update_document(doc_id(i), 5..8).unwrap();
+                index.delete_document(doc_id(i + 50));
+            }
+            index.purge().unwrap();
+            index.index_document(0..10, None).unwrap();
+            index.commit().unwrap();
+            assert!(expected(&index) != persisted);
+            // Crash without persisting
+        }
+        let index = Index::<u32>::open(path).unwrap();
+        index.verify().unwrap();
+        assert_eq!(index.doc_count, 5000);
+        assert_eq!(expected(&index), persisted);
+    }
+
+    #[test]
+    fn verify() {
+        use std::fs::OpenOptions;
+        use std::io::{Seek, SeekFrom, Write};
+        use error::Error;
+
+        let path = &create_test_dir("persistence/verify");
+        let _ = fs::remove_file(path.join("index.manifest"));
+        {
+            let mut index = Index::<u32>::open_positional(path).unwrap();
+            for i in 0..100 {
+                index.index_document(0..i % 10 + 50, None).unwrap();
+            }
+            index.optimize().unwrap();
+            index.persist().unwrap();
+        }
+        Index::<u32>::open(path).unwrap().verify().unwrap();
+        {
+            let mut file = OpenOptions::new().write(true).open(path.join("pages.bin")).unwrap();
+            file.seek(SeekFrom::Start(100)).unwrap();
+            file.write_all(&[0xFF; 8]).unwrap();
+        }
+        let mut index = Index::<u32>::open(path).unwrap();
+        assert!(matches!(index.verify(), Err(Error::Corruption(_))));
+        // Queries stop at the corrupt page and keep the error
+        let failed = (0..60)
+            .filter(|term| {
+                let mut postings = index.query_atom(term).1;
+                postings.by_ref().count();
+                matches!(postings.error(), Some(Error::Corruption(_)))
+            })
+            .count();
+        assert!(failed > 0);
+        for doc_id in 0..100 {
+            index.delete_document(DocId(doc_id));
+        }
+        assert!(matches!(index.purge(), Err(Error::Corruption(_))));
+    }
+
+    #[test]
+    fn persist_without_dir() {
+        let path = &create_test_dir("persistence/persist_without_dir");
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        let mut index = Index::<u32>::new(RamPageCache::new(pmgr), SharedVocabulary::new());
+        assert!(index.persist().is_err());
+    }
+}
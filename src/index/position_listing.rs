@@ -0,0 +1,392 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use error::Result;
+use utils::persistence::Persistent;
+
+use page_manager::{Pages, PageId, Block, BlockIter, BlockId, RamPageCache, PageCache, BlockManager,
+                   PageStore, BLOCKSIZE, PAGESIZE};
+
+use index::posting::DocId;
+use index::positions::PositionDecoder;
+
+/// Number of u32 words stored in a block
+pub const WORDS_PER_BLOCK: usize = BLOCKSIZE / 4;
+
+/// Stores the positions of a term in the documents it occurs in.
+///
+/// The positions live in their own blocks, apart from the postings.
+/// They form a stream of u32 words with one entry per document:
+/// `[doc_id, count, position deltas...]`.
+/// Entries may span multiple blocks.
+///
+/// For every block the listing remembers the entry covering its first word.
+/// This allows `PositionDecoder` to skip blocks.
+#[derive(Clone, Debug)]
+pub struct PositionListing {
+    pages: Pages,
+    current_page: Option<PageId>,
+    block_counter: BlockId,
+    // (doc_id, start word) of the entry covering the first word of each shipped block
+    skips: Vec<(DocId, u32)>,
+    // Entries starting in the not yet shipped words
+    starts: VecDeque<(DocId, u32)>,
+    // The entry covering the first word of the next block
+    current: (DocId, u32),
+    word_buffer: VecDeque<u32>,
+    last_doc: Option<DocId>,
+    words: u32,
+}
+
+impl PositionListing {
+    pub fn new() -> Self {
+        PositionListing {
+            pages: Pages::new(),
+            current_page: None,
+            block_counter: BlockId::first(),
+            skips: Vec::new(),
+            starts: VecDeque::new(),
+            current: (DocId(0), 0),
+            word_buffer: VecDeque::new(),
+            last_doc: None,
+            words: 0,
+        }
+    }
+
+    /// Adds the sorted positions of the term in a document.
+    /// Documents have to be added in ascending order.
+    pub fn add<S: PageStore>(&mut self,
+                             doc_id: DocId,
+                             positions: &[u32],
+                             page_cache: &mut RamPageCache<S>)
+                             -> Result<()> {
+        // Same as for listings: a committed unfull page has to be unraveled first
+        if self.pages.unfull().is_some() || self.has_partial_block() {
+            self.unravel_tail(page_cache)?;
+        }
+        // Don't allow duplicate entries for documents
+        if self.last_doc.is_some_and(|last| last >= doc_id) {
+            return Ok(());
+        }
+        self.last_doc = Some(doc_id);
+        self.starts.push_back((doc_id, self.words));
+        self.word_buffer.push_back(doc_id.0);
+        self.word_buffer.push_back(positions.len() as u32);
+        let mut last = 0;
+        for position in positions {
+            self.word_buffer.push_back(position - last);
+            last = *position;
+        }
+        self.words += 2 + positions.len() as u32;
+        while self.word_buffer.len() >= WORDS_PER_BLOCK {
+            self.ship_words(page_cache)?;
+        }
+        Ok(())
+    }
+
+    pub fn commit<S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>) -> Result<()> {
+        if !self.word_buffer.is_empty() {
+            self.ship_words(page_cache)?;
+        }
+        if let Some(unfull_page) = self.current_page {
+            self.pages.add_unfull(page_cache.flush_unfull(unfull_page, self.block_counter)?);
+            self.current_page = None;
+            self.block_counter = BlockId::first();
+        }
+        Ok(())
+    }
+
+    /// Decodes all entries of this listing, lets `f` modify them and encodes
+    /// the result again.
+    /// The pages of the old entries are released.
+    pub fn rewrite<F, S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>, f: F) -> Result<()>
+        where F: FnOnce(&mut Vec<(DocId, Vec<u32>)>)
+    {
+        self.commit(page_cache)?;
+        let mut entries = Vec::new();
+        {
+            let mut decoder = self.position_decoder(page_cache);
+            while let Some(entry) = decoder.next_entry() {
+                entries.push(entry);
+            }
+            if let Some(err) = decoder.error() {
+                return Err(err.clone());
+            }
+        }
+        f(&mut entries);
+        self.release(page_cache)?;
+        *self = PositionListing::new();
+        for &(doc_id, ref positions) in &entries {
+            self.add(doc_id, positions, page_cache)?;
+        }
+        self.commit(page_cache)
+    }
+
+    /// Returns true if every entry is stored in a page
+    pub fn is_committed(&self) -> bool {
+        self.word_buffer.is_empty() && self.current_page.is_none()
+    }
+
+    /// Returns true if no document is stored in this listing
+    pub fn is_empty(&self) -> bool {
+        self.words == 0
+    }
+
+    /// The last document added to this listing
+    pub fn last(&self) -> Option<DocId> {
+        self.last_doc
+    }
+
+    pub fn position_decoder<'a>(&'a self, cache: &'a dyn PageCache) -> PositionDecoder<'a> {
+        let block_iter = BlockIter::new(cache, self.pages.clone());
+        PositionDecoder::new(block_iter, &self.skips, self.words)
+    }
+
+    /// Reads all pages of this listing to check that they are intact
+    pub fn verify(&self, cache: &dyn PageCache) -> Result<()> {
+        self.pages.verify(cache)
+    }
+
+    /// Returns the pages of this listing to the page cache.
+    /// The listing must not be used afterwards.
+    fn release<S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>) -> Result<()> {
+        for page_id in &self.pages.0 {
+            page_cache.delete_page(*page_id)?;
+        }
+        if let Some(unfull_page) = self.pages.take_unfull() {
+            page_cache.delete_unfull(unfull_page.page_id())?;
+        }
+        Ok(())
+    }
+
+    /// Takes up to a block of words from the buffer and ships it.
+    fn ship_words<S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>) -> Result<()> {
+        // Find the entry covering the first word of this block
+        let first_word = (self.skips.len() * WORDS_PER_BLOCK) as u32;
+        while self.starts.front().is_some_and(|start| start.1 <= first_word) {
+            self.current = self.starts.pop_front().unwrap();
+        }
+        self.skips.push(self.current);
+        let mut block = [0u8; BLOCKSIZE];
+        for chunk in block.chunks_mut(4) {
+            match self.word_buffer.pop_front() {
+                Some(word) => chunk.copy_from_slice(&word.to_le_bytes()),
+                None => break,
+            }
+        }
+        self.ship(page_cache, Block(block))
+    }
+
+    /// Stores a block on the current page like `Listing` does
+    fn ship<S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>, block: Block) -> Result<()> {
+        if self.block_counter == BlockId::first() {
+            self.current_page = Some(page_cache.store_block(block));
+        } else {
+            page_cache.store_in_place(self.current_page.unwrap(), self.block_counter, block)?;
+        }
+        if self.block_counter == BlockId::last() {
+            self.pages.push(page_cache.flush_page(self.current_page.take().unwrap())?);
+        }
+        self.block_counter.inc();
+        Ok(())
+    }
+
+    /// Is the last shipped block only partially filled?
+    /// This is the case after a commit that did not end on a block boundary.
+    fn has_partial_block(&self) -> bool {
+        self.current_page.is_none() && self.word_buffer.is_empty() &&
+        !(self.words as usize).is_multiple_of(WORDS_PER_BLOCK)
+    }
+
+    /// Rewinds to the state before the blocks of the last committed page
+    /// were shipped. Their words are put back into the buffer.
+    ///
+    /// Unlike postings, words are not self-delimiting. So besides an unfull
+    /// page, a full page ending with a partially filled block is unraveled,
+    /// too.
+    fn unravel_tail<S: PageStore>(&mut self, page_cache: &mut RamPageCache<S>) -> Result<()> {
+        assert!(self.current_page.is_none());
+        let block_count = match self.pages.unfull() {
+            Some(unfull_page) => (unfull_page.to().0 - unfull_page.from().0) as usize,
+            None => PAGESIZE,
+        };
+        let first_block = self.skips.len() - block_count;
+        let first_word = (first_block * WORDS_PER_BLOCK) as u32;
+        let covering = self.skips[first_block];
+        let (starts, words) = {
+            let mut decoder = self.position_decoder(page_cache);
+            // The covering entry might start on an earlier page.
+            // Walk the entries from there to find the ones starting on the last page
+            decoder.seek_word(covering.1);
+            let mut starts = VecDeque::new();
+            while let Some((doc_id, start)) = decoder.skip_entry() {
+                if start >= first_word {
+                    starts.push_back((doc_id, start));
+                }
+            }
+            if let Some(err) = decoder.error() {
+                return Err(err.clone());
+            }
+            decoder = self.position_decoder(page_cache);
+            decoder.seek_word(first_word);
+            let words = decoder.collect_words();
+            if let Some(err) = decoder.error() {
+                return Err(err.clone());
+            }
+            (starts, words)
+        };
+        match self.pages.take_unfull() {
+            Some(unfull_page) => page_cache.delete_unfull(unfull_page.page_id())?,
+            None => page_cache.delete_page(self.pages.0.pop().unwrap())?,
+        }
+        self.skips.truncate(first_block);
+        self.current = covering;
+        self.starts = starts;
+        self.word_buffer = words;
+        self.block_counter = BlockId::first();
+        Ok(())
+    }
+}
+
+/// Only committed position listings can be persisted.
+impl Persistent for PositionListing {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        debug_assert!(self.current_page.is_none() && self.word_buffer.is_empty());
+        self.pages.write_to(target)?;
+        self.skips.write_to(target)?;
+        self.last_doc.write_to(target)?;
+        self.words.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        let mut listing = PositionListing::new();
+        listing.pages = Pages::read_from(source)?;
+        listing.skips = Vec::read_from(source)?;
+        listing.last_doc = Option::read_from(source)?;
+        listing.words = u32::read_from(source)?;
+        Ok(listing)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use test_utils::create_test_dir;
+
+    use super::PositionListing;
+    use utils::persistence::Persistent;
+    use index::posting::DocId;
+    use page_manager::{FsPageManager, RamPageCache};
+
+    fn new_cache(name: &str) -> RamPageCache {
+        let path = &create_test_dir(format!("position_listing/{}", name).as_str());
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        RamPageCache::new(pmgr)
+    }
+
+    fn positions(i: u32) -> Vec<u32> {
+        (0..i % 40).map(|p| p * 3 + i % 5).collect()
+    }
+
+    #[test]
+    fn basic() {
+        let mut cache = new_cache("basic");
+        let mut listing = PositionListing::new();
+        listing.add(DocId(0), &[1, 5, 7], &mut cache).unwrap();
+        listing.add(DocId(3), &[0], &mut cache).unwrap();
+        listing.commit(&mut cache).unwrap();
+        let mut decoder = listing.position_decoder(&cache);
+        assert_eq!(decoder.positions(DocId(0)), Some(vec![1, 5, 7]));
+        assert_eq!(decoder.positions(DocId(2)), None);
+        assert_eq!(decoder.positions(DocId(3)), Some(vec![0]));
+        assert_eq!(decoder.positions(DocId(4)), None);
+    }
+
+    #[test]
+    fn many() {
+        let mut cache = new_cache("many");
+        let mut listing = PositionListing::new();
+        for i in 0..5000 {
+            listing.add(DocId(i * 2), &positions(i), &mut cache).unwrap();
+        }
+        listing.commit(&mut cache).unwrap();
+        let mut decoder = listing.position_decoder(&cache);
+        for i in 0..5000 {
+            assert_eq!(decoder.positions(DocId(i * 2)), Some(positions(i)));
+            assert_eq!(decoder.positions(DocId(i * 2 + 1)), None);
+        }
+    }
+
+    #[test]
+    fn skipping() {
+        let mut cache = new_cache("skipping");
+        let mut listing = PositionListing::new();
+        for i in 0..5000 {
+            listing.add(DocId(i), &positions(i), &mut cache).unwrap();
+        }
+        listing.commit(&mut cache).unwrap();
+        let mut decoder = listing.position_decoder(&cache);
+        assert_eq!(decoder.positions(DocId(3)), Some(positions(3)));
+        assert_eq!(decoder.positions(DocId(2500)), Some(positions(2500)));
+        assert_eq!(decoder.positions(DocId(2539)), Some(positions(2539)));
+        assert_eq!(decoder.positions(DocId(4999)), Some(positions(4999)));
+        assert_eq!(decoder.positions(DocId(5000)), None);
+    }
+
+    #[test]
+    fn add_after_commit() {
+        let mut cache = new_cache("add_after_commit");
+        let mut listing = PositionListing::new();
+        for i in 0..3000 {
+            listing.add(DocId(i), &positions(i), &mut cache).unwrap();
+            if i % 7 == 0 {
+                listing.commit(&mut cache).unwrap();
+            }
+        }
+        listing.commit(&mut cache).unwrap();
+        let mut decoder = listing.position_decoder(&cache);
+        for i in 0..3000 {
+            assert_eq!(decoder.positions(DocId(i)), Some(positions(i)));
+        }
+    }
+
+    #[test]
+    fn rewrite() {
+        let mut cache = new_cache("rewrite");
+        let mut listing = PositionListing::new();
+        for i in 0..1000 {
+            listing.add(DocId(i), &positions(i), &mut cache).unwrap();
+        }
+        listing.rewrite(&mut cache, |entries| entries.retain(|e| (e.0).0 % 2 == 0)).unwrap();
+        let mut decoder = listing.position_decoder(&cache);
+        for i in 0..1000 {
+            if i % 2 == 0 {
+                assert_eq!(decoder.positions(DocId(i)), Some(positions(i)));
+            } else {
+                assert_eq!(decoder.positions(DocId(i)), None);
+            }
+        }
+        listing.rewrite(&mut cache, |entries| entries.clear()).unwrap();
+        assert!(listing.is_empty());
+    }
+
+    #[test]
+    fn persistent() {
+        let mut cache = new_cache("persistent");
+        let mut listing = PositionListing::new();
+        for i in 0..100 {
+            listing.add(DocId(i), &positions(i), &mut cache).unwrap();
+        }
+        listing.commit(&mut cache).unwrap();
+        let mut bytes = Vec::new();
+        listing.write_to(&mut bytes).unwrap();
+        let mut restored = PositionListing::read_from(&mut Cursor::new(bytes)).unwrap();
+        restored.add(DocId(100), &[4, 2000], &mut cache).unwrap();
+        restored.commit(&mut cache).unwrap();
+        let mut decoder = restored.position_decoder(&cache);
+        assert_eq!(decoder.positions(DocId(99)), Some(positions(99)));
+        assert_eq!(decoder.positions(DocId(100)), Some(vec![4, 2000]));
+    }
+}
@@ -0,0 +1,202 @@
+//! Access to the positions of terms in documents.
+//!
+//! Positions are only stored by positional indices.
+//! See `Index::new_positional`.
+use std::collections::VecDeque;
+
+use error::Error;
+use page_manager::BlockIter;
+use utils::seeking_iterator::SeekingIterator;
+use index::posting::{Posting, PostingIterator, DocId};
+use index::position_listing::WORDS_PER_BLOCK;
+
+/// Decodes the positions of a term for increasing doc ids.
+#[derive(Clone, Debug)]
+pub struct PositionDecoder<'a> {
+    blocks: BlockIter<'a>,
+    skips: &'a [(DocId, u32)],
+    words: u32,
+    block: [u32; WORDS_PER_BLOCK],
+    // Index of the decoded block and of the next block `blocks` yields
+    block_index: usize,
+    next_block: usize,
+    // Next word to read
+    word: u32,
+    // Doc id and position count of an entry that was read but not yet consumed
+    header: Option<(DocId, u32)>,
+}
+
+impl<'a> PositionDecoder<'a> {
+    pub fn new(blocks: BlockIter<'a>, skips: &'a [(DocId, u32)], words: u32) -> Self {
+        PositionDecoder {
+            blocks,
+            skips,
+            words,
+            block: [0; WORDS_PER_BLOCK],
+            block_index: usize::MAX,
+            next_block: 0,
+            word: 0,
+            header: None,
+        }
+    }
+
+    /// The error of the page that could not be read, if decoding stopped
+    /// early
+    pub fn error(&self) -> Option<&Error> {
+        self.blocks.error()
+    }
+
+    /// Returns the positions of the term in a document or None if the term
+    /// does not occur in it.
+    ///
+    /// Documents have to be requested in ascending order. Entries before the
+    /// requested document are skipped.
+    pub fn positions(&mut self, doc_id: DocId) -> Option<Vec<u32>> {
+        // Jump to the entry covering the last block that starts not after doc_id
+        let index = self.skips.partition_point(|skip| skip.0 <= doc_id);
+        if index > 0 {
+            let start = self.skips[index - 1].1;
+            if start > self.word {
+                self.seek_word(start);
+            }
+        }
+        loop {
+            let (entry_doc, count) = self.read_header()?;
+            if entry_doc > doc_id {
+                return None;
+            }
+            self.header = None;
+            if entry_doc == doc_id {
+                return self.read_positions(count);
+            }
+            self.word += count;
+        }
+    }
+
+    /// Decodes the next entry
+    pub fn next_entry(&mut self) -> Option<(DocId, Vec<u32>)> {
+        let (doc_id, count) = self.read_header()?;
+        self.header = None;
+        Some((doc_id, self.read_positions(count)?))
+    }
+
+    /// Skips the next entry. Returns its doc id and the word it starts at
+    pub fn skip_entry(&mut self) -> Option<(DocId, u32)> {
+        let start = self.word;
+        let (doc_id, count) = self.read_header()?;
+        self.header = None;
+        self.word += count;
+        Some((doc_id, start))
+    }
+
+    /// Continues reading at `word`. Seeking backwards is not possible
+    pub fn seek_word(&mut self, word: u32) {
+        debug_assert!(word >= self.word || self.header.is_some());
+        self.header = None;
+        self.word = word;
+    }
+
+    /// Reads all remaining words
+    pub fn collect_words(&mut self) -> VecDeque<u32> {
+        let mut words = VecDeque::new();
+        while let Some(word) = self.read_word() {
+            words.push_back(word);
+        }
+        words
+    }
+
+    fn read_header(&mut self) -> Option<(DocId, u32)> {
+        if self.header.is_none() {
+            let doc_id = DocId(self.read_word()?);
+            let count = self.read_word()?;
+            self.header = Some((doc_id, count));
+        }
+        self.header
+    }
+
+    fn read_positions(&mut self, count: u32) -> Option<Vec<u32>> {
+        let mut positions = Vec::with_capacity(count as usize);
+        let mut last = 0;
+        for _ in 0..count {
+            last += self.read_word()?;
+            positions.push(last);
+        }
+        Some(positions)
+    }
+
+    fn read_word(&mut self) -> Option<u32> {
+        if self.word >= self.words {
+            return None;
+        }
+        let block_index = self.word as usize / WORDS_PER_BLOCK;
+        if block_index != self.block_index {
+            self.blocks.skip_blocks(block_index - self.next_block);
+            let block = self.blocks.next()?;
+            for (word, bytes) in self.block.iter_mut().zip(block.0.chunks(4)) {
+                *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+            }
+            self.block_index = block_index;
+            self.next_block = block_index + 1;
+        }
+        let word = self.block[self.word as usize % WORDS_PER_BLOCK];
+        self.word += 1;
+        Some(word)
+    }
+}
+
+/// Iterates over the postings of a term and gives access to the positions
+/// of the term in the current document.
+#[derive(Clone, Debug)]
+pub struct PositionalIterator<'a> {
+    postings: PostingIterator<'a>,
+    positions: Option<PositionDecoder<'a>>,
+}
+
+impl<'a> PositionalIterator<'a> {
+    pub fn new(postings: PostingIterator<'a>, positions: PositionDecoder<'a>) -> Self {
+        PositionalIterator {
+            postings,
+            positions: Some(positions),
+        }
+    }
+
+    /// An iterator without any postings
+    pub fn empty() -> Self {
+        PositionalIterator {
+            postings: PostingIterator::Empty,
+            positions: None,
+        }
+    }
+
+    /// The error that ended the iteration early or made positions
+    /// unavailable, if any
+    pub fn error(&self) -> Option<&Error> {
+        self.postings.error().or(self.positions.as_ref().and_then(|p| p.error()))
+    }
+
+    /// Returns the positions of the term in a document.
+    /// Documents have to be requested in ascending order.
+    pub fn positions(&mut self, doc_id: DocId) -> Option<Vec<u32>> {
+        self.positions.as_mut()?.positions(doc_id)
+    }
+}
+
+impl<'a> Iterator for PositionalIterator<'a> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Posting> {
+        self.postings.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.postings.size_hint()
+    }
+}
+
+impl<'a> SeekingIterator for PositionalIterator<'a> {
+    type Item = Posting;
+
+    fn next_seek(&mut self, other: &Posting) -> Option<Posting> {
+        self.postings.next_seek(other)
+    }
+}
@@ -1,13 +1,23 @@
-use compressor::Compressor;
+use std::io::{self, Read, Write};
+use std::collections::BTreeSet;
+
+use error::Error;
+use compressor::BestCompressor;
 use page_manager::BlockIter;
 use utils::ring_buffer::BiasedRingBuffer;
 use utils::Baseable;
-use utils::seeking_iterator::SeekingIterator;
+use utils::seeking_iterator::{SeekingIterator, SeekKey};
 use utils::progress::Progress;
-use index::listing::UsedCompressor;
-
-#[derive(Debug, PartialEq, Eq, Ord, PartialOrd, Copy, Clone)]
-pub struct Posting(pub DocId);
+use utils::persistence::Persistent;
+use index::elias_fano::EliasFanoDecoder;
+use index::bitmap::BitmapDecoder;
+
+/// A document of a listing and the number of times the term occurs in it.
+///
+/// Postings are not ordered. Seeking and the query operators order them by
+/// `doc_id()` and ignore the term frequency.
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct Posting(pub DocId, pub u32);
 #[derive(Debug, PartialEq, Eq, Ord, PartialOrd, Copy, Clone)]
 pub struct DocId(pub u32);
 
@@ -35,20 +45,44 @@ impl<'a> Baseable<&'a DocId> for DocId {
     }
 }
 
+impl Persistent for DocId {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.0.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok(DocId(u32::read_from(source)?))
+    }
+}
+
 impl Posting {
     #[inline]
     pub fn none() -> Posting {
-        Posting(DocId::none())
+        Posting(DocId::none(), 0)
     }
 
     pub fn doc_id(&self) -> DocId {
         self.0
     }
+
+    /// How often the term occurs in the document
+    pub fn tf(&self) -> u32 {
+        self.1
+    }
 }
 
 impl Default for Posting {
     fn default() -> Self {
-        Posting(DocId(0))
+        Posting(DocId(0), 0)
+    }
+}
+
+impl SeekKey for Posting {
+    type Key = DocId;
+
+    #[inline]
+    fn seek_key(&self) -> DocId {
+        self.0
     }
 }
 
@@ -63,12 +97,27 @@ impl<'a> Baseable<&'a Posting> for Posting {
     }
 }
 
+impl Persistent for Posting {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.0.write_to(target)?;
+        self.1.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok(Posting(DocId::read_from(source)?, u32::read_from(source)?))
+    }
+}
+
 /// Wraps the Decoder around an enum.
-/// For the possibility of an empty decoder
+/// For the possibility of an empty decoder, decoders of optimized listings
+/// or an iterator that skips the postings of deleted documents
 #[derive(Clone, Debug)]
 pub enum PostingIterator<'a> {
     Empty,
     Decoder(PostingDecoder<'a>),
+    EliasFano(EliasFanoDecoder<'a>),
+    Bitmap(BitmapDecoder<'a>),
+    Filtered(Box<PostingIterator<'a>>, &'a BTreeSet<DocId>),
 }
 
 /// Takes a block iterator and a list of biases and iterates over the resulting
@@ -80,6 +129,12 @@ pub struct PostingDecoder<'a> {
     blocks: BlockIter<'a>,
     pos: u32,
     len: u32,
+    // Set if a block could not be decompressed
+    error: Option<Error>,
+    // Average number of postings in a block.
+    // Blocks hold varying numbers of postings, so positions after skipping
+    // blocks are estimated with it
+    block_len: u32,
 }
 
 impl<'a> PostingDecoder<'a> {
@@ -89,10 +144,17 @@ impl<'a> PostingDecoder<'a> {
             bias_list: bias_list,
             posting_buffer: BiasedRingBuffer::new(),
             pos: 0,
-            len: len
+            len: len,
+            error: None,
+            block_len: len / (bias_list.len() as u32).max(1),
         }
     }
 
+    /// The error that ended the iteration early, if any
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref().or(self.blocks.error())
+    }
+
     pub fn progress(&self) -> Progress {
         use std::cmp;
         // It is possible, that self.pos exeeds self.len
@@ -104,6 +166,20 @@ impl<'a> PostingDecoder<'a> {
     }
 }
 
+impl<'a> PostingIterator<'a> {
+    /// The error that ended the iteration early, if any. Iterating stops at
+    /// the first page that cannot be read
+    pub fn error(&self) -> Option<&Error> {
+        match *self {
+            PostingIterator::Empty => None,
+            PostingIterator::Decoder(ref decoder) => decoder.error(),
+            PostingIterator::EliasFano(ref decoder) => decoder.error(),
+            PostingIterator::Bitmap(ref decoder) => decoder.error(),
+            PostingIterator::Filtered(ref decoder, _) => decoder.error(),
+        }
+    }
+}
+
 impl<'a> Iterator for PostingIterator<'a> {
     type Item = Posting;
 
@@ -111,6 +187,11 @@ impl<'a> Iterator for PostingIterator<'a> {
         match *self {
             PostingIterator::Empty => None,
             PostingIterator::Decoder(ref mut decoder) => decoder.next(),
+            PostingIterator::EliasFano(ref mut decoder) => decoder.next(),
+            PostingIterator::Bitmap(ref mut decoder) => decoder.next(),
+            PostingIterator::Filtered(ref mut decoder, deleted) => {
+                decoder.find(|posting| !deleted.contains(&posting.doc_id()))
+            }
         }
     }
 
@@ -118,11 +199,36 @@ impl<'a> Iterator for PostingIterator<'a> {
         match *self {
             PostingIterator::Empty => (0, Some(0)),
             PostingIterator::Decoder(ref decoder) => decoder.size_hint(),
+            PostingIterator::EliasFano(ref decoder) => decoder.size_hint(),
+            PostingIterator::Bitmap(ref decoder) => decoder.size_hint(),
+            PostingIterator::Filtered(ref decoder, deleted) => {
+                let (len, upper) = decoder.size_hint();
+                (len.saturating_sub(deleted.len()), upper)
+            }
         }
     }
 }
 
-impl<'a> ExactSizeIterator for PostingIterator<'a> {}
+impl<'a> SeekingIterator for PostingIterator<'a> {
+    type Item = Posting;
+
+    fn next_seek(&mut self, other: &Posting) -> Option<Posting> {
+        match *self {
+            PostingIterator::Empty => None,
+            PostingIterator::Decoder(ref mut decoder) => decoder.next_seek(other),
+            PostingIterator::EliasFano(ref mut decoder) => decoder.next_seek(other),
+            PostingIterator::Bitmap(ref mut decoder) => decoder.next_seek(other),
+            PostingIterator::Filtered(ref mut decoder, deleted) => {
+                let posting = decoder.next_seek(other)?;
+                if deleted.contains(&posting.doc_id()) {
+                    decoder.find(|posting| !deleted.contains(&posting.doc_id()))
+                } else {
+                    Some(posting)
+                }
+            }
+        }
+    }
+}
 
 impl<'a> ExactSizeIterator for PostingDecoder<'a> {}
 
@@ -130,12 +236,21 @@ impl<'a> Iterator for PostingDecoder<'a> {
     type Item = Posting;
 
     fn next(&mut self) -> Option<Posting> {
-        if self.posting_buffer.is_empty() {
+        if self.posting_buffer.is_empty() && self.error.is_none() {
             if let Some(block) = self.blocks.next() {
-                let (bias, rest) = self.bias_list.split_first().unwrap();
-                self.bias_list = rest;
-                self.posting_buffer.set_base(*bias);
-                UsedCompressor::decompress(block, &mut self.posting_buffer);
+                match self.bias_list.split_first() {
+                    Some((bias, rest)) => {
+                        self.bias_list = rest;
+                        self.posting_buffer.set_base(*bias);
+                        let decompressed =
+                            BestCompressor::decompress(block, &mut self.posting_buffer);
+                        if let Err(err) = decompressed {
+                            self.error = Some(err);
+                        }
+                    }
+                    // More blocks than biases: The listing is corrupt
+                    None => self.error = Some(Error::InvalidBlock(block.0[0])),
+                }
             }
         }
         self.pos += 1;
@@ -157,7 +272,7 @@ impl<'a> SeekingIterator for PostingDecoder<'a> {
 
     fn next_seek(&mut self, other: &Self::Item) -> Option<Self::Item> {
         // Check in what block we have to seek to
-        let index = match self.bias_list.binary_search(other) {
+        let index = match self.bias_list.binary_search_by_key(&other.doc_id(), Posting::doc_id) {
             Err(index) => index,
             Ok(index) => index,
         };
@@ -172,14 +287,14 @@ impl<'a> SeekingIterator for PostingDecoder<'a> {
             self.posting_buffer.flush();
             // Get block
             if index > 1 {
-                self.pos += (index as u32 - 1u32) * 16u32;
+                self.pos += (index as u32 - 1u32) * self.block_len;
                 self.blocks.skip_blocks(index - 1);
                 self.bias_list = &self.bias_list[index - 1..];
             }
         }
         loop {
             let v = self.next()?;
-            if v >= *other {
+            if v.doc_id() >= other.doc_id() {
                 return Some(v);
             }
         }
@@ -200,7 +315,7 @@ mod tests {
 
     fn new_cache(name: &str) -> RamPageCache {
         let path = &create_test_dir(format!("posting/{}", name).as_str());
-        let pmgr = FsPageManager::new(&path.join("pages.bin"));
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
         RamPageCache::new(pmgr)
     }
 
@@ -208,20 +323,35 @@ mod tests {
     fn single() {
         let mut cache = new_cache("single");
         let mut listing = Listing::new();
-        listing.add(&[Posting(DocId(0))], &mut cache);
-        listing.commit(&mut cache);
+        listing.add(&[Posting(DocId(0), 1)], &mut cache).unwrap();
+        listing.commit(&mut cache).unwrap();
         assert_eq!(listing.posting_decoder(&cache).collect::<Vec<_>>(),
-                   vec![Posting(DocId(0))]);
+                   vec![Posting(DocId(0), 1)]);
+    }
+
+    #[test]
+    fn term_frequencies() {
+        let mut cache = new_cache("term_frequencies");
+        let mut listing = Listing::new();
+        for i in 0..100 {
+            listing.add(&[Posting(DocId(i * 2), i % 7 + 1)], &mut cache).unwrap();
+        }
+        listing.commit(&mut cache).unwrap();
+        assert_eq!(listing.posting_decoder(&cache).map(|p| p.tf()).collect::<Vec<_>>(),
+                   (0..100).map(|i| i % 7 + 1).collect::<Vec<_>>());
+        let mut decoder = listing.posting_decoder(&cache);
+        // Seeking ignores the term frequency of the target
+        assert_eq!(decoder.next_seek(&Posting(DocId(100), 0)).map(|p| p.tf()), Some(51 % 7));
     }
 
     #[test]
     fn overcall() {
         let mut cache = new_cache("overcall");
         let mut listing = Listing::new();
-        listing.add(&[Posting(DocId(0))], &mut cache);
-        listing.commit(&mut cache);
+        listing.add(&[Posting(DocId(0), 1)], &mut cache).unwrap();
+        listing.commit(&mut cache).unwrap();
         let mut decoder = listing.posting_decoder(&cache);
-        assert_eq!(decoder.next(), Some(Posting(DocId(0))));
+        assert_eq!(decoder.next(), Some(Posting(DocId(0), 1)));
         assert_eq!(decoder.next(), None);
         assert_eq!(decoder.next(), None);
     }
@@ -231,10 +361,10 @@ mod tests {
         let mut cache = new_cache("many");
         let mut listing = Listing::new();
         for i in 0..2048 {
-            listing.add(&[Posting(DocId(i))], &mut cache);
+            listing.add(&[Posting(DocId(i), 1)], &mut cache).unwrap();
         }
-        listing.commit(&mut cache);
-        let res = (0..2048).map(|i| Posting(DocId(i))).collect::<Vec<_>>();
+        listing.commit(&mut cache).unwrap();
+        let res = (0..2048).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>();
         assert_eq!(listing.posting_decoder(&cache).collect::<Vec<_>>(), res);
     }
 
@@ -245,16 +375,16 @@ mod tests {
         let mut listing2 = Listing::new();
         let mut listing3 = Listing::new();
         for i in 0..2049 {
-            listing1.add(&[Posting(DocId(i))], &mut cache);
-            listing2.add(&[Posting(DocId(i * 2))], &mut cache);
-            listing3.add(&[Posting(DocId(i * 3))], &mut cache);
+            listing1.add(&[Posting(DocId(i), 1)], &mut cache).unwrap();
+            listing2.add(&[Posting(DocId(i * 2), 1)], &mut cache).unwrap();
+            listing3.add(&[Posting(DocId(i * 3), 1)], &mut cache).unwrap();
         }
-        listing1.commit(&mut cache);
-        listing2.commit(&mut cache);
-        listing3.commit(&mut cache);
-        let res1 = (0..2049).map(|i| Posting(DocId(i))).collect::<Vec<_>>();
-        let res2 = (0..2049).map(|i| Posting(DocId(i * 2))).collect::<Vec<_>>();
-        let res3 = (0..2049).map(|i| Posting(DocId(i * 3))).collect::<Vec<_>>();
+        listing1.commit(&mut cache).unwrap();
+        listing2.commit(&mut cache).unwrap();
+        listing3.commit(&mut cache).unwrap();
+        let res1 = (0..2049).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>();
+        let res2 = (0..2049).map(|i| Posting(DocId(i * 2), 1)).collect::<Vec<_>>();
+        let res3 = (0..2049).map(|i| Posting(DocId(i * 3), 1)).collect::<Vec<_>>();
         assert_eq!(listing1.posting_decoder(&cache).collect::<Vec<_>>(), res1);
         assert_eq!(listing2.posting_decoder(&cache).collect::<Vec<_>>(), res2);
         assert_eq!(listing3.posting_decoder(&cache).collect::<Vec<_>>(), res3);
@@ -267,22 +397,22 @@ mod tests {
         let mut listing2 = Listing::new();
         let mut listing3 = Listing::new();
         for i in 0..4596 {
-            listing1.add(&[Posting(DocId(i))], &mut cache);
+            listing1.add(&[Posting(DocId(i), 1)], &mut cache).unwrap();
             if i % 2 == 0 {
-                listing2.add(&[Posting(DocId(i * 2))], &mut cache);
+                listing2.add(&[Posting(DocId(i * 2), 1)], &mut cache).unwrap();
             }
             if i % 3 == 0 {
-                listing3.add(&[Posting(DocId(i * 3))], &mut cache);
+                listing3.add(&[Posting(DocId(i * 3), 1)], &mut cache).unwrap();
             }
         }
-        listing1.commit(&mut cache);
-        listing2.commit(&mut cache);
-        listing3.commit(&mut cache);
-        let res1 = (0..4596).map(|i| Posting(DocId(i))).collect::<Vec<_>>();
+        listing1.commit(&mut cache).unwrap();
+        listing2.commit(&mut cache).unwrap();
+        listing3.commit(&mut cache).unwrap();
+        let res1 = (0..4596).map(|i| Posting(DocId(i), 1)).collect::<Vec<_>>();
         let res2 =
-            (0..4596).filter(|i| i % 2 == 0).map(|i| Posting(DocId(i * 2))).collect::<Vec<_>>();
+            (0..4596).filter(|i| i % 2 == 0).map(|i| Posting(DocId(i * 2), 1)).collect::<Vec<_>>();
         let res3 =
-            (0..4596).filter(|i| i % 3 == 0).map(|i| Posting(DocId(i * 3))).collect::<Vec<_>>();
+            (0..4596).filter(|i| i % 3 == 0).map(|i| Posting(DocId(i * 3), 1)).collect::<Vec<_>>();
         assert_eq!(listing1.posting_decoder(&cache).collect::<Vec<_>>(), res1);
         assert_eq!(listing2.posting_decoder(&cache).collect::<Vec<_>>(), res2);
         assert_eq!(listing3.posting_decoder(&cache).collect::<Vec<_>>(), res3);
@@ -293,26 +423,26 @@ mod tests {
         let mut cache = new_cache("seeking");
         let mut listing1 = Listing::new();
         for i in 0..100 {
-            listing1.add(&[Posting(DocId(i))], &mut cache);
+            listing1.add(&[Posting(DocId(i), 1)], &mut cache).unwrap();
         }
-        listing1.commit(&mut cache);
+        listing1.commit(&mut cache).unwrap();
         let mut decoder = listing1.posting_decoder(&cache);
         // Case 2
-        assert_eq!(decoder.next_seek(&Posting(DocId(5))),
-                   Some(Posting(DocId(5))));
-        assert_eq!(decoder.next_seek(&Posting(DocId(6))),
-                   Some(Posting(DocId(6))));
+        assert_eq!(decoder.next_seek(&Posting(DocId(5), 1)),
+                   Some(Posting(DocId(5), 1)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(6), 1)),
+                   Some(Posting(DocId(6), 1)));
         // Case 3
-        assert_eq!(decoder.next_seek(&Posting(DocId(64))),
-                   Some(Posting(DocId(64))));
-        assert_eq!(decoder.next_seek(&Posting(DocId(78))),
-                   Some(Posting(DocId(78))));
+        assert_eq!(decoder.next_seek(&Posting(DocId(64), 1)),
+                   Some(Posting(DocId(64), 1)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(78), 1)),
+                   Some(Posting(DocId(78), 1)));
         // Case 1
-        assert_eq!(decoder.next_seek(&Posting(DocId(18))),
-                   Some(Posting(DocId(79))));
+        assert_eq!(decoder.next_seek(&Posting(DocId(18), 1)),
+                   Some(Posting(DocId(79), 1)));
 
         // Overseek
-        assert_eq!(decoder.next_seek(&Posting(DocId(200))), None);
+        assert_eq!(decoder.next_seek(&Posting(DocId(200), 1)), None);
     }
 
     #[test]
@@ -320,21 +450,21 @@ mod tests {
         let mut cache = new_cache("multipage_seeking");
         let mut listing1 = Listing::new();
         for i in (0..100_000).map(|i| i * 7) {
-            listing1.add(&[Posting(DocId(i))], &mut cache);
+            listing1.add(&[Posting(DocId(i), 1)], &mut cache).unwrap();
         }
-        listing1.commit(&mut cache);
+        listing1.commit(&mut cache).unwrap();
         let mut decoder = listing1.posting_decoder(&cache);
 
-        assert_eq!(decoder.next(), Some(Posting(DocId(0))));
-        assert_eq!(decoder.next(), Some(Posting(DocId(7))));
-        assert_eq!(decoder.next_seek(&Posting(DocId(7000))),
-                   Some(Posting(DocId(7000))));
-        assert_eq!(decoder.next_seek(&Posting(DocId(14001))),
-                   Some(Posting(DocId(14007))));
-        assert_eq!(decoder.next_seek(&Posting(DocId(699_993))),
-                   Some(Posting(DocId(699_993))));
+        assert_eq!(decoder.next(), Some(Posting(DocId(0), 1)));
+        assert_eq!(decoder.next(), Some(Posting(DocId(7), 1)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(7000), 1)),
+                   Some(Posting(DocId(7000), 1)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(14001), 1)),
+                   Some(Posting(DocId(14007), 1)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(699_993), 1)),
+                   Some(Posting(DocId(699_993), 1)));
         assert_eq!(decoder.next(), None);
-        assert_eq!(decoder.next_seek(&Posting(DocId(14001))), None);
+        assert_eq!(decoder.next_seek(&Posting(DocId(14001), 1)), None);
     }
 
 
@@ -343,29 +473,29 @@ mod tests {
         let mut cache = new_cache("ext_multipage_seeking");
         let mut listing1 = Listing::new();
         for i in 0..100_000 {
-            listing1.add(&[Posting(DocId(i))], &mut cache);
+            listing1.add(&[Posting(DocId(i), 1)], &mut cache).unwrap();
         }
-        listing1.commit(&mut cache);
+        listing1.commit(&mut cache).unwrap();
         let mut decoder = listing1.posting_decoder(&cache);
 
-        assert_eq!(decoder.next(), Some(Posting(DocId(0))));
-        assert_eq!(decoder.next(), Some(Posting(DocId(1))));
-        assert_eq!(decoder.next_seek(&Posting(DocId(2))),
-                   Some(Posting(DocId(2))));
-        assert_eq!(decoder.next_seek(&Posting(DocId(3))),
-                   Some(Posting(DocId(3))));
-        assert_eq!(decoder.next_seek(&Posting(DocId(1000))),
-                   Some(Posting(DocId(1000))));
-        assert_eq!(decoder.next_seek(&Posting(DocId(1001))),
-                   Some(Posting(DocId(1001))));
-        assert_eq!(decoder.next_seek(&Posting(DocId(99_990))),
-                   Some(Posting(DocId(99_990))));
-        assert_eq!(decoder.next_seek(&Posting(DocId(99_995))),
-                   Some(Posting(DocId(99_995))));
-        assert_eq!(decoder.next(), Some(Posting(DocId(99_996))));
-        assert_eq!(decoder.next(), Some(Posting(DocId(99_997))));
-        assert_eq!(decoder.next(), Some(Posting(DocId(99_998))));
-        assert_eq!(decoder.next(), Some(Posting(DocId(99_999))));
+        assert_eq!(decoder.next(), Some(Posting(DocId(0), 1)));
+        assert_eq!(decoder.next(), Some(Posting(DocId(1), 1)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(2), 1)),
+                   Some(Posting(DocId(2), 1)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(3), 1)),
+                   Some(Posting(DocId(3), 1)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(1000), 1)),
+                   Some(Posting(DocId(1000), 1)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(1001), 1)),
+                   Some(Posting(DocId(1001), 1)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(99_990), 1)),
+                   Some(Posting(DocId(99_990), 1)));
+        assert_eq!(decoder.next_seek(&Posting(DocId(99_995), 1)),
+                   Some(Posting(DocId(99_995), 1)));
+        assert_eq!(decoder.next(), Some(Posting(DocId(99_996), 1)));
+        assert_eq!(decoder.next(), Some(Posting(DocId(99_997), 1)));
+        assert_eq!(decoder.next(), Some(Posting(DocId(99_998), 1)));
+        assert_eq!(decoder.next(), Some(Posting(DocId(99_999), 1)));
     }
 
 }
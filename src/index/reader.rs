@@ -0,0 +1,258 @@
+//! Snapshots of an `Index` that can be queried from other threads.
+use std::hash::Hash;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use page_manager::{RamPageCache, CacheReader, PageStore, Pin, FsPageManager};
+use index::{Index, InverseDocumentFrequency, posting_iterator};
+use index::listing::Listing;
+use index::position_listing::PositionListing;
+use index::posting::{DocId, PostingIterator};
+use index::positions::PositionalIterator;
+use index::vocabulary::{TermId, SharedVocabulary, Vocabulary};
+
+/// The committed state of an `Index`, queryable while the index goes on
+/// indexing.
+///
+/// Created by `Index::reader`. The reader sees the index as of the last
+/// `commit` and is not affected by later changes. Pages it reads are not
+/// reused until it and all its clones are dropped, so long living readers
+/// hold back disk space. Cloning is cheap.
+///
+/// The vocabulary is shared with the index. So terms indexed after the
+/// snapshot are known, but have no postings yet.
+pub struct IndexReader<TTerm: Hash + Eq, TStore = FsPageManager> {
+    snapshot: Arc<Snapshot<TTerm>>,
+    cache: CacheReader<TStore>,
+}
+
+/// The listing metadata of a commit
+struct Snapshot<TTerm: Hash + Eq> {
+    listings: BTreeMap<TermId, Arc<Listing>>,
+    positions: Option<BTreeMap<TermId, Arc<PositionListing>>>,
+    vocabulary: SharedVocabulary<TTerm>,
+    deleted_docs: BTreeSet<DocId>,
+    doc_count: usize,
+    // Keeps the pages of the listings from being reused
+    _pin: Pin,
+}
+
+/// Takes a snapshot of committed listings. Pages deleted from now on are
+/// kept until the snapshot is dropped
+pub fn snapshot<TTerm, S>(listings: &BTreeMap<TermId, Arc<Listing>>,
+                          positions: &Option<BTreeMap<TermId, Arc<PositionListing>>>,
+                          vocabulary: &SharedVocabulary<TTerm>,
+                          deleted_docs: &BTreeSet<DocId>,
+                          doc_count: usize,
+                          page_cache: &mut RamPageCache<S>)
+                          -> IndexReader<TTerm, S>
+    where TTerm: Hash + Eq,
+          S: PageStore
+{
+    IndexReader {
+        snapshot: Arc::new(Snapshot {
+            listings: listings.clone(),
+            positions: positions.clone(),
+            vocabulary: vocabulary.clone(),
+            deleted_docs: deleted_docs.clone(),
+            doc_count,
+            _pin: page_cache.pin(),
+        }),
+        cache: page_cache.reader(),
+    }
+}
+
+impl<TTerm, TStore> Index<TTerm, TStore>
+    where TTerm: Hash + Ord,
+          TStore: PageStore
+{
+    /// Returns a snapshot of the index as of the last commit. See
+    /// `IndexReader`
+    pub fn reader(&self) -> IndexReader<TTerm, TStore> {
+        self.reader.clone()
+    }
+}
+
+impl<TTerm, TStore> IndexReader<TTerm, TStore>
+    where TTerm: Hash + Eq,
+          TStore: PageStore
+{
+    /// See `Index::get_term_id`
+    pub fn get_term_id(&self, atom: &TTerm) -> Option<TermId> {
+        self.snapshot.vocabulary.get(atom)
+    }
+
+    /// See `Index::query_atom`
+    pub fn query_atom(&self, atom: &TTerm) -> (InverseDocumentFrequency, PostingIterator<'_>) {
+        match self.get_term_id(atom) {
+            Some(term_id) => self.query_term(&term_id),
+            None => (InverseDocumentFrequency(0.0), PostingIterator::Empty),
+        }
+    }
+
+    /// See `Index::query_term`
+    pub fn query_term(&self, term_id: &TermId) -> (InverseDocumentFrequency, PostingIterator<'_>) {
+        match self.snapshot.listings.get(term_id) {
+            Some(listing) => {
+                posting_iterator(listing,
+                                 &self.cache,
+                                 self.snapshot.doc_count,
+                                 &self.snapshot.deleted_docs)
+            }
+            None => (InverseDocumentFrequency(0.0), PostingIterator::Empty),
+        }
+    }
+
+    /// See `Index::query_positional`
+    pub fn query_positional(&self, atom: &TTerm) -> Option<PositionalIterator<'_>> {
+        let positions = self.snapshot.positions.as_ref()?;
+        if let Some(term_id) = self.get_term_id(atom) {
+            if let Some(position_listing) = positions.get(&term_id) {
+                let (_, postings) = self.query_term(&term_id);
+                let decoder = position_listing.position_decoder(&self.cache);
+                return Some(PositionalIterator::new(postings, decoder));
+            }
+        }
+        Some(PositionalIterator::empty())
+    }
+
+    /// See `Index::term_df`
+    pub fn term_df(&self, term_id: &TermId) -> usize {
+        self.snapshot.listings.get(term_id).map_or(0, |listing| listing.len())
+    }
+
+    /// Number of documents in the snapshot, without deleted ones
+    pub fn doc_count(&self) -> usize {
+        self.snapshot.doc_count
+    }
+}
+
+impl<TTerm: Hash + Eq, TStore> Clone for IndexReader<TTerm, TStore> {
+    fn clone(&self) -> Self {
+        IndexReader {
+            snapshot: self.snapshot.clone(),
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use test_utils::create_test_dir;
+
+    use index::{Index, IndexReader};
+    use index::posting::{Posting, DocId};
+    use index::vocabulary::{SharedVocabulary, TermId};
+    use page_manager::{FsPageManager, RamPageCache};
+
+    fn new_index(name: &str) -> Index<u32> {
+        let path = &create_test_dir(format!("index_reader/{}", name).as_str());
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        Index::new(RamPageCache::new(pmgr), SharedVocabulary::new())
+    }
+
+    fn postings(reader: &IndexReader<u32>, term_id: TermId) -> Vec<Posting> {
+        (reader.query_term(&term_id).1).collect()
+    }
+
+    #[test]
+    fn snapshot() {
+        let mut index = new_index("snapshot");
+        for i in 0..100 {
+            index.index_document(0..i % 5 + 1, None).unwrap();
+        }
+        // Nothing committed yet
+        assert_eq!(index.reader().doc_count(), 0);
+        index.commit().unwrap();
+        let reader = index.reader();
+        let term_id = index.get_term_id(&0).unwrap();
+        assert_eq!(reader.term_df(&index.get_term_id(&4).unwrap()), 20);
+        for i in 0..100 {
+            index.index_document(0..i % 5 + 1, None).unwrap();
+        }
+        assert!(index.delete_document(DocId(0)));
+        index.commit().unwrap();
+        index.index_document(10..12, None).unwrap();
+        // The old snapshot is unchanged
+        assert_eq!(reader.doc_count(), 100);
+        assert_eq!(postings(&reader, term_id).len(), 100);
+        assert_eq!(index.reader().doc_count(), 199);
+        assert_eq!((index.reader().query_atom(&0).1).count(), 199);
+        // New terms are known to old snapshots, but have no postings
+        assert!(reader.get_term_id(&10).is_some());
+        assert_eq!((reader.query_atom(&10).1).count(), 0);
+        assert_eq!((index.reader().query_atom(&10).1).count(), 0);
+        index.commit().unwrap();
+        assert_eq!((index.reader().query_atom(&10).1).count(), 1);
+    }
+
+    #[test]
+    fn positions() {
+        let path = &create_test_dir("index_reader/positions");
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        let mut index = Index::new_positional(RamPageCache::new(pmgr), SharedVocabulary::new());
+        assert!(index.reader().query_positional(&0).is_some());
+        index.index_document(vec![0, 1, 0].into_iter(), None).unwrap();
+        index.commit().unwrap();
+        let reader = index.reader();
+        index.update_document(DocId(0), vec![1, 0].into_iter()).unwrap();
+        index.commit().unwrap();
+        assert_eq!(reader.query_positional(&0).unwrap().positions(DocId(0)), Some(vec![0, 2]));
+        assert_eq!(index.reader().query_positional(&0).unwrap().positions(DocId(0)),
+                   Some(vec![1]));
+    }
+
+    #[test]
+    fn concurrent_readers() {
+        let mut index = new_index("concurrent_readers");
+        let doc_id = |i: u32| DocId(i * 1000 + i * i % 997);
+        // Sparse doc ids, so that the listings fill full pages
+        for i in 0..5000 {
+            index.index_document(0..3, Some(doc_id(i))).unwrap();
+        }
+        index.commit().unwrap();
+        let term_id = index.get_term_id(&0).unwrap();
+        let reader = index.reader();
+        let expected = postings(&reader, term_id);
+        let threads = (0..4)
+            .map(|_| {
+                let reader = reader.clone();
+                let expected = expected.clone();
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        assert_eq!(postings(&reader, term_id), expected);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        // Rewrites the listings meanwhile, which frees their pages
+        let pages = index.cache_stats().store.bytes_on_disk;
+        for i in 0..100 {
+            index.update_document(doc_id(i), 1..4).unwrap();
+            index.commit().unwrap();
+        }
+        let pinned_growth = index.cache_stats().store.bytes_on_disk - pages;
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(postings(&reader, term_id), expected);
+        assert_eq!(postings(&index.reader(), term_id).len(), 4900);
+        // Without readers, the freed pages are reused sooner
+        drop(reader);
+        let pages = index.cache_stats().store.bytes_on_disk;
+        for i in 100..200 {
+            index.update_document(doc_id(i), 1..4).unwrap();
+            index.commit().unwrap();
+        }
+        index.verify().unwrap();
+        assert!(index.cache_stats().store.bytes_on_disk - pages < pinned_growth);
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<IndexReader<String>>();
+    }
+}
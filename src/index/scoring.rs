@@ -0,0 +1,266 @@
+//! Ranking of documents.
+//!
+//! A `Scorer` rates how well a document matches a single query term, based
+//! on statistics of the index. `Index::rank` sums these ratings over all
+//! terms of a query.
+use std::hash::Hash;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use index::{Index, InverseDocumentFrequency};
+use index::posting::DocId;
+use page_manager::PageStore;
+
+/// Statistics of the whole index
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CollectionStats {
+    /// Number of documents in the listings. Deleted documents count until
+    /// they are purged, as they still count for the document frequencies
+    pub doc_count: usize,
+    /// Average number of terms in a document
+    pub avg_doc_length: f32,
+}
+
+/// Statistics of a term in a document
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TermStats {
+    /// Number of documents the term occurs in
+    pub df: usize,
+    /// Number of times the term occurs in the document
+    pub tf: u32,
+    /// Number of terms in the document
+    pub doc_length: u32,
+}
+
+/// Rates how well a document matches a term. Higher is better.
+pub trait Scorer {
+    fn score(&self, collection: &CollectionStats, term: &TermStats) -> f32;
+
+    /// The highest score a term with the given document frequency can reach
+    /// in a document where it occurs at most `max_tf` times.
+    /// `Index::top_k` uses this to skip documents.
+    ///
+    /// The default assumes that scores never decrease with the term frequency
+    /// and never increase with the document length.
+    fn upper_bound(&self, collection: &CollectionStats, df: usize, max_tf: u32) -> f32 {
+        let term = TermStats {
+            df,
+            tf: max_tf,
+            doc_length: 0,
+        };
+        self.score(collection, &term)
+    }
+}
+
+/// Term frequency times inverse document frequency: tf * ln(N/df)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TfIdf;
+
+impl Scorer for TfIdf {
+    fn score(&self, collection: &CollectionStats, term: &TermStats) -> f32 {
+        term.tf as f32 * InverseDocumentFrequency::from(collection.doc_count, term.df).0
+    }
+}
+
+/// Okapi BM25.
+///
+/// `k1` limits how much repetitions of a term count. `b` controls how much
+/// the score is normalized by the length of the document.
+#[derive(Clone, Copy, Debug)]
+pub struct Bm25 {
+    pub k1: f32,
+    pub b: f32,
+}
+
+impl Bm25 {
+    pub fn new(k1: f32, b: f32) -> Self {
+        Bm25 { k1, b }
+    }
+}
+
+/// k1 = 1.2 and b = 0.75
+impl Default for Bm25 {
+    fn default() -> Self {
+        Bm25::new(1.2, 0.75)
+    }
+}
+
+impl Scorer for Bm25 {
+    fn score(&self, collection: &CollectionStats, term: &TermStats) -> f32 {
+        let n = collection.doc_count as f32;
+        let df = term.df as f32;
+        let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+        let avg_doc_length = if collection.avg_doc_length > 0.0 {
+            collection.avg_doc_length
+        } else {
+            1.0
+        };
+        let tf = term.tf as f32;
+        let norm = 1.0 - self.b + self.b * term.doc_length as f32 / avg_doc_length;
+        idf * tf * (self.k1 + 1.0) / (tf + self.k1 * norm)
+    }
+}
+
+impl<TTerm, TStore> Index<TTerm, TStore>
+    where TTerm: Hash + Ord,
+          TStore: PageStore
+{
+    pub fn collection_stats(&self) -> CollectionStats {
+        CollectionStats {
+            doc_count: self.doc_count + self.deleted_docs.len(),
+            avg_doc_length: self.doc_store.avg_doc_length(),
+        }
+    }
+
+    /// Scores every document that contains at least one of the terms.
+    /// The scores of the terms are summed up.
+    ///
+    /// Returns the documents ordered by descending score.
+    pub fn rank<S: Scorer>(&self, terms: &[TTerm], scorer: &S) -> Vec<(DocId, f32)> {
+        let collection = self.collection_stats();
+        let mut scores = BTreeMap::new();
+        for term in terms {
+            let term_id = match self.get_term_id(term) {
+                Some(term_id) => term_id,
+                None => continue,
+            };
+            let df = self.term_df(&term_id);
+            for posting in self.query_term(&term_id).1 {
+                let doc_length = self.doc_store
+                    .doc_length(posting.doc_id())
+                    .unwrap_or_else(|| posting.tf());
+                let stats = TermStats {
+                    df,
+                    tf: posting.tf(),
+                    doc_length,
+                };
+                *scores.entry(posting.doc_id()).or_insert(0.0) += scorer.score(&collection, &stats);
+            }
+        }
+        let mut ranked = scores.into_iter().collect::<Vec<_>>();
+        ranked.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal).then(a.0.cmp(&b.0)));
+        ranked
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use test_utils::create_test_dir;
+
+    use super::{Scorer, TfIdf, Bm25, CollectionStats, TermStats};
+    use index::Index;
+    use index::posting::DocId;
+    use index::vocabulary::SharedVocabulary;
+    use page_manager::{FsPageManager, RamPageCache};
+
+    fn new_index(name: &str) -> Index<&'static str> {
+        let path = &create_test_dir(format!("scoring/{}", name).as_str());
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        let mut index = Index::new(RamPageCache::new(pmgr), SharedVocabulary::new());
+        for doc in &["rust rust rust",
+                     "rust is a language",
+                     "a language",
+                     "rust rust and more rust and more words in a long document"] {
+            index.index_document(doc.split(' '), None).unwrap();
+        }
+        index.commit().unwrap();
+        index
+    }
+
+    const COLLECTION: CollectionStats = CollectionStats {
+        doc_count: 100,
+        avg_doc_length: 10.0,
+    };
+
+    #[test]
+    fn tf_idf() {
+        let stats = TermStats {
+            df: 10,
+            tf: 2,
+            doc_length: 10,
+        };
+        assert!((TfIdf.score(&COLLECTION, &stats) - 2.0 * 10f32.ln()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn bm25() {
+        let bm25 = Bm25::default();
+        let stats = TermStats {
+            df: 10,
+            tf: 1,
+            doc_length: 10,
+        };
+        // With an average document length bm25 reduces to idf * (k1 + 1) / (1 + k1)
+        let idf = (90.5f32 / 10.5 + 1.0).ln();
+        assert!((bm25.score(&COLLECTION, &stats) - idf).abs() < 1e-6);
+        // Repetitions count less and less
+        let tf = |tf| bm25.score(&COLLECTION, &TermStats { tf, ..stats });
+        assert!(tf(2) - tf(1) > tf(3) - tf(2));
+        // Long documents are penalized
+        let long = bm25.score(&COLLECTION, &TermStats { doc_length: 100, ..stats });
+        assert!(long < tf(1));
+        // Without length normalization the length does not matter
+        let bm25 = Bm25::new(1.2, 0.0);
+        assert_eq!(bm25.score(&COLLECTION, &TermStats { doc_length: 100, ..stats }),
+                   bm25.score(&COLLECTION, &stats));
+    }
+
+    #[test]
+    fn upper_bound() {
+        let bm25 = Bm25::default();
+        let bound = bm25.upper_bound(&COLLECTION, 10, 3);
+        for tf in 1..4 {
+            for doc_length in 1..50 {
+                let stats = TermStats {
+                    df: 10,
+                    tf,
+                    doc_length,
+                };
+                assert!(bm25.score(&COLLECTION, &stats) <= bound);
+            }
+        }
+    }
+
+    #[test]
+    fn rank() {
+        let index = new_index("rank");
+        assert_eq!(index.collection_stats().avg_doc_length, 5.25);
+        let ranked = index.rank(&["rust"], &Bm25::default());
+        assert_eq!(ranked.iter().map(|r| r.0).collect::<Vec<_>>(),
+                   vec![DocId(0), DocId(3), DocId(1)]);
+        let ranked = index.rank(&["rust"], &TfIdf);
+        assert_eq!(ranked.iter().map(|r| r.0).collect::<Vec<_>>(),
+                   vec![DocId(0), DocId(3), DocId(1)]);
+        assert_eq!(ranked[0].1, ranked[1].1);
+        let ranked = index.rank(&["language", "rust", "unknown"], &Bm25::default());
+        assert_eq!(ranked.iter().map(|r| r.0).collect::<Vec<_>>(),
+                   vec![DocId(1), DocId(2), DocId(0), DocId(3)]);
+    }
+
+    #[test]
+    fn rank_deleted() {
+        let mut index = new_index("rank_deleted");
+        index.delete_document(DocId(0));
+        let ranked = index.rank(&["rust"], &Bm25::default());
+        assert_eq!(ranked.iter().map(|r| r.0).collect::<Vec<_>>(), vec![DocId(3), DocId(1)]);
+    }
+
+    #[test]
+    fn rank_mostly_deleted() {
+        let mut index = new_index("rank_mostly_deleted");
+        for doc_id in 0..3 {
+            index.delete_document(DocId(doc_id));
+        }
+        // "a" is in more documents than are left. Its score must not turn negative
+        let ranked = index.rank(&["rust", "a"], &TfIdf);
+        assert_eq!(ranked.iter().map(|r| r.0).collect::<Vec<_>>(), vec![DocId(3)]);
+        assert!(ranked[0].1 >= 0.0);
+        assert_eq!(index.top_k(&["rust", "a"], 1, &TfIdf), ranked);
+        assert_eq!(index.top_k(&["rust", "a"], 1, &Bm25::default()),
+                   index.rank(&["rust", "a"], &Bm25::default()));
+        index.purge().unwrap();
+        assert_eq!(index.collection_stats().doc_count, 1);
+        assert_eq!(index.rank(&["rust", "a"], &TfIdf), vec![(DocId(3), 0.0)]);
+    }
+}
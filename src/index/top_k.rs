@@ -0,0 +1,277 @@
+//! Top-k retrieval with Block-Max WAND.
+//!
+//! WAND keeps the k best documents seen so far. A document can only enter
+//! them if its score exceeds the worst of them, the threshold. Every term
+//! has an upper bound of its score. Documents whose terms can not sum up
+//! to more than the threshold are skipped without decoding their postings.
+//!
+//! Block-Max WAND additionally uses an upper bound per block, derived from
+//! the highest term frequency of the block. So whole blocks can be skipped
+//! even if the term as a whole scores high.
+use std::hash::Hash;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use index::Index;
+use page_manager::PageStore;
+use index::posting::{DocId, Posting, PostingIterator};
+use index::scoring::{Scorer, TermStats};
+use utils::seeking_iterator::SeekingIterator;
+
+/// The postings of a term and the upper bounds of their scores
+struct Cursor<'a> {
+    postings: PostingIterator<'a>,
+    current: Option<Posting>,
+    biases: &'a [Posting],
+    block_max_score: Vec<f32>,
+    max_score: f32,
+    df: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn doc_id(&self) -> Option<DocId> {
+        self.current.map(|posting| posting.doc_id())
+    }
+
+    fn advance(&mut self) {
+        self.current = self.postings.next();
+    }
+
+    /// Moves to the first posting >= doc_id
+    fn seek(&mut self, doc_id: DocId) {
+        if self.doc_id().is_some_and(|current| current < doc_id) {
+            self.current = self.postings.next_seek(&Posting(doc_id, 0));
+        }
+    }
+
+    /// Index of the first block starting after doc_id
+    fn next_block(&self, doc_id: DocId) -> usize {
+        self.biases.partition_point(|bias| bias.doc_id() <= doc_id)
+    }
+
+    /// Upper bound of the score in the block that may contain doc_id
+    fn block_max_score(&self, doc_id: DocId) -> f32 {
+        let block = self.next_block(doc_id) - 1;
+        let max_score = self.block_max_score[block];
+        // A posting equal to a bias might be the last one of the previous block
+        if block > 0 && self.biases[block].doc_id() == doc_id {
+            max_score.max(self.block_max_score[block - 1])
+        } else {
+            max_score
+        }
+    }
+
+    /// The first doc id that might be in a block after the one containing
+    /// doc_id. None if that is the last block
+    fn block_end(&self, doc_id: DocId) -> Option<DocId> {
+        self.biases.get(self.next_block(doc_id)).map(|bias| bias.doc_id())
+    }
+}
+
+/// A scored document. Better documents are greater
+#[derive(PartialEq)]
+struct Candidate(f32, DocId);
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Lower doc ids win ties
+        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal).then(other.1.cmp(&self.1))
+    }
+}
+
+impl<TTerm, TStore> Index<TTerm, TStore>
+    where TTerm: Hash + Ord,
+          TStore: PageStore
+{
+    /// Returns the k documents with the highest score for the terms.
+    /// Same as the first k results of `rank`, but without scoring every
+    /// document.
+    pub fn top_k<S: Scorer>(&self, terms: &[TTerm], k: usize, scorer: &S) -> Vec<(DocId, f32)> {
+        if k == 0 {
+            return Vec::new();
+        }
+        let collection = self.collection_stats();
+        let mut cursors = Vec::new();
+        for term in terms {
+            let listing = match self.get_term_id(term).and_then(|id| self.listings.get(&id)) {
+                Some(listing) => listing,
+                None => continue,
+            };
+            let df = listing.len();
+            let block_max_score = listing.block_max_tf()
+                .iter()
+                .map(|max_tf| scorer.upper_bound(&collection, df, *max_tf))
+                .collect::<Vec<_>>();
+            let (_, mut postings) = self.posting_iterator(listing);
+            cursors.push(Cursor {
+                current: postings.next(),
+                postings,
+                biases: listing.block_biases(),
+                max_score: scorer.upper_bound(&collection, df, listing.max_tf()),
+                block_max_score,
+                df,
+            });
+        }
+
+        let mut top = BinaryHeap::new();
+        let mut threshold = f32::NEG_INFINITY;
+        // Indices of the cursors, ordered by their current doc id
+        let mut order = (0..cursors.len()).collect::<Vec<_>>();
+        loop {
+            order.retain(|&i| cursors[i].current.is_some());
+            order.sort_by_key(|&i| cursors[i].doc_id());
+            // Find the pivot: the first cursor at which the upper bounds exceed
+            // the threshold. No document before its doc id can make it
+            let mut bound = 0.0;
+            let mut pivot = match order.iter().position(|&i| {
+                bound += cursors[i].max_score;
+                bound > threshold
+            }) {
+                Some(pivot) => pivot,
+                None => break,
+            };
+            let pivot_doc = cursors[order[pivot]].doc_id().unwrap();
+            while order.get(pivot + 1).is_some_and(|&i| cursors[i].doc_id() == Some(pivot_doc)) {
+                pivot += 1;
+            }
+            let block_bound: f32 =
+                order[..pivot + 1].iter().map(|&i| cursors[i].block_max_score(pivot_doc)).sum();
+            if block_bound > threshold {
+                if cursors[order[0]].doc_id() == Some(pivot_doc) {
+                    // Every cursor up to the pivot is on the pivot doc. Score it
+                    let doc_length = self.doc_store.doc_length(pivot_doc);
+                    let mut score = 0.0;
+                    for cursor in cursors.iter_mut().filter(|c| c.doc_id() == Some(pivot_doc)) {
+                        let tf = cursor.current.unwrap().tf();
+                        let stats = TermStats {
+                            df: cursor.df,
+                            tf,
+                            doc_length: doc_length.unwrap_or(tf),
+                        };
+                        score += scorer.score(&collection, &stats);
+                        cursor.advance();
+                    }
+                    if top.len() < k {
+                        top.push(Reverse(Candidate(score, pivot_doc)));
+                    } else if score > threshold {
+                        top.pop();
+                        top.push(Reverse(Candidate(score, pivot_doc)));
+                    }
+                    if top.len() == k {
+                        threshold = (top.peek().unwrap().0).0;
+                    }
+                } else {
+                    for &i in &order[..pivot] {
+                        cursors[i].seek(pivot_doc);
+                    }
+                }
+            } else {
+                // No document up to the end of the current blocks can make it.
+                // Skip them
+                let mut next = order.get(pivot + 1).and_then(|&i| cursors[i].doc_id());
+                for &i in &order[..pivot + 1] {
+                    if let Some(end) = cursors[i].block_end(pivot_doc) {
+                        next = Some(next.map_or(end, |next| next.min(end)));
+                    }
+                }
+                let next = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                for &i in &order[..pivot + 1] {
+                    cursors[i].seek(next);
+                }
+            }
+        }
+        let mut result = top.into_iter()
+            .map(|Reverse(Candidate(score, doc_id))| (doc_id, score))
+            .collect::<Vec<_>>();
+        result.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal).then(a.0.cmp(&b.0)));
+        result
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use test_utils::create_test_dir;
+
+    use index::Index;
+    use index::posting::DocId;
+    use index::scoring::{Bm25, TfIdf};
+    use index::vocabulary::SharedVocabulary;
+    use page_manager::{FsPageManager, RamPageCache};
+
+    fn new_index(name: &str) -> Index<u32> {
+        let path = &create_test_dir(format!("top_k/{}", name).as_str());
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        let mut index = Index::new(RamPageCache::new(pmgr), SharedVocabulary::new());
+        for i in 0..5000u32 {
+            // Term t occurs in every (t + 2)th document, a varying number of times
+            let doc = (0..30u32)
+                .filter(|t| i % (t + 2) == 0)
+                .flat_map(|t| (0..(i * 7 + t * 13) % 5 + 1).map(move |_| t))
+                .chain((0..i % 17).map(|f| 100 + f));
+            index.index_document(doc, None).unwrap();
+        }
+        index.commit().unwrap();
+        index
+    }
+
+    #[test]
+    fn same_as_rank() {
+        let index = new_index("same_as_rank");
+        let queries: &[&[u32]] = &[&[0], &[0, 1], &[3, 17, 29], &[0, 5, 100], &[28, 29, 116],
+                                   &[1, 1, 2], &[1000]];
+        for query in queries {
+            for k in &[1, 10, 100] {
+                let ranked = index.rank(query, &Bm25::default());
+                assert_eq!(index.top_k(query, *k, &Bm25::default()),
+                           ranked.into_iter().take(*k).collect::<Vec<_>>());
+                let ranked = index.rank(query, &TfIdf);
+                assert_eq!(index.top_k(query, *k, &TfIdf),
+                           ranked.into_iter().take(*k).collect::<Vec<_>>());
+            }
+        }
+    }
+
+    #[test]
+    fn deleted_documents() {
+        let mut index = new_index("deleted_documents");
+        let best = index.top_k(&[0, 1], 5, &Bm25::default());
+        index.delete_document(best[0].0);
+        let top = index.top_k(&[0, 1], 5, &Bm25::default());
+        assert!(top.iter().all(|&(doc_id, _)| doc_id != best[0].0));
+        assert_eq!(top,
+                   index.rank(&[0, 1], &Bm25::default()).into_iter().take(5).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn optimized() {
+        let mut index = new_index("optimized");
+        let queries: &[&[u32]] = &[&[0], &[0, 1], &[3, 17, 29], &[0, 5, 100]];
+        let before = queries.iter()
+            .map(|query| index.top_k(query, 10, &Bm25::default()))
+            .collect::<Vec<_>>();
+        index.optimize().unwrap();
+        for (query, best) in queries.iter().zip(before) {
+            assert_eq!(index.top_k(query, 10, &Bm25::default()), best);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let index = new_index("empty");
+        assert_eq!(index.top_k(&[0], 0, &Bm25::default()), vec![]);
+        assert_eq!(index.top_k(&[], 10, &Bm25::default()), vec![]);
+        assert_eq!(index.top_k(&[5000], 10, &Bm25::default()), Vec::<(DocId, f32)>::new());
+    }
+}
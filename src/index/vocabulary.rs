@@ -1,21 +1,97 @@
 use std::hash::Hash;
 use std::collections::HashMap;
-use std::collections::hash_map::Iter;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, RwLock};
+use std::vec;
+
+use utils::persistence::{Persistent, MAX_PREALLOCATION};
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Copy, Clone)]
 pub struct TermId(pub u64);
 
-#[derive(Debug, Clone)]
-pub struct SharedVocabulary<TTerm: Hash + Eq>(HashMap<TTerm, TermId>);
+/// Maps terms to `TermId`s. Clones share the mapping, so indices created
+/// with clones of one vocabulary agree on the `TermId` of every term, even
+/// when used from different threads.
+#[derive(Debug)]
+pub struct SharedVocabulary<TTerm: Hash + Eq>(Arc<RwLock<HashMap<TTerm, TermId>>>);
 
 impl<TTerm: Hash + Eq> SharedVocabulary<TTerm> {
     pub fn new() -> Self {
-        SharedVocabulary(HashMap::new())
+        SharedVocabulary(Arc::new(RwLock::new(HashMap::new())))
+    }
+
+    /// Adds the terms of `other`, e.g. read from a persisted index, to this
+    /// vocabulary.
+    ///
+    /// Terms known to both must have the same `TermId`. Fails if they
+    /// don't, or if the `TermId` of a new term is taken by another term.
+    /// Nothing is added then.
+    pub fn merge(&self, other: SharedVocabulary<TTerm>) -> io::Result<()> {
+        let mut new_terms = other.0.write().unwrap().drain().collect::<Vec<_>>();
+        new_terms.sort_by_key(|&(_, term_id)| term_id);
+        let mut terms = self.0.write().unwrap();
+        // Term ids are handed out in order. New terms continue from here
+        let mut next_id = terms.len() as u64;
+        let mut added = Vec::new();
+        for (term, term_id) in new_terms {
+            match terms.get(&term) {
+                Some(&known) if known == term_id => continue,
+                None if term_id.0 == next_id => {
+                    next_id += 1;
+                    added.push((term, term_id));
+                }
+                _ => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              "vocabulary assigns term ids differently"))
+                }
+            }
+        }
+        terms.extend(added);
+        Ok(())
+    }
+}
+
+impl<TTerm: Hash + Eq> Clone for SharedVocabulary<TTerm> {
+    fn clone(&self) -> Self {
+        SharedVocabulary(self.0.clone())
+    }
+}
+
+impl Persistent for TermId {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.0.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok(TermId(u64::read_from(source)?))
+    }
+}
+
+/// The vocabulary is stored as a list of (term, term_id) pairs
+impl<TTerm: Hash + Eq + Persistent> Persistent for SharedVocabulary<TTerm> {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        let terms = self.0.read().unwrap();
+        terms.len().write_to(target)?;
+        for (term, term_id) in terms.iter() {
+            term.write_to(target)?;
+            term_id.write_to(target)?;
+        }
+        Ok(())
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        let len = usize::read_from(source)?;
+        let mut terms = HashMap::with_capacity(len.min(MAX_PREALLOCATION));
+        for _ in 0..len {
+            let term = TTerm::read_from(source)?;
+            terms.insert(term, TermId::read_from(source)?);
+        }
+        Ok(SharedVocabulary(Arc::new(RwLock::new(terms))))
     }
 }
 
 pub trait TermIterator<'a, TTerm: 'a> {
-    type TIter: Iterator<Item=(&'a TTerm, &'a TermId)>;
+    type TIter: Iterator<Item=(TTerm, TermId)>;
     fn iterate_terms(&'a self) -> Self::TIter;
 }
 
@@ -24,35 +100,38 @@ pub trait Vocabulary<TTerm> {
     fn get(&self, &TTerm) -> Option<TermId>;
 }
 
-impl<'a, TTerm: 'a + Hash + Eq> TermIterator<'a, TTerm> for SharedVocabulary<TTerm> {
-    type TIter = Iter<'a, TTerm, TermId>;
+/// Iterates over a copy of the terms. Other clones of the vocabulary may
+/// add terms meanwhile
+impl<'a, TTerm: 'a + Hash + Eq + Clone> TermIterator<'a, TTerm> for SharedVocabulary<TTerm> {
+    type TIter = vec::IntoIter<(TTerm, TermId)>;
 
     fn iterate_terms(&'a self) -> Self::TIter {
-        self.0.iter()
+        let terms = self.0.read().unwrap();
+        let terms = terms.iter().map(|(term, term_id)| (term.clone(), *term_id));
+        terms.collect::<Vec<_>>().into_iter()
     }
 }
 
 impl<TTerm: Hash + Eq> Vocabulary<TTerm> for SharedVocabulary<TTerm>{
     fn get_or_add(&mut self, term: TTerm) -> TermId {
-        {//Scope of read lock            
-            if let Some(term_id) = self.0.get(&term) {
-                return *term_id;
-            }            
-        }
-        {
-            //between last time checking and write locking, the term could have already been added!
-            if let Some(term_id) = self.0.get(&term) {
+        {//Scope of read lock
+            if let Some(term_id) = self.0.read().unwrap().get(&term) {
                 return *term_id;
             }
-            //It was obivously not added. so we will do this now!
-            let term_id = TermId(self.0.len() as u64);
-            self.0.insert(term, term_id);
-            term_id
         }
+        let mut terms = self.0.write().unwrap();
+        //between last time checking and write locking, the term could have already been added!
+        if let Some(term_id) = terms.get(&term) {
+            return *term_id;
+        }
+        //It was obivously not added. so we will do this now!
+        let term_id = TermId(terms.len() as u64);
+        terms.insert(term, term_id);
+        term_id
     }
 
     fn get(&self, term: &TTerm) -> Option<TermId> {
-        self.0.get(term).cloned()
+        self.0.read().unwrap().get(term).cloned()
     }
 }
 
@@ -67,3 +146,65 @@ impl<TTerm> Vocabulary<TTerm> for HashMap<TTerm, TermId> where TTerm: Hash + Eq{
         self.get(term).cloned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::thread;
+
+    use std::io::Cursor;
+
+    use utils::persistence::Persistent;
+    use super::{SharedVocabulary, Vocabulary, TermId};
+
+    #[test]
+    fn shared_between_threads() {
+        let vocab = SharedVocabulary::new();
+        let threads = (0..4u64)
+            .map(|t| {
+                let mut vocab = vocab.clone();
+                thread::spawn(move || {
+                    (0..1000)
+                        .map(|i| (i * (t + 1)) % 1000)
+                        .map(|term| (term, vocab.get_or_add(term)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut term_ids = BTreeSet::new();
+        for thread in threads {
+            for (term, term_id) in thread.join().unwrap() {
+                assert_eq!(vocab.get(&term), Some(term_id));
+                term_ids.insert(term_id.0);
+            }
+        }
+        assert_eq!(term_ids, (0..1000).collect());
+    }
+
+    #[test]
+    fn merge() {
+        let mut vocab = SharedVocabulary::new();
+        let mut stored = SharedVocabulary::new();
+        for term in 0..10 {
+            stored.get_or_add(term);
+        }
+        vocab.get_or_add(0);
+        vocab.merge(stored).unwrap();
+        assert_eq!(vocab.get(&9), Some(TermId(9)));
+        assert_eq!(vocab.get_or_add(10), TermId(10));
+        // 11 got the id of 10 elsewhere
+        let mut stored = SharedVocabulary::new();
+        for term in (0..10).chain(11..12) {
+            stored.get_or_add(term);
+        }
+        assert!(vocab.merge(stored).is_err());
+        assert_eq!(vocab.get(&11), None);
+    }
+
+    #[test]
+    fn corrupt_length() {
+        let mut bytes = Vec::new();
+        usize::MAX.write_to(&mut bytes).unwrap();
+        assert!(SharedVocabulary::<u32>::read_from(&mut Cursor::new(bytes)).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+//! Batches of changes that are applied to an `Index` as a whole.
+use std::hash::Hash;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use error::Result;
+use page_manager::PageStore;
+use index::Index;
+use index::listing::Listing;
+use index::position_listing::PositionListing;
+use index::doc_store::DocStore;
+use index::posting::DocId;
+use index::vocabulary::TermId;
+
+/// Changes to an `Index` that are committed or rolled back together.
+///
+/// Created by `Index::begin`. Queries see either all documents of a batch
+/// or none of them. While the batch runs, pages it deletes are not freed.
+/// So rolling back, e.g. to retry a batch that failed, restores the index
+/// exactly as it was. Dropping a writer without committing rolls back.
+///
+/// Terms first seen in a rolled back batch stay in the vocabulary.
+pub struct IndexWriter<'a, TTerm: 'a + Hash + Eq, TStore: 'a + PageStore> {
+    index: &'a mut Index<TTerm, TStore>,
+    // Taken on commit or rollback
+    snapshot: Option<Snapshot>,
+}
+
+/// The metadata of an index before the batch
+struct Snapshot {
+    listings: BTreeMap<TermId, Arc<Listing>>,
+    positions: Option<BTreeMap<TermId, Arc<PositionListing>>>,
+    doc_store: DocStore,
+    deleted_docs: BTreeSet<DocId>,
+    last_doc_id: DocId,
+    doc_count: usize,
+}
+
+impl<TTerm, TStore> Index<TTerm, TStore>
+    where TTerm: Hash + Ord,
+          TStore: PageStore
+{
+    /// Commits the index and starts a batch of changes. See `IndexWriter`
+    pub fn begin(&mut self) -> Result<IndexWriter<'_, TTerm, TStore>> {
+        self.commit()?;
+        let snapshot = Snapshot {
+            listings: self.listings.clone(),
+            positions: self.positions.clone(),
+            doc_store: self.doc_store.clone(),
+            deleted_docs: self.deleted_docs.clone(),
+            last_doc_id: self.last_doc_id,
+            doc_count: self.doc_count,
+        };
+        self.page_manager.begin();
+        Ok(IndexWriter {
+            index: self,
+            snapshot: Some(snapshot),
+        })
+    }
+}
+
+impl<'a, TTerm, TStore> IndexWriter<'a, TTerm, TStore>
+    where TTerm: Hash + Ord,
+          TStore: PageStore
+{
+    /// See `Index::index_document`
+    pub fn index_document<TIter>(&mut self,
+                                 document: TIter,
+                                 overwrite_doc_id: Option<DocId>)
+                                 -> Result<DocId>
+        where TIter: Iterator<Item = TTerm>
+    {
+        self.index.index_document(document, overwrite_doc_id)
+    }
+
+    /// See `Index::index_term`
+    pub fn index_term(&mut self, doc_id: DocId, term: TTerm) -> Result<()> {
+        self.index.index_term(doc_id, term)
+    }
+
+    /// See `Index::update_document`
+    pub fn update_document<TIter>(&mut self, doc_id: DocId, document: TIter) -> Result<bool>
+        where TIter: Iterator<Item = TTerm>
+    {
+        self.index.update_document(doc_id, document)
+    }
+
+    /// See `Index::delete_document`
+    pub fn delete_document(&mut self, doc_id: DocId) -> bool {
+        self.index.delete_document(doc_id)
+    }
+
+    /// Makes the batch visible and frees the pages it deleted.
+    /// If storing the listings fails, the batch is rolled back. If only
+    /// freeing fails, the batch is visible but some pages are not reused
+    pub fn commit(mut self) -> Result<()> {
+        self.index.commit_listings()?;
+        // From here on the batch stands
+        self.snapshot = None;
+        let freed = self.index.page_manager.commit_batch();
+        self.index.publish()?;
+        freed
+    }
+
+    /// Discards the batch and frees the pages it stored
+    pub fn rollback(mut self) -> Result<()> {
+        self.restore()
+    }
+}
+
+impl<'a, TTerm, TStore> IndexWriter<'a, TTerm, TStore>
+    where TTerm: Hash + Eq,
+          TStore: PageStore
+{
+    fn restore(&mut self) -> Result<()> {
+        if let Some(snapshot) = self.snapshot.take() {
+            let index = &mut *self.index;
+            index.listings = snapshot.listings;
+            index.positions = snapshot.positions;
+            index.doc_store = snapshot.doc_store;
+            index.deleted_docs = snapshot.deleted_docs;
+            index.last_doc_id = snapshot.last_doc_id;
+            index.doc_count = snapshot.doc_count;
+            index.page_manager.rollback_batch()?;
+            // The snapshot of the index pins the pages freed above. A new one
+            // lets them be reused
+            index.publish()?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a, TTerm, TStore> Drop for IndexWriter<'a, TTerm, TStore>
+    where TTerm: Hash + Eq,
+          TStore: PageStore
+{
+    fn drop(&mut self) {
+        // Errors can not be reported here. Call rollback to see them
+        let _ = self.restore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use test_utils::{create_test_dir, FailingStore};
+
+    use index::Index;
+    use index::posting::{Posting, DocId};
+    use index::vocabulary::SharedVocabulary;
+    use page_manager::{FsPageManager, RamPageCache};
+
+    fn new_index(name: &str) -> Index<u32> {
+        let path = &create_test_dir(format!("index_writer/{}", name).as_str());
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        Index::new(RamPageCache::new(pmgr), SharedVocabulary::new())
+    }
+
+    fn postings(index: &Index<u32>, terms: u32) -> Vec<Vec<Posting>> {
+        (0..terms).map(|t| (index.query_atom(&t).1).collect()).collect()
+    }
+
+    #[test]
+    fn commit() {
+        let mut index = new_index("commit");
+        index.index_document(0..10, None).unwrap();
+        {
+            let mut writer = index.begin().unwrap();
+            for i in 0..100 {
+                writer.index_document(i % 20..i % 20 + 5, None).unwrap();
+            }
+            assert!(writer.delete_document(DocId(0)));
+            writer.commit().unwrap();
+        }
+        assert_eq!(index.doc_count, 100);
+        assert_eq!((index.query_atom(&0).1).count(), 5);
+    }
+
+    #[test]
+    fn rollback() {
+        let mut index = new_index("rollback");
+        for i in 0..2000 {
+            index.index_document((0..3).chain(3 + i % 7..6 + i % 7), Some(DocId(i * 1000)))
+                .unwrap();
+        }
+        index.commit().unwrap();
+        let before = postings(&index, 12);
+        {
+            let mut writer = index.begin().unwrap();
+            // Spills full pages and rewrites listings stored before
+            for i in 2000..3500 {
+                writer.index_document((0..3).chain(i % 12..i % 12 + 3), Some(DocId(i * 1000)))
+                    .unwrap();
+            }
+            for i in 0..100 {
+                writer.update_document(DocId(i * 1000), 9..12).unwrap();
+            }
+            writer.rollback().unwrap();
+        }
+        index.verify().unwrap();
+        assert_eq!(index.doc_count, 2000);
+        assert_eq!(postings(&index, 12), before);
+        // The freed pages are reused without harm
+        for i in 2000..3000 {
+            index.index_document(0..3, Some(DocId(i * 1000))).unwrap();
+        }
+        index.commit().unwrap();
+        assert_eq!(postings(&index, 12)[3..], before[3..]);
+        assert_eq!((index.query_atom(&0).1).count(), 3000);
+    }
+
+    #[test]
+    fn drop_rolls_back() {
+        let mut index = new_index("drop_rolls_back");
+        index.index_document(0..10, None).unwrap();
+        index.commit().unwrap();
+        let before = postings(&index, 20);
+        let failed = {
+            let mut writer = index.begin().unwrap();
+            writer.index_document(5..15, Some(DocId(10))).unwrap();
+            writer.index_document(10..20, Some(DocId(5))).is_err()
+        };
+        assert!(failed);
+        assert_eq!(postings(&index, 20), before);
+        // Retry the batch
+        let mut writer = index.begin().unwrap();
+        writer.index_document(5..15, Some(DocId(10))).unwrap();
+        writer.index_document(10..20, Some(DocId(11))).unwrap();
+        writer.commit().unwrap();
+        assert_eq!((index.query_atom(&12).1).collect::<Vec<_>>(),
+                   vec![Posting(DocId(10), 1), Posting(DocId(11), 1)]);
+    }
+
+    #[test]
+    fn commit_fails() {
+        let (store, writes_left) = FailingStore::new();
+        let mut index = Index::new(RamPageCache::new(store), SharedVocabulary::new());
+        for i in 0..500 {
+            index.index_document((0..3).chain(3 + i % 7..6 + i % 7), Some(DocId(i * 1000)))
+                .unwrap();
+        }
+        index.commit().unwrap();
+        let postings = |index: &Index<u32, FailingStore>| {
+            (0..12).map(|t| (index.query_atom(&t).1).collect::<Vec<_>>()).collect::<Vec<_>>()
+        };
+        let published = |index: &Index<u32, FailingStore>| {
+            let reader = index.reader();
+            (0..12).map(|t| (reader.query_atom(&t).1).collect::<Vec<_>>()).collect::<Vec<_>>()
+        };
+        let before = postings(&index);
+        // Fail at every write the batch does, until it succeeds
+        let mut writes = 0;
+        let mut rolled_back = false;
+        loop {
+            // Pages rewritten while a reader holds them are freed once it is
+            // dropped. That happens during the commit below
+            let reader = index.reader();
+            index.update_document(DocId(1000), (0..3).chain(4..7)).unwrap();
+            index.commit().unwrap();
+            let result = {
+                let mut writer = index.begin().unwrap();
+                writes_left.store(writes, Ordering::SeqCst);
+                let updated = (0..10)
+                    .try_for_each(|i| writer.update_document(DocId(i * 1000), 9..12).map(|_| ()));
+                drop(reader);
+                updated.and_then(|_| writer.commit())
+            };
+            writes_left.store(usize::MAX, Ordering::SeqCst);
+            index.verify().unwrap();
+            // Readers see the state queries on the index see
+            assert_eq!(published(&index), postings(&index));
+            if result.is_ok() {
+                break;
+            }
+            rolled_back |= postings(&index) == before;
+            writes += 1;
+        }
+        assert!(rolled_back);
+        assert_eq!((index.reader().query_atom(&0).1).count(), 490);
+        assert!((index.reader().query_atom(&9).1).any(|p| p == Posting(DocId(0), 1)));
+    }
+}
@@ -7,9 +7,13 @@
 //! Here you will find the basic building blocks on which perlin is build upon!
 #[macro_use]
 pub mod utils;
-mod compressor;
+pub mod error;
+pub mod compressor;
 pub mod page_manager;
 pub mod index;
+pub mod query;
+
+pub use error::{Error, Result};
 
 #[cfg(test)]
 pub mod test_utils;
@@ -1,5 +1,7 @@
 use std::fmt;
+use std::io::{self, Read, Write};
 
+use utils::persistence::Persistent;
 use page_manager::PAGESIZE;
 
 pub const BLOCKSIZE: usize = 64;
@@ -34,6 +36,16 @@ impl BlockId {
     }
 }
 
+impl Persistent for BlockId {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.0.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok(BlockId(u16::read_from(source)?))
+    }
+}
+
 impl Block {
     pub fn empty() -> Self {
         Block([0; BLOCKSIZE])
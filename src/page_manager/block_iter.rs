@@ -1,32 +1,52 @@
 use std::sync::Arc;
 use std::usize;
+use std::fmt;
 
-use page_manager::{Pages, Page, PageId, BlockId, Block, RamPageCache, PageCache, PAGESIZE};
+use error::Error;
+use page_manager::{Pages, Page, PageId, BlockId, Block, PageCache, PAGESIZE};
 
-#[derive(Clone, Debug)]
+/// Iterates over the blocks of some pages, fetching them from a page cache
+///
+/// Stops at a page that cannot be read, e.g. because it is corrupt. See
+/// `error`.
+#[derive(Clone)]
 pub struct BlockIter<'a> {
-    cache: &'a RamPageCache,
+    cache: &'a dyn PageCache,
     pages: Pages,
     current_page: (PageId, Arc<Page>),
     page_index: usize,
-    ptr: usize
+    ptr: usize,
+    error: Option<Error>,
 }
 
 impl<'a> BlockIter<'a> {
-    pub fn new(cache: &'a RamPageCache, pages: Pages) -> Self {
+    pub fn new(cache: &'a dyn PageCache, pages: Pages) -> Self {
         BlockIter {
             cache: cache,
             pages: pages,
             current_page: (PageId::none(), Arc::new(Page::empty())),
             page_index: usize::MAX,
             ptr: 0,
+            error: None,
         }
     }
 
+    /// The error of the page that could not be read, if the iteration
+    /// stopped early
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
     fn get_page(&mut self) -> Option<()> {
         //On what page are we?
         let page_id = self.pages.get(self.calc_page_index())?;
-        let page = self.cache.get_page(page_id);
+        let page = match self.cache.get_page(page_id) {
+            Ok(page) => page,
+            Err(err) => {
+                self.error = Some(err);
+                return None;
+            }
+        };
         self.page_index = self.calc_page_index();
         self.current_page = (page_id, page);
         Some(())
@@ -54,10 +74,22 @@ impl<'a> BlockIter<'a> {
     }
 }
 
+impl<'a> fmt::Debug for BlockIter<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BlockIter")
+            .field("pages", &self.pages)
+            .field("ptr", &self.ptr)
+            .finish()
+    }
+}
+
 impl<'a> Iterator for BlockIter<'a> {
     type Item = Block;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
         if self.calc_page_index() != self.page_index {
             self.get_page()?;
         }
@@ -80,7 +112,7 @@ mod tests {
 
     fn new_cache(name: &str) -> RamPageCache {
         let path = &create_test_dir(format!("block_iter/{}", name).as_str());
-        let pmgr = FsPageManager::new(&path.join("pages.bin"));
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
         RamPageCache::new(pmgr)
     }
 
@@ -93,9 +125,9 @@ mod tests {
             for j in 1..PAGESIZE {
                 cache.store_in_place(PageId(i),
                                      BlockId(j as u16),
-                                     Block([(j % 255) as u8; BLOCKSIZE]));
+                                     Block([(j % 255) as u8; BLOCKSIZE])).unwrap();
             }
-            cache.flush_page(PageId(i));
+            cache.flush_page(PageId(i)).unwrap();
         }
         let pages = Pages((0..2048).map(|i| PageId(i)).collect::<Vec<_>>(), None);
         let mut iter = BlockIter::new(&cache, pages);
@@ -111,7 +143,7 @@ mod tests {
     fn unfull() {
         let mut cache = new_cache("unfull");
         assert_eq!(cache.store_block(Block([1; BLOCKSIZE])), PageId(0));
-        assert_eq!(cache.flush_unfull(PageId(0), BlockId(1)),
+        assert_eq!(cache.flush_unfull(PageId(0), BlockId(1)).unwrap(),
                    UnfullPage::new(PageId(0), BlockId(1), BlockId(2)));
         let mut iter =
             BlockIter::new(&cache,
@@ -132,13 +164,13 @@ mod tests {
             for j in 1..PAGESIZE {
                 cache.store_in_place(PageId(i),
                                      BlockId(j as u16),
-                                     Block([(j % 255) as u8; BLOCKSIZE]));
+                                     Block([(j % 255) as u8; BLOCKSIZE])).unwrap();
             }
-            cache.flush_page(PageId(i));
+            cache.flush_page(PageId(i)).unwrap();
         }
         // Add Unfull page
         assert_eq!(cache.store_block(Block([1; BLOCKSIZE])), PageId(2048));
-        assert_eq!(cache.flush_unfull(PageId(2048), BlockId(1)),
+        assert_eq!(cache.flush_unfull(PageId(2048), BlockId(1)).unwrap(),
                    UnfullPage::new(PageId(2048), BlockId(1), BlockId(2)));
         let mut iter =
             BlockIter::new(&cache,
@@ -163,9 +195,9 @@ mod tests {
         for i in 1..PAGESIZE - 1 {
             cache.store_in_place(PageId(0),
                                  BlockId(i as u16),
-                                 Block([(i % 255) as u8; BLOCKSIZE]));
+                                 Block([(i % 255) as u8; BLOCKSIZE])).unwrap();
         }
-        let unfull_page = cache.flush_unfull(PageId(0), BlockId::last());
+        let unfull_page = cache.flush_unfull(PageId(0), BlockId::last()).unwrap();
         let mut iter = BlockIter::new(&cache, Pages(Vec::new(), Some(unfull_page)));
         for i in 0..PAGESIZE - 1 {
             assert_eq!(iter.next(), Some(Block([(i % 255) as u8; BLOCKSIZE])));
@@ -182,9 +214,9 @@ mod tests {
             for j in 1..PAGESIZE {
                 cache.store_in_place(PageId(i),
                                      BlockId(j as u16),
-                                     Block([(j % 255) as u8; BLOCKSIZE]));
+                                     Block([(j % 255) as u8; BLOCKSIZE])).unwrap();
             }
-            cache.flush_page(PageId(i));
+            cache.flush_page(PageId(i)).unwrap();
         }
         let pages1 = Pages((0..1024).map(|i| PageId(i)).collect::<Vec<_>>(), None);
         let pages2 = Pages((1024..2048).map(|i| PageId(i)).collect::<Vec<_>>(), None);
@@ -211,9 +243,9 @@ mod tests {
             for j in 1..PAGESIZE {
                 cache.store_in_place(PageId(i),
                                      BlockId(j as u16),
-                                     Block([(j % 255) as u8; BLOCKSIZE]));
+                                     Block([(j % 255) as u8; BLOCKSIZE])).unwrap();
             }
-            cache.flush_page(PageId(i));
+            cache.flush_page(PageId(i)).unwrap();
         }
 
         let pages = Pages((0..2048).map(|i| PageId(i)).collect::<Vec<_>>(), None);
@@ -239,18 +271,18 @@ mod tests {
             for j in 1..PAGESIZE {
                 cache.store_in_place(PageId(i),
                                      BlockId(j as u16),
-                                     Block([((j as usize) % 255) as u8; BLOCKSIZE]));
+                                     Block([((j as usize) % 255) as u8; BLOCKSIZE])).unwrap();
             }
-            cache.flush_page(PageId(i));
+            cache.flush_page(PageId(i)).unwrap();
         }
         // Add Unfull page
         assert_eq!(cache.store_block(Block([110; BLOCKSIZE])), PageId(10));
-        cache.store_in_place(PageId(10), BlockId(1), Block([111; BLOCKSIZE]));
-        cache.store_in_place(PageId(10), BlockId(2), Block([112; BLOCKSIZE]));
-        cache.store_in_place(PageId(10), BlockId(3), Block([113; BLOCKSIZE]));
-        cache.store_in_place(PageId(10), BlockId(4), Block([114; BLOCKSIZE]));
-        cache.store_in_place(PageId(10), BlockId(5), Block([115; BLOCKSIZE]));
-        assert_eq!(cache.flush_unfull(PageId(10), BlockId(6)),
+        cache.store_in_place(PageId(10), BlockId(1), Block([111; BLOCKSIZE])).unwrap();
+        cache.store_in_place(PageId(10), BlockId(2), Block([112; BLOCKSIZE])).unwrap();
+        cache.store_in_place(PageId(10), BlockId(3), Block([113; BLOCKSIZE])).unwrap();
+        cache.store_in_place(PageId(10), BlockId(4), Block([114; BLOCKSIZE])).unwrap();
+        cache.store_in_place(PageId(10), BlockId(5), Block([115; BLOCKSIZE])).unwrap();
+        assert_eq!(cache.flush_unfull(PageId(10), BlockId(6)).unwrap(),
                    UnfullPage::new(PageId(10), BlockId(1), BlockId(7)));
         let mut iter =
             BlockIter::new(&cache,
@@ -1,63 +1,168 @@
-use std::path::Path;
-use std::io::{Seek, SeekFrom, Write, Read};
+use std::path::{Path, PathBuf};
+use std::io::{self, Seek, SeekFrom, Write, Read};
 use std::fs::{OpenOptions, File};
+use std::sync::atomic::{AtomicU64, Ordering};
 
+use error::{Error, Result};
 use utils::counter::Counter;
-use page_manager::{UnfullPage, Page, PageId, BlockId, PageStore, PAGESIZE, BLOCKSIZE};
+use utils::persistence::Persistent;
+use page_manager::{UnfullPage, Page, PageId, BlockId, PageStore, StoreStats, PAGESIZE};
+use page_manager::page::STORED_PAGE_BYTES;
+use page_manager::journal::Journal;
 
+/// Stores pages in a file.
+///
+/// Every page is followed by its CRC32C checksum, which is verified when
+/// the page is read. See `CorruptPage`.
+///
+/// Pages referenced by the last written state are saved to a rollback
+/// journal next to the file (`pages.journal` for `pages.bin`) before they
+/// are overwritten. `open` rolls the file back to that state, so a crash
+/// never leaves pages behind that do not match it.
 #[derive(Debug)]
 pub struct FsPageManager {
     pages: File,
+    journal: Journal,
     count: Counter,
     last_page_last_block: BlockId,
     unpopulated_pages: Vec<PageId>,
+    // Statistics. Not persisted
+    pages_read: AtomicU64,
+    pages_written: u64,
+    unfull_rewrites: u64,
 }
 
 impl FsPageManager {
-    pub fn new(path: &Path) -> Self {
-        FsPageManager {
+    /// Creates a new page file at `path`. An existing file is truncated
+    pub fn new(path: &Path) -> Result<Self> {
+        Ok(FsPageManager {
             pages: OpenOptions::new()
                 .read(true)
                 .write(true)
                 .create(true)
                 .truncate(true)
-                .open(path)
-                .unwrap(),
+                .open(path)?,
+            journal: Journal::create(&journal_path(path), 0, 0)?,
             count: Counter::new(),
             last_page_last_block: BlockId(PAGESIZE as u16),
             unpopulated_pages: Vec::new(),
-        }
+            pages_read: AtomicU64::new(0),
+            pages_written: 0,
+            unfull_rewrites: 0,
+        })
+    }
+
+    /// Opens an existing page file without truncating it.
+    /// The state of the page manager (page count, free pages, ...) is read
+    /// from `state`. It has to be written by `write_state` beforehand.
+    ///
+    /// Pages overwritten since `state` was written are restored from the
+    /// journal.
+    pub fn open<R: Read>(path: &Path, state: &mut R) -> Result<Self> {
+        let pages = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)?;
+        let count = Counter::read_from(state)?;
+        let last_page_last_block = BlockId::read_from(state)?;
+        let unpopulated_pages = Vec::read_from(state)?;
+        let generation = u64::read_from(state)?;
+        let journal = Journal::recover(&journal_path(path), &pages, generation, count.retrieve())?;
+        Ok(FsPageManager {
+            pages,
+            journal,
+            count,
+            last_page_last_block,
+            unpopulated_pages,
+            pages_read: AtomicU64::new(0),
+            pages_written: 0,
+            unfull_rewrites: 0,
+        })
+    }
+
+    /// Writes the state needed to reopen this page manager.
+    /// Call `checkpoint` once it is safely stored
+    pub fn write_state<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.count.write_to(target)?;
+        self.last_page_last_block.write_to(target)?;
+        self.unpopulated_pages.write_to(target)?;
+        // The generation of the state being written
+        (self.journal.generation() + 1).write_to(target)
+    }
+
+    /// Makes the state last written by `write_state` the one `open` rolls
+    /// back to. No page may be stored in between
+    pub fn checkpoint(&mut self) -> Result<()> {
+        let generation = self.journal.generation() + 1;
+        Ok(self.journal.checkpoint(generation, self.count.retrieve())?)
+    }
+
+    /// Flushes all written pages to disk
+    pub fn sync(&self) -> Result<()> {
+        Ok(self.pages.sync_all()?)
     }
 
     //TODO: Think about solving this with write_at in https://doc.rust-lang.org/std/os/unix/fs/trait.FileExt.html
-    fn write_page(&mut self, page: Page, page_id: PageId) {
-        let id = page_id.0;
-        let mut f = self.pages.try_clone().unwrap();
-        f.seek(SeekFrom::Start(id * PAGESIZE as u64 * BLOCKSIZE as u64)).unwrap();
-        f.write_all(page.as_slice()).unwrap();
-        self.last_page_last_block = BlockId(PAGESIZE as u16);
+    fn write_page(&mut self, page: Page, page_id: PageId) -> Result<()> {
+        self.journal.save(&self.pages, page_id)?;
+        let mut f = self.pages.try_clone()?;
+        f.seek(SeekFrom::Start(page_id.0 * STORED_PAGE_BYTES as u64))?;
+        page.write_checked(&mut f)?;
+        self.pages_written += 1;
+        Ok(())
+    }
+}
+
+fn journal_path(path: &Path) -> PathBuf {
+    path.with_extension("journal")
+}
+
+// Readers share the file with other threads, so they must not move its cursor
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset)? {
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "page cut off")),
+            n => {
+                buf = &mut { buf }[n..];
+                offset += n as u64;
+            }
+        }
     }
+    Ok(())
 }
 
 impl PageStore for FsPageManager {
-    fn store_full(&mut self, page: Page) -> PageId {
-        let id = self.unpopulated_pages.pop().unwrap_or_else(|| PageId(self.count.retrieve_and_inc()));
-        self.write_page(page, id);
-        id
+    fn store_full(&mut self, page: Page) -> Result<PageId> {
+        let id = match self.unpopulated_pages.last() {
+            Some(&id) => id,
+            None => PageId(self.count.retrieve()),
+        };
+        self.write_page(page, id)?;
+        // Only hand out the id once the page is written
+        if self.unpopulated_pages.pop().is_none() {
+            self.count.retrieve_and_inc();
+        }
+        self.last_page_last_block = BlockId(PAGESIZE as u16);
+        Ok(id)
     }
 
-    fn store_unfull(&mut self, page: Page, block_id: BlockId) -> UnfullPage {
-        let (mut container_page, page_id) = if self.last_page_last_block.0 + block_id.0 >
-                                               PAGESIZE as u16 {
-            // New Page
-            self.last_page_last_block = BlockId(1);
-            (Page::empty(), PageId(self.count.retrieve_and_inc()))
+    fn store_unfull(&mut self, page: Page, block_id: BlockId) -> Result<UnfullPage> {
+        let new_page = self.last_page_last_block.0 + block_id.0 > PAGESIZE as u16;
+        let (mut container_page, page_id, first_block) = if new_page {
+            (Page::empty(), PageId(self.count.retrieve()), BlockId(1))
         } else {
             // Fits on same page
             let page_id = PageId(self.count.retrieve() - 1);
-            (self.get_page(page_id), page_id)
+            (self.get_page(page_id)?, page_id, self.last_page_last_block)
         };
-        let first_block = self.last_page_last_block;
         // First byte of an unfull page acts as reference counter.
         container_page[BlockId::first()].0[0] += 1;
         // Now copy the full blocks over to the unfull page
@@ -65,45 +170,65 @@ impl PageStore for FsPageManager {
             container_page[BlockId(first_block.0 + i)] = page[BlockId(i)];
         }
         // Write the new page
-        self.write_page(container_page, page_id);
+        self.write_page(container_page, page_id)?;
+        if new_page {
+            self.count.retrieve_and_inc();
+        } else {
+            self.unfull_rewrites += 1;
+        }
         // And set the last_page_last_block
         self.last_page_last_block = BlockId(first_block.0 + block_id.0);
-        UnfullPage::new(page_id, first_block, self.last_page_last_block)
+        Ok(UnfullPage::new(page_id, first_block, self.last_page_last_block))
     }
 
     #[inline]
-    fn delete_page(&mut self, page_id: PageId) {
+    fn delete_page(&mut self, page_id: PageId) -> Result<()> {
         self.unpopulated_pages.push(page_id);
+        Ok(())
     }
 
     ///This method deletes an unfull page.
     ///Actually it just decreases the refcount of that page
     ///In case it becomes zero, the page is added to the unpopulated pages
-    ///Otherwise the new refcount is written
-    fn delete_unfull(&mut self, page_id: PageId) {
-        let mut refcount: [u8; 1] = [0; 1];
-        let mut f = self.pages.try_clone().unwrap();
-        //Seek start of page
-        f.seek(SeekFrom::Start(page_id.0 * PAGESIZE as u64 * BLOCKSIZE as u64)).unwrap();
-        //Read one byte...
-        f.read_exact(&mut refcount).unwrap();
-        //Decrease it
-        refcount[0] -= 1;
+    ///Otherwise the page is written with the new refcount
+    fn delete_unfull(&mut self, page_id: PageId) -> Result<()> {
+        let mut page = self.get_page(page_id)?;
+        //Decrease the refcount
+        page[BlockId::first()].0[0] -= 1;
         //If its zero it means no more relevant data is on that page
         //Throw it into the unpopulated pages
-        if refcount[0] == 0 {
+        if page[BlockId::first()].0[0] == 0 {
             self.unpopulated_pages.push(page_id);
+            // If it was the page unfull pages are currently appended to,
+            // stop doing so. It might be handed out as a full page next.
+            if page_id.0 + 1 == self.count.retrieve() {
+                self.last_page_last_block = BlockId(PAGESIZE as u16);
+            }
         } else {
-            //Otherwise we have to write the refcount back to page... alas
-            f.seek(SeekFrom::Start(page_id.0 * PAGESIZE as u64 * BLOCKSIZE as u64)).unwrap();
-            f.write_all(&refcount).unwrap();
+            //Otherwise we have to write the page again, as its checksum changed
+            self.write_page(page, page_id)?;
+            self.unfull_rewrites += 1;
         }
+        Ok(())
     }
 
-    fn get_page(&self, page_id: PageId) -> Page {
-        let mut f = self.pages.try_clone().unwrap();
-        f.seek(SeekFrom::Start(page_id.0 * PAGESIZE as u64 * BLOCKSIZE as u64)).unwrap();
-        Page::from_read(&mut f)
+    fn get_page(&self, page_id: PageId) -> Result<Page> {
+        if page_id.0 >= self.count.retrieve() {
+            return Err(Error::UnknownPage(page_id));
+        }
+        let mut raw = [0; STORED_PAGE_BYTES];
+        read_exact_at(&self.pages, &mut raw, page_id.0 * STORED_PAGE_BYTES as u64)?;
+        self.pages_read.fetch_add(1, Ordering::Relaxed);
+        Page::read_checked(&mut &raw[..], page_id)
+    }
+
+    fn stats(&self) -> StoreStats {
+        StoreStats {
+            pages_read: self.pages_read.load(Ordering::Relaxed),
+            pages_written: self.pages_written,
+            unfull_rewrites: self.unfull_rewrites,
+            bytes_on_disk: self.count.retrieve() * STORED_PAGE_BYTES as u64,
+        }
     }
 }
 
@@ -111,22 +236,28 @@ impl PageStore for FsPageManager {
 mod tests {
     use test_utils::create_test_dir;
 
+    use std::fs::OpenOptions;
+    use std::io::{Seek, SeekFrom, Write};
+
     use super::FsPageManager;
-    use page_manager::{UnfullPage, Page, PageStore, PageId, Block, BlockId, BLOCKSIZE, PAGESIZE};
+    use error::Error;
+    use page_manager::{UnfullPage, Page, PageStore, PageId, Block, BlockId, StoreStats,
+                       BLOCKSIZE, PAGESIZE};
+    use page_manager::page::STORED_PAGE_BYTES;
 
     fn new_pmgr(name: &str) -> FsPageManager {
         let path = &create_test_dir(format!("fs_page_manager/{}", name).as_str());
-        FsPageManager::new(&path.join("pages.bin"))
+        FsPageManager::new(&path.join("pages.bin")).unwrap()
     }
 
     #[test]
     fn delete_unfull_basic() {
         let mut pmgr = new_pmgr("delete_unfull_basic");
-        assert_eq!(pmgr.store_unfull(Page::empty(), BlockId(1)),
+        assert_eq!(pmgr.store_unfull(Page::empty(), BlockId(1)).unwrap(),
                    UnfullPage::new(PageId(0), BlockId(1), BlockId(2)));
-        pmgr.delete_unfull(PageId(0));
+        pmgr.delete_unfull(PageId(0)).unwrap();
         assert_eq!(pmgr.unpopulated_pages, vec![PageId(0)]);
-        assert_eq!(pmgr.store_full(Page::empty()), PageId(0));
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(0));
     }
 
     #[test]
@@ -137,30 +268,30 @@ mod tests {
             ref_p[BlockId(i as u16 + 1u16)] = Block([(i % 255) as u8; BLOCKSIZE]);
             let mut p = Page::empty();
             p[BlockId::first()] = Block([(i % 255) as u8; BLOCKSIZE]);
-            assert_eq!(pmgr.store_unfull(p, BlockId(1)),
+            assert_eq!(pmgr.store_unfull(p, BlockId(1)).unwrap(),
                        UnfullPage::new(PageId(0), BlockId(i as u16 + 1), BlockId(i as u16 + 2)));
         }
         ref_p[BlockId::first()].0[0] = (PAGESIZE - 1) as u8;
-        assert_eq!(pmgr.get_page(PageId(0)), ref_p);
-        pmgr.delete_unfull(PageId(0));
+        assert_eq!(pmgr.get_page(PageId(0)).unwrap(), ref_p);
+        pmgr.delete_unfull(PageId(0)).unwrap();
         assert_eq!(pmgr.unpopulated_pages, vec![]);
-        assert_eq!(pmgr.store_full(Page::empty()), PageId(1));
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(1));
         for _ in 0..PAGESIZE -2 {
             assert_eq!(pmgr.unpopulated_pages, vec![]);
-            pmgr.delete_unfull(PageId(0));
+            pmgr.delete_unfull(PageId(0)).unwrap();
         }
         assert_eq!(pmgr.unpopulated_pages, vec![PageId(0)]);
-        assert_eq!(pmgr.store_full(Page::empty()), PageId(0));
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(0));
     }
     
     #[test]
     fn basic_unfull() {
         let mut pmgr = new_pmgr("basic_unfull");
-        assert_eq!(pmgr.store_unfull(Page::empty(), BlockId(1)),
+        assert_eq!(pmgr.store_unfull(Page::empty(), BlockId(1)).unwrap(),
                    UnfullPage::new(PageId(0), BlockId(1), BlockId(2)));
         let mut p = Page::empty();
         p[BlockId::first()].0[0] = 1;
-        assert_eq!(pmgr.get_page(PageId(0)), p);
+        assert_eq!(pmgr.get_page(PageId(0)).unwrap(), p);
     }
 
     #[test]
@@ -173,9 +304,9 @@ mod tests {
             ref_p[BlockId(i as u16 + 1u16)] = Block([(i % 255) as u8; BLOCKSIZE]);
         }
         ref_p[BlockId::first()].0[0] = 1;
-        assert_eq!(pmgr.store_unfull(p, BlockId::last()),
+        assert_eq!(pmgr.store_unfull(p, BlockId::last()).unwrap(),
                    UnfullPage::new(PageId(0), BlockId(1), BlockId(PAGESIZE as u16)));
-        assert_eq!(pmgr.get_page(PageId(0)), ref_p);
+        assert_eq!(pmgr.get_page(PageId(0)).unwrap(), ref_p);
     }
 
     #[test]
@@ -186,11 +317,11 @@ mod tests {
             ref_p[BlockId(i as u16 + 1u16)] = Block([(i % 255) as u8; BLOCKSIZE]);
             let mut p = Page::empty();
             p[BlockId::first()] = Block([(i % 255) as u8; BLOCKSIZE]);
-            assert_eq!(pmgr.store_unfull(p, BlockId(1)),
+            assert_eq!(pmgr.store_unfull(p, BlockId(1)).unwrap(),
                        UnfullPage::new(PageId(0), BlockId(i as u16 + 1), BlockId(i as u16 + 2)));
         }
         ref_p[BlockId::first()].0[0] = (PAGESIZE - 1) as u8;
-        assert_eq!(pmgr.get_page(PageId(0)), ref_p);
+        assert_eq!(pmgr.get_page(PageId(0)).unwrap(), ref_p);
     }
 
     #[test]
@@ -201,18 +332,18 @@ mod tests {
             ref_p[BlockId(i as u16 + 1u16)] = Block([(i % 255) as u8; BLOCKSIZE]);
             let mut p = Page::empty();
             p[BlockId::first()] = Block([(i % 255) as u8; BLOCKSIZE]);
-            assert_eq!(pmgr.store_unfull(p, BlockId(1)),
+            assert_eq!(pmgr.store_unfull(p, BlockId(1)).unwrap(),
                        UnfullPage::new(PageId(0), BlockId(i as u16 + 1), BlockId(i as u16 + 2)));
         }
         for i in 0..PAGESIZE - 1 {
             let mut p = Page::empty();
             p[BlockId::first()] = Block([(i % 255) as u8; BLOCKSIZE]);
-            assert_eq!(pmgr.store_unfull(p, BlockId(1)),
+            assert_eq!(pmgr.store_unfull(p, BlockId(1)).unwrap(),
                        UnfullPage::new(PageId(1), BlockId(i as u16 + 1), BlockId(i as u16 + 2)));
         }
         ref_p[BlockId::first()].0[0] = (PAGESIZE - 1) as u8;
-        assert_eq!(pmgr.get_page(PageId(0)), ref_p);
-        assert_eq!(pmgr.get_page(PageId(1)), ref_p);
+        assert_eq!(pmgr.get_page(PageId(0)).unwrap(), ref_p);
+        assert_eq!(pmgr.get_page(PageId(1)).unwrap(), ref_p);
     }
 
     #[test]
@@ -220,26 +351,101 @@ mod tests {
         let mut pmgr = new_pmgr("unfull_after_full");
         let mut p = Page::empty();
         p[BlockId::first()] = Block([1; BLOCKSIZE]);
-        assert_eq!(pmgr.store_full(p), PageId(0));
-        assert_eq!(pmgr.get_page(PageId(0)), p);
-        assert_eq!(pmgr.store_unfull(Page::empty(), BlockId(1)),
+        assert_eq!(pmgr.store_full(p).unwrap(), PageId(0));
+        assert_eq!(pmgr.get_page(PageId(0)).unwrap(), p);
+        assert_eq!(pmgr.store_unfull(Page::empty(), BlockId(1)).unwrap(),
                    UnfullPage::new(PageId(1), BlockId(1), BlockId(2)));
         let mut unf_p = Page::empty();
         unf_p[BlockId::first()].0[0] = 1;
-        assert_eq!(pmgr.get_page(PageId(1)), unf_p);
-        assert_eq!(pmgr.store_full(p), PageId(2));
-        assert_eq!(pmgr.get_page(PageId(2)), p);
-        assert_eq!(pmgr.store_unfull(Page::empty(), BlockId(1)),
+        assert_eq!(pmgr.get_page(PageId(1)).unwrap(), unf_p);
+        assert_eq!(pmgr.store_full(p).unwrap(), PageId(2));
+        assert_eq!(pmgr.get_page(PageId(2)).unwrap(), p);
+        assert_eq!(pmgr.store_unfull(Page::empty(), BlockId(1)).unwrap(),
                    UnfullPage::new(PageId(3), BlockId(1), BlockId(2)));
-        assert_eq!(pmgr.get_page(PageId(1)), unf_p);
+        assert_eq!(pmgr.get_page(PageId(1)).unwrap(), unf_p);
     }
 
 
+    #[test]
+    fn delete_last_unfull() {
+        let mut pmgr = new_pmgr("delete_last_unfull");
+        assert_eq!(pmgr.store_unfull(Page::empty(), BlockId(1)).unwrap(),
+                   UnfullPage::new(PageId(0), BlockId(1), BlockId(2)));
+        pmgr.delete_unfull(PageId(0)).unwrap();
+        // The deleted page must not be used for unfull pages anymore
+        assert_eq!(pmgr.store_unfull(Page::empty(), BlockId(1)).unwrap(),
+                   UnfullPage::new(PageId(1), BlockId(1), BlockId(2)));
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(0));
+    }
+
+    #[test]
+    fn reopen() {
+        let path = &create_test_dir("fs_page_manager/reopen");
+        let mut state = Vec::new();
+        let mut p = Page::empty();
+        p[BlockId::first()] = Block([1; BLOCKSIZE]);
+        {
+            let mut pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+            assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(0));
+            assert_eq!(pmgr.store_full(p).unwrap(), PageId(1));
+            assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(2));
+            assert_eq!(pmgr.store_unfull(Page::empty(), BlockId(1)).unwrap(),
+                       UnfullPage::new(PageId(3), BlockId(1), BlockId(2)));
+            pmgr.delete_page(PageId(0)).unwrap();
+            pmgr.sync().unwrap();
+            pmgr.write_state(&mut state).unwrap();
+        }
+        let mut pmgr = FsPageManager::open(&path.join("pages.bin"), &mut &state[..]).unwrap();
+        assert_eq!(pmgr.get_page(PageId(1)).unwrap(), p);
+        assert_eq!(pmgr.store_unfull(Page::empty(), BlockId(1)).unwrap(),
+                   UnfullPage::new(PageId(3), BlockId(2), BlockId(3)));
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(0));
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(4));
+    }
+
+    #[test]
+    fn rollback() {
+        let path = &create_test_dir("fs_page_manager/rollback");
+        let mut state = Vec::new();
+        let mut p = Page::empty();
+        p[BlockId::first()] = Block([1; BLOCKSIZE]);
+        let unfull;
+        {
+            let mut pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+            assert_eq!(pmgr.store_full(p).unwrap(), PageId(0));
+            assert_eq!(pmgr.store_unfull(p, BlockId(1)).unwrap(),
+                       UnfullPage::new(PageId(1), BlockId(1), BlockId(2)));
+            unfull = pmgr.get_page(PageId(1)).unwrap();
+            pmgr.sync().unwrap();
+            pmgr.write_state(&mut state).unwrap();
+            pmgr.checkpoint().unwrap();
+            // Overwrite both pages of the state and add another one
+            pmgr.delete_page(PageId(0)).unwrap();
+            assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(0));
+            assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(2));
+            pmgr.store_unfull(Page::empty(), BlockId(1)).unwrap();
+            pmgr.delete_unfull(PageId(1)).unwrap();
+            // Crash without writing the state
+        }
+        let mut pmgr = FsPageManager::open(&path.join("pages.bin"), &mut &state[..]).unwrap();
+        assert_eq!(pmgr.get_page(PageId(0)).unwrap(), p);
+        assert_eq!(pmgr.get_page(PageId(1)).unwrap(), unfull);
+        assert_eq!(pmgr.pages.metadata().unwrap().len(), 2 * STORED_PAGE_BYTES as u64);
+        // Once a newer state is written, the journal of the old one is discarded
+        pmgr.delete_page(PageId(0)).unwrap();
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(0));
+        state.clear();
+        pmgr.write_state(&mut state).unwrap();
+        drop(pmgr);
+        let pmgr = FsPageManager::open(&path.join("pages.bin"), &mut &state[..]).unwrap();
+        assert_eq!(pmgr.get_page(PageId(0)).unwrap(), Page::empty());
+    }
+
     #[test]
     fn store_page() {
         let path = &create_test_dir("fs_page_manager/store_page");
-        let mut pmgr = FsPageManager::new(&path.join("pages.bin"));
-        assert_eq!(pmgr.store_full(Page::empty()), PageId(0));
+        let mut pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(0));
     }
 
 
@@ -247,43 +453,93 @@ mod tests {
     #[test]
     fn delete_page() {
         let path = &create_test_dir("fs_page_manager/delete_page");
-        let mut pmgr = FsPageManager::new(&path.join("pages.bin"));
-        assert_eq!(pmgr.store_full(Page::empty()), PageId(0));
-        assert_eq!(pmgr.store_full(Page::empty()), PageId(1));
-        assert_eq!(pmgr.store_full(Page::empty()), PageId(2));
-        assert_eq!(pmgr.store_full(Page::empty()), PageId(3));
-        pmgr.delete_page(PageId(1));
-        assert_eq!(pmgr.store_full(Page::empty()), PageId(1));
+        let mut pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(0));
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(1));
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(2));
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(3));
+        pmgr.delete_page(PageId(1)).unwrap();
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(1));
     }
 
     #[test]
     fn get_page() {
         let path = &create_test_dir("fs_page_manager/get_page");
-        let mut pmgr = FsPageManager::new(&path.join("pages.bin"));
-        assert_eq!(pmgr.store_full(Page::empty()), PageId(0));
-        assert_eq!(pmgr.get_page(PageId(0)), Page::empty());
+        let mut pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(0));
+        assert_eq!(pmgr.get_page(PageId(0)).unwrap(), Page::empty());
         let mut p = Page::empty();
         p[BlockId::first()] = Block([1; BLOCKSIZE]);
-        assert_eq!(pmgr.store_full(p), PageId(1));
-        assert_eq!(pmgr.get_page(PageId(1)), p);
+        assert_eq!(pmgr.store_full(p).unwrap(), PageId(1));
+        assert_eq!(pmgr.get_page(PageId(1)).unwrap(), p);
     }
 
     #[test]
     fn combined() {
         let path = &create_test_dir("fs_page_manager/combined");
-        let mut pmgr = FsPageManager::new(&path.join("pages.bin"));
+        let mut pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
         let mut p = Page::empty();
         p[BlockId::first()] = Block([1; BLOCKSIZE]);
         p[BlockId(1)] = Block([2; BLOCKSIZE]);
-        assert_eq!(pmgr.store_full(Page::empty()), PageId(0));
-        assert_eq!(pmgr.store_full(p), PageId(1));
-        assert_eq!(pmgr.store_full(p), PageId(2));
+        assert_eq!(pmgr.store_full(Page::empty()).unwrap(), PageId(0));
+        assert_eq!(pmgr.store_full(p).unwrap(), PageId(1));
+        assert_eq!(pmgr.store_full(p).unwrap(), PageId(2));
         p[BlockId(2)] = Block([3; BLOCKSIZE]);
-        assert_eq!(pmgr.store_full(p), PageId(3));
-        assert_eq!(pmgr.get_page(PageId(3)), p);
-        assert!(pmgr.get_page(PageId(1)) != p);
-        pmgr.delete_page(PageId(1));
-        assert_eq!(pmgr.store_full(p), PageId(1));
-        assert_eq!(pmgr.get_page(PageId(1)), p);
+        assert_eq!(pmgr.store_full(p).unwrap(), PageId(3));
+        assert_eq!(pmgr.get_page(PageId(3)).unwrap(), p);
+        assert!(pmgr.get_page(PageId(1)).unwrap() != p);
+        pmgr.delete_page(PageId(1)).unwrap();
+        assert_eq!(pmgr.store_full(p).unwrap(), PageId(1));
+        assert_eq!(pmgr.get_page(PageId(1)).unwrap(), p);
+    }
+
+    #[test]
+    fn stats() {
+        let mut pmgr = new_pmgr("stats");
+        assert_eq!(pmgr.stats(), StoreStats::default());
+        pmgr.store_full(Page::empty()).unwrap();
+        pmgr.store_unfull(Page::empty(), BlockId(1)).unwrap();
+        pmgr.store_unfull(Page::empty(), BlockId(1)).unwrap();
+        pmgr.delete_unfull(PageId(1)).unwrap();
+        pmgr.get_page(PageId(0)).unwrap();
+        assert_eq!(pmgr.stats(),
+                   StoreStats {
+                       // Adding and releasing unfull pages reads their
+                       // container page
+                       pages_read: 3,
+                       pages_written: 4,
+                       unfull_rewrites: 2,
+                       bytes_on_disk: 2 * STORED_PAGE_BYTES as u64,
+                   });
+    }
+
+    #[test]
+    fn corrupt_page() {
+        let path = &create_test_dir("fs_page_manager/corrupt_page");
+        let mut pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        let mut p = Page::empty();
+        p[BlockId(3)] = Block([3; BLOCKSIZE]);
+        pmgr.store_full(p).unwrap();
+        pmgr.store_full(p).unwrap();
+        pmgr.sync().unwrap();
+        // Flip a bit of the second page, as a torn write would
+        {
+            let mut f = OpenOptions::new().write(true).open(path.join("pages.bin")).unwrap();
+            f.seek(SeekFrom::Start(STORED_PAGE_BYTES as u64 + 3 * BLOCKSIZE as u64)).unwrap();
+            f.write_all(&[2]).unwrap();
+        }
+        assert_eq!(pmgr.get_page(PageId(0)).unwrap(), p);
+        match pmgr.get_page(PageId(1)) {
+            Err(Error::Corruption(corrupt)) => {
+                assert_eq!(corrupt.page_id, PageId(1));
+                assert_eq!(corrupt.stored, p.checksum());
+                assert!(corrupt.to_string().starts_with("Page 1 is corrupt"));
+            }
+            other => panic!("Expected corruption, got {:?}", other),
+        }
+        match pmgr.get_page(PageId(2)) {
+            Err(Error::UnknownPage(PageId(2))) => {}
+            other => panic!("Expected unknown page, got {:?}", other),
+        }
     }
 }
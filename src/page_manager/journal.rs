@@ -0,0 +1,122 @@
+//! Rollback journal of `FsPageManager`.
+//!
+//! The manifest of a persisted index references pages that must survive
+//! until the next manifest is in place. Before such a page is overwritten,
+//! its stored bytes are appended to the journal and synced. Opening the
+//! index after a crash copies them back, which restores the page file to
+//! the state the manifest describes.
+//!
+//! The journal starts with the generation of the manifest it belongs to.
+//! Every persisted manifest gets a new generation, so a journal left behind
+//! by an older one is recognized and discarded.
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use utils::persistence::Persistent;
+use page_manager::PageId;
+use page_manager::page::STORED_PAGE_BYTES;
+
+#[derive(Debug)]
+pub struct Journal {
+    file: File,
+    generation: u64,
+    /// Number of pages at the last checkpoint. Pages after those are not
+    /// referenced by the manifest and need no saving
+    durable_pages: u64,
+    saved: BTreeSet<PageId>,
+}
+
+impl Journal {
+    /// Creates an empty journal at `path`. An existing one is truncated
+    pub fn create(path: &Path, generation: u64, durable_pages: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut journal = Journal {
+            file,
+            generation,
+            durable_pages,
+            saved: BTreeSet::new(),
+        };
+        journal.checkpoint(generation, durable_pages)?;
+        Ok(journal)
+    }
+
+    /// Opens the journal at `path` for the manifest of `generation`.
+    ///
+    /// If the journal belongs to that manifest, the pages saved in it are
+    /// written back to `pages`, and pages created after the manifest are
+    /// cut off. A missing or outdated journal is discarded.
+    pub fn recover(path: &Path,
+                   pages: &File,
+                   generation: u64,
+                   durable_pages: u64)
+                   -> io::Result<Self> {
+        if let Ok(file) = File::open(path) {
+            let mut source = BufReader::new(file);
+            if u64::read_from(&mut source).ok() == Some(generation) {
+                let mut raw = [0; STORED_PAGE_BYTES];
+                // A torn last entry was never followed by a page write
+                while let Ok(page_id) = PageId::read_from(&mut source) {
+                    if source.read_exact(&mut raw).is_err() {
+                        break;
+                    }
+                    write_raw(pages, page_id, &raw)?;
+                }
+                pages.set_len(durable_pages * STORED_PAGE_BYTES as u64)?;
+                pages.sync_all()?;
+            }
+        }
+        Journal::create(path, generation, durable_pages)
+    }
+
+    /// Generation of the manifest this journal belongs to
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Saves the stored bytes of `page_id` before it is overwritten.
+    /// Does nothing if the page is not referenced by the manifest or is
+    /// already saved
+    pub fn save(&mut self, pages: &File, page_id: PageId) -> io::Result<()> {
+        if page_id.0 >= self.durable_pages || self.saved.contains(&page_id) {
+            return Ok(());
+        }
+        let mut raw = [0; STORED_PAGE_BYTES];
+        let mut source = pages.try_clone()?;
+        source.seek(SeekFrom::Start(page_id.0 * STORED_PAGE_BYTES as u64))?;
+        source.read_exact(&mut raw)?;
+        let mut entry = Vec::with_capacity(8 + STORED_PAGE_BYTES);
+        page_id.write_to(&mut entry)?;
+        entry.extend_from_slice(&raw);
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&entry)?;
+        // The page may only be overwritten once its copy is on disk
+        self.file.sync_data()?;
+        self.saved.insert(page_id);
+        Ok(())
+    }
+
+    /// Starts a new journal once the manifest of `generation` is in place
+    pub fn checkpoint(&mut self, generation: u64, durable_pages: u64) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        generation.write_to(&mut self.file)?;
+        self.file.sync_all()?;
+        self.generation = generation;
+        self.durable_pages = durable_pages;
+        self.saved.clear();
+        Ok(())
+    }
+}
+
+fn write_raw(pages: &File, page_id: PageId, raw: &[u8]) -> io::Result<()> {
+    let mut target = pages.try_clone()?;
+    target.seek(SeekFrom::Start(page_id.0 * STORED_PAGE_BYTES as u64))?;
+    target.write_all(raw)
+}
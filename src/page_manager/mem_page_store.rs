@@ -0,0 +1,142 @@
+use error::{Error, Result};
+use page_manager::{UnfullPage, Page, PageId, BlockId, PageStore, PAGESIZE};
+
+/// Keeps pages in memory. For indices that are thrown away after use.
+///
+/// Same semantics as `FsPageManager`: Deleted pages are reused and unfull
+/// pages are shared and reference counted in their first byte.
+#[derive(Debug)]
+pub struct MemPageStore {
+    pages: Vec<Page>,
+    last_page_last_block: BlockId,
+    unpopulated_pages: Vec<PageId>,
+}
+
+impl MemPageStore {
+    pub fn new() -> Self {
+        MemPageStore {
+            pages: Vec::new(),
+            last_page_last_block: BlockId(PAGESIZE as u16),
+            unpopulated_pages: Vec::new(),
+        }
+    }
+}
+
+impl Default for MemPageStore {
+    fn default() -> Self {
+        MemPageStore::new()
+    }
+}
+
+impl PageStore for MemPageStore {
+    fn store_full(&mut self, page: Page) -> Result<PageId> {
+        self.last_page_last_block = BlockId(PAGESIZE as u16);
+        if let Some(page_id) = self.unpopulated_pages.pop() {
+            self.pages[page_id.0 as usize] = page;
+            return Ok(page_id);
+        }
+        self.pages.push(page);
+        Ok(PageId(self.pages.len() as u64 - 1))
+    }
+
+    fn store_unfull(&mut self, page: Page, block_id: BlockId) -> Result<UnfullPage> {
+        if self.last_page_last_block.0 + block_id.0 > PAGESIZE as u16 {
+            // New Page
+            self.last_page_last_block = BlockId(1);
+            self.pages.push(Page::empty());
+        }
+        let page_id = PageId(self.pages.len() as u64 - 1);
+        let first_block = self.last_page_last_block;
+        let container_page = &mut self.pages[page_id.0 as usize];
+        // First byte of an unfull page acts as reference counter.
+        container_page[BlockId::first()].0[0] += 1;
+        for i in 0..block_id.0 {
+            container_page[BlockId(first_block.0 + i)] = page[BlockId(i)];
+        }
+        self.last_page_last_block = BlockId(first_block.0 + block_id.0);
+        Ok(UnfullPage::new(page_id, first_block, self.last_page_last_block))
+    }
+
+    #[inline]
+    fn delete_page(&mut self, page_id: PageId) -> Result<()> {
+        self.unpopulated_pages.push(page_id);
+        Ok(())
+    }
+
+    /// Decreases the reference count of an unfull page.
+    /// Once it is zero, the page is free to be used again
+    fn delete_unfull(&mut self, page_id: PageId) -> Result<()> {
+        let refcount = match self.pages.get_mut(page_id.0 as usize) {
+            Some(page) => &mut page[BlockId::first()].0[0],
+            None => return Err(Error::UnknownPage(page_id)),
+        };
+        *refcount -= 1;
+        if *refcount == 0 {
+            self.unpopulated_pages.push(page_id);
+            // If it was the page unfull pages are currently appended to,
+            // stop doing so. It might be handed out as a full page next.
+            if page_id.0 + 1 == self.pages.len() as u64 {
+                self.last_page_last_block = BlockId(PAGESIZE as u16);
+            }
+        }
+        Ok(())
+    }
+
+    fn get_page(&self, page_id: PageId) -> Result<Page> {
+        self.pages.get(page_id.0 as usize).cloned().ok_or(Error::UnknownPage(page_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MemPageStore;
+    use page_manager::{UnfullPage, Page, PageStore, PageId, Block, BlockId, RamPageCache,
+                       BlockManager, PageCache, BLOCKSIZE, PAGESIZE};
+
+    #[test]
+    fn store_and_delete() {
+        let mut store = MemPageStore::new();
+        let mut p = Page::empty();
+        p[BlockId::first()] = Block([1; BLOCKSIZE]);
+        assert_eq!(store.store_full(Page::empty()).unwrap(), PageId(0));
+        assert_eq!(store.store_full(p).unwrap(), PageId(1));
+        assert_eq!(store.get_page(PageId(1)).unwrap(), p);
+        store.delete_page(PageId(0)).unwrap();
+        assert_eq!(store.store_full(p).unwrap(), PageId(0));
+        assert_eq!(store.get_page(PageId(0)).unwrap(), p);
+    }
+
+    #[test]
+    fn unfull() {
+        let mut store = MemPageStore::new();
+        let mut ref_p = Page::empty();
+        for i in 0..PAGESIZE - 1 {
+            ref_p[BlockId(i as u16 + 1u16)] = Block([i as u8; BLOCKSIZE]);
+            let mut p = Page::empty();
+            p[BlockId::first()] = Block([i as u8; BLOCKSIZE]);
+            assert_eq!(store.store_unfull(p, BlockId(1)).unwrap(),
+                       UnfullPage::new(PageId(0), BlockId(i as u16 + 1), BlockId(i as u16 + 2)));
+        }
+        ref_p[BlockId::first()].0[0] = (PAGESIZE - 1) as u8;
+        assert_eq!(store.get_page(PageId(0)).unwrap(), ref_p);
+        for _ in 0..PAGESIZE - 1 {
+            assert_eq!(store.unpopulated_pages, vec![]);
+            store.delete_unfull(PageId(0)).unwrap();
+        }
+        assert_eq!(store.unpopulated_pages, vec![PageId(0)]);
+        // The deleted page must not be used for unfull pages anymore
+        assert_eq!(store.store_unfull(Page::empty(), BlockId(1)).unwrap(),
+                   UnfullPage::new(PageId(1), BlockId(1), BlockId(2)));
+        assert_eq!(store.store_full(Page::empty()).unwrap(), PageId(0));
+    }
+
+    #[test]
+    fn ram_page_cache() {
+        let mut cache = RamPageCache::new(MemPageStore::new());
+        assert_eq!(cache.store_block(Block([1; BLOCKSIZE])), PageId(0));
+        assert_eq!(cache.flush_page(PageId(0)).unwrap(), PageId(0));
+        assert_eq!(cache.get_page(PageId(0)).unwrap()[BlockId::first()], Block([1; BLOCKSIZE]));
+        cache.delete_page(PageId(0)).unwrap();
+        assert_eq!(cache.store().unpopulated_pages, vec![PageId(0)]);
+    }
+}
@@ -0,0 +1,408 @@
+use std::path::Path;
+use std::io::{self, Write, Read};
+use std::fs::{OpenOptions, File};
+use std::os::raw::{c_void, c_int, c_long};
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+
+use utils::counter::Counter;
+use utils::persistence::Persistent;
+use error::{Error, Result};
+use page_manager::{UnfullPage, Page, PageId, BlockId, PageStore, CorruptPage, PAGESIZE};
+use page_manager::page::{PAGE_BYTES, STORED_PAGE_BYTES};
+
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const MAP_SHARED: c_int = 1;
+const MS_ASYNC: c_int = 1;
+
+extern "C" {
+    fn mmap(addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: c_long)
+            -> *mut c_void;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn msync(addr: *mut c_void, len: usize, flags: c_int) -> c_int;
+}
+
+/// The file grows by at least that many pages at once
+const MIN_GROWTH: u64 = 64;
+
+/// Stores pages in a memory mapped file.
+///
+/// Same semantics and file layout as `FsPageManager`, but pages are written
+/// in place, so the operating system's page cache does the caching. Reads
+/// through `PageStore` copy pages like any other store. Only `page` returns
+/// a view into the mapping, to callers holding the store itself.
+///
+/// The file grows in steps and is mapped anew each time. Views borrow the
+/// store, so none can outlive a new mapping.
+///
+/// Checksums are written along with every page, but only `get_page`
+/// verifies them. Unlike `FsPageManager` it keeps no rollback journal: A
+/// crash may leave pages behind that do not match the last written state.
+#[derive(Debug)]
+pub struct MmapPageStore {
+    pages: File,
+    map: *mut u8,
+    // Number of pages mapped
+    capacity: u64,
+    count: Counter,
+    last_page_last_block: BlockId,
+    unpopulated_pages: Vec<PageId>,
+    // Generation of the state opened from. Kept for `FsPageManager`
+    generation: u64,
+}
+
+// The mapping is only written through &mut self
+unsafe impl Send for MmapPageStore {}
+unsafe impl Sync for MmapPageStore {}
+
+impl MmapPageStore {
+    pub fn new(path: &Path) -> Result<Self> {
+        let pages = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(MmapPageStore {
+            pages,
+            map: ptr::null_mut(),
+            capacity: 0,
+            count: Counter::new(),
+            last_page_last_block: BlockId(PAGESIZE as u16),
+            unpopulated_pages: Vec::new(),
+            generation: 0,
+        })
+    }
+
+    /// Opens an existing page file without truncating it.
+    /// The state of the page store is read from `state`. It has to be
+    /// written by `write_state` beforehand.
+    ///
+    /// The file and state are compatible with `FsPageManager::open`.
+    pub fn open<R: Read>(path: &Path, state: &mut R) -> Result<Self> {
+        let mut store = MmapPageStore {
+            pages: OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(path)?,
+            map: ptr::null_mut(),
+            capacity: 0,
+            count: Counter::read_from(state)?,
+            last_page_last_block: BlockId::read_from(state)?,
+            unpopulated_pages: Vec::read_from(state)?,
+            generation: u64::read_from(state)?,
+        };
+        let capacity = store.pages.metadata()?.len() / STORED_PAGE_BYTES as u64;
+        store.remap(capacity)?;
+        Ok(store)
+    }
+
+    /// Writes the state needed to reopen this page store
+    pub fn write_state<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.count.write_to(target)?;
+        self.last_page_last_block.write_to(target)?;
+        self.unpopulated_pages.write_to(target)?;
+        (self.generation + 1).write_to(target)
+    }
+
+    /// Flushes all written pages to disk
+    pub fn sync(&self) -> Result<()> {
+        if !self.map.is_null() &&
+           unsafe { msync(self.map as *mut c_void, self.map_len(), MS_ASYNC) } != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(self.pages.sync_all()?)
+    }
+
+    /// A view of a stored page without copying it. Its checksum is not
+    /// verified
+    pub fn page(&self, page_id: PageId) -> Result<&Page> {
+        if page_id.0 >= self.count.retrieve() {
+            return Err(Error::UnknownPage(page_id));
+        }
+        // Page is a plain byte array, so it is never misaligned
+        Ok(unsafe { &*(self.slot(page_id) as *const Page) })
+    }
+
+    /// The checksum stored with a page
+    fn stored_checksum(&self, page_id: PageId) -> u32 {
+        let mut checksum = [0; 4];
+        unsafe {
+            ptr::copy_nonoverlapping(self.slot(page_id).add(PAGE_BYTES), checksum.as_mut_ptr(), 4);
+        }
+        u32::from_le_bytes(checksum)
+    }
+
+    /// Writes the checksum of a page after it was changed
+    fn seal(&mut self, page_id: PageId) {
+        let checksum = self.page_mut(page_id).checksum().to_le_bytes();
+        unsafe {
+            ptr::copy_nonoverlapping(checksum.as_ptr(), self.slot(page_id).add(PAGE_BYTES), 4);
+        }
+    }
+
+    /// Grows the mapping so it contains `page_id`
+    fn reserve(&mut self, page_id: PageId) -> io::Result<()> {
+        if page_id.0 >= self.capacity {
+            let capacity = (page_id.0 + 1).max(self.capacity * 2).max(MIN_GROWTH);
+            self.remap(capacity)?;
+        }
+        Ok(())
+    }
+
+    /// The page has to be reserved. Call `seal` after changing it
+    fn page_mut(&mut self, page_id: PageId) -> &mut Page {
+        assert!(page_id.0 < self.capacity, "Page {:?} was never reserved", page_id);
+        unsafe { &mut *(self.slot(page_id) as *mut Page) }
+    }
+
+    fn slot(&self, page_id: PageId) -> *mut u8 {
+        unsafe { self.map.add(page_id.0 as usize * STORED_PAGE_BYTES) }
+    }
+
+    fn map_len(&self) -> usize {
+        self.capacity as usize * STORED_PAGE_BYTES
+    }
+
+    /// Grows the file to `capacity` pages if needed and maps all of it
+    fn remap(&mut self, capacity: u64) -> io::Result<()> {
+        self.unmap();
+        if self.pages.metadata()?.len() < capacity * STORED_PAGE_BYTES as u64 {
+            self.pages.set_len(capacity * STORED_PAGE_BYTES as u64)?;
+        }
+        if capacity == 0 {
+            return Ok(());
+        }
+        let len = capacity as usize * STORED_PAGE_BYTES;
+        let map = unsafe {
+            mmap(ptr::null_mut(),
+                 len,
+                 PROT_READ | PROT_WRITE,
+                 MAP_SHARED,
+                 self.pages.as_raw_fd(),
+                 0)
+        };
+        // MAP_FAILED
+        if map as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        self.map = map as *mut u8;
+        self.capacity = capacity;
+        Ok(())
+    }
+
+    fn unmap(&mut self) {
+        if !self.map.is_null() {
+            unsafe { munmap(self.map as *mut c_void, self.map_len()) };
+            self.map = ptr::null_mut();
+            self.capacity = 0;
+        }
+    }
+}
+
+impl Drop for MmapPageStore {
+    fn drop(&mut self) {
+        self.unmap();
+    }
+}
+
+impl PageStore for MmapPageStore {
+    fn store_full(&mut self, page: Page) -> Result<PageId> {
+        let id = match self.unpopulated_pages.last() {
+            Some(&id) => id,
+            None => PageId(self.count.retrieve()),
+        };
+        self.reserve(id)?;
+        // Only hand out the id once there is room for the page
+        if self.unpopulated_pages.pop().is_none() {
+            self.count.retrieve_and_inc();
+        }
+        *self.page_mut(id) = page;
+        self.seal(id);
+        self.last_page_last_block = BlockId(PAGESIZE as u16);
+        Ok(id)
+    }
+
+    fn store_unfull(&mut self, page: Page, block_id: BlockId) -> Result<UnfullPage> {
+        let page_id = if self.last_page_last_block.0 + block_id.0 > PAGESIZE as u16 {
+            // New Page
+            self.reserve(PageId(self.count.retrieve()))?;
+            self.last_page_last_block = BlockId(1);
+            let page_id = PageId(self.count.retrieve_and_inc());
+            *self.page_mut(page_id) = Page::empty();
+            page_id
+        } else {
+            // Fits on same page
+            PageId(self.count.retrieve() - 1)
+        };
+        let first_block = self.last_page_last_block;
+        {
+            let container_page = self.page_mut(page_id);
+            // First byte of an unfull page acts as reference counter.
+            container_page[BlockId::first()].0[0] += 1;
+            for i in 0..block_id.0 {
+                container_page[BlockId(first_block.0 + i)] = page[BlockId(i)];
+            }
+        }
+        self.seal(page_id);
+        self.last_page_last_block = BlockId(first_block.0 + block_id.0);
+        Ok(UnfullPage::new(page_id, first_block, self.last_page_last_block))
+    }
+
+    #[inline]
+    fn delete_page(&mut self, page_id: PageId) -> Result<()> {
+        self.unpopulated_pages.push(page_id);
+        Ok(())
+    }
+
+    /// Decreases the reference count of an unfull page.
+    /// Once it is zero, the page is free to be used again
+    fn delete_unfull(&mut self, page_id: PageId) -> Result<()> {
+        if page_id.0 >= self.count.retrieve() {
+            return Err(Error::UnknownPage(page_id));
+        }
+        let refcount = {
+            let refcount = &mut self.page_mut(page_id)[BlockId::first()].0[0];
+            *refcount -= 1;
+            *refcount
+        };
+        self.seal(page_id);
+        if refcount == 0 {
+            self.unpopulated_pages.push(page_id);
+            // If it was the page unfull pages are currently appended to,
+            // stop doing so. It might be handed out as a full page next.
+            if page_id.0 + 1 == self.count.retrieve() {
+                self.last_page_last_block = BlockId(PAGESIZE as u16);
+            }
+        }
+        Ok(())
+    }
+
+    fn get_page(&self, page_id: PageId) -> Result<Page> {
+        let page = *self.page(page_id)?;
+        CorruptPage::check(page_id, &page, self.stored_checksum(page_id))?;
+        Ok(page)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use test_utils::create_test_dir;
+
+    use super::{MmapPageStore, MIN_GROWTH};
+    use error::Error;
+    use page_manager::{UnfullPage, Page, PageStore, PageId, Block, BlockId, FsPageManager,
+                       BLOCKSIZE, PAGESIZE};
+
+    fn new_store(name: &str) -> MmapPageStore {
+        let path = &create_test_dir(format!("mmap_page_store/{}", name).as_str());
+        MmapPageStore::new(&path.join("pages.bin")).unwrap()
+    }
+
+    #[test]
+    fn store_and_get() {
+        let mut store = new_store("store_and_get");
+        let mut p = Page::empty();
+        p[BlockId::first()] = Block([1; BLOCKSIZE]);
+        assert_eq!(store.store_full(Page::empty()).unwrap(), PageId(0));
+        assert_eq!(store.store_full(p).unwrap(), PageId(1));
+        assert_eq!(store.get_page(PageId(1)).unwrap(), p);
+        assert_eq!(*store.page(PageId(0)).unwrap(), Page::empty());
+        store.delete_page(PageId(0)).unwrap();
+        assert_eq!(store.store_full(p).unwrap(), PageId(0));
+        assert_eq!(store.page(PageId(0)).unwrap(), store.page(PageId(1)).unwrap());
+        // Mapped, but never stored
+        assert!(matches!(store.page(PageId(2)), Err(Error::UnknownPage(PageId(2)))));
+    }
+
+    #[test]
+    fn grow() {
+        let mut store = new_store("grow");
+        for i in 0..MIN_GROWTH * 3 {
+            let mut p = Page::empty();
+            p[BlockId::last()] = Block([i as u8; BLOCKSIZE]);
+            assert_eq!(store.store_full(p).unwrap(), PageId(i));
+        }
+        for i in 0..MIN_GROWTH * 3 {
+            assert_eq!(store.page(PageId(i)).unwrap()[BlockId::last()],
+                       Block([i as u8; BLOCKSIZE]));
+        }
+    }
+
+    #[test]
+    fn unfull() {
+        let mut store = new_store("unfull");
+        let mut ref_p = Page::empty();
+        for i in 0..PAGESIZE - 1 {
+            ref_p[BlockId(i as u16 + 1u16)] = Block([i as u8; BLOCKSIZE]);
+            let mut p = Page::empty();
+            p[BlockId::first()] = Block([i as u8; BLOCKSIZE]);
+            assert_eq!(store.store_unfull(p, BlockId(1)).unwrap(),
+                       UnfullPage::new(PageId(0), BlockId(i as u16 + 1), BlockId(i as u16 + 2)));
+        }
+        ref_p[BlockId::first()].0[0] = (PAGESIZE - 1) as u8;
+        assert_eq!(*store.page(PageId(0)).unwrap(), ref_p);
+        // The next one goes to a new page
+        assert_eq!(store.store_unfull(Page::empty(), BlockId(1)).unwrap(),
+                   UnfullPage::new(PageId(1), BlockId(1), BlockId(2)));
+        for _ in 0..PAGESIZE - 1 {
+            assert_eq!(store.unpopulated_pages, vec![]);
+            store.delete_unfull(PageId(0)).unwrap();
+        }
+        assert_eq!(store.unpopulated_pages, vec![PageId(0)]);
+        assert_eq!(store.store_full(Page::empty()).unwrap(), PageId(0));
+    }
+
+    #[test]
+    fn compatible_with_fs_page_manager() {
+        let path = &create_test_dir("mmap_page_store/compatible_with_fs_page_manager");
+        let mut p = Page::empty();
+        p[BlockId(3)] = Block([3; BLOCKSIZE]);
+        let mut state = Vec::new();
+        {
+            let mut store = MmapPageStore::new(&path.join("pages.bin")).unwrap();
+            assert_eq!(store.store_full(p).unwrap(), PageId(0));
+            assert_eq!(store.store_unfull(p, BlockId(4)).unwrap(),
+                       UnfullPage::new(PageId(1), BlockId(1), BlockId(5)));
+            store.sync().unwrap();
+            store.write_state(&mut state).unwrap();
+        }
+        let mut pmgr = FsPageManager::open(&path.join("pages.bin"), &mut &state[..]).unwrap();
+        assert_eq!(pmgr.get_page(PageId(0)).unwrap(), p);
+        assert_eq!(pmgr.get_page(PageId(1)).unwrap()[BlockId(4)], Block([3; BLOCKSIZE]));
+        assert_eq!(pmgr.store_unfull(p, BlockId(4)).unwrap(),
+                   UnfullPage::new(PageId(1), BlockId(5), BlockId(9)));
+        pmgr.sync().unwrap();
+        state.clear();
+        pmgr.write_state(&mut state).unwrap();
+
+        let mut store = MmapPageStore::open(&path.join("pages.bin"), &mut &state[..]).unwrap();
+        assert_eq!(store.page(PageId(1)).unwrap()[BlockId::first()].0[0], 2);
+        assert_eq!(store.page(PageId(1)).unwrap()[BlockId(8)], Block([3; BLOCKSIZE]));
+        assert_eq!(store.store_full(Page::empty()).unwrap(), PageId(2));
+    }
+
+    #[test]
+    fn corrupt_page() {
+        let mut store = new_store("corrupt_page");
+        assert_eq!(store.store_full(Page::empty()).unwrap(), PageId(0));
+        assert_eq!(store.get_page(PageId(0)).unwrap(), Page::empty());
+        store.page_mut(PageId(0))[BlockId(1)].0[7] = 1;
+        match store.get_page(PageId(0)) {
+            Err(Error::Corruption(corrupt)) => assert_eq!(corrupt.page_id, PageId(0)),
+            other => panic!("Expected corruption, got {:?}", other),
+        }
+        store.seal(PageId(0));
+        assert!(store.get_page(PageId(0)).is_ok());
+        match store.get_page(PageId(1)) {
+            Err(Error::UnknownPage(PageId(1))) => {}
+            other => panic!("Expected unknown page, got {:?}", other),
+        }
+    }
+}
@@ -3,40 +3,89 @@
 //! Many of these `Block`s then make up a `Page`.
 //! These `Page`s can then be written or retrieved to/from disk (or any other medium).
 //! This module provides traits for abstracting this concept.
+//!
+//! Implement `PageStore` to plug in another storage mechanism.
 use std::sync::Arc;
 
-pub use page_manager::page::{Pages, UnfullPage, Page, PageId, PAGESIZE};
+use error::Result;
+
+pub use page_manager::page::{Pages, UnfullPage, Page, PageId, CorruptPage, PAGESIZE};
 pub use page_manager::block::{Block, BlockId, BLOCKSIZE};
 pub use page_manager::fs_page_manager::FsPageManager;
-pub use page_manager::ram_page_cache::RamPageCache;
+pub use page_manager::mem_page_store::MemPageStore;
+#[cfg(unix)]
+pub use page_manager::mmap_page_store::MmapPageStore;
+pub use page_manager::ram_page_cache::{RamPageCache, CacheReader, Pin};
 pub use page_manager::block_iter::BlockIter;
+pub use page_manager::stats::{StoreStats, CacheStats, CacheEvent, CacheListener};
 
 mod page;
 mod block;
 mod fs_page_manager;
+mod mem_page_store;
+#[cfg(unix)]
+mod mmap_page_store;
 mod ram_page_cache;
 mod block_iter;
+mod stats;
+mod journal;
 
+/// Read access to pages, as needed by `BlockIter`
 pub trait PageCache {
-    fn get_page(&self, PageId) -> Arc<Page>;
-    fn delete_page(&mut self, PageId);
-    fn delete_unfull(&mut self, PageId);
+    fn get_page(&self, page_id: PageId) -> Result<Arc<Page>>;
 }
 
-trait PageStore {
-    fn store_unfull(&mut self, Page, BlockId) -> UnfullPage;
-    fn store_full(&mut self, Page) -> PageId;
-    fn get_page(&self, PageId) -> Page;
-    fn delete_page(&mut self, PageId);
-    fn delete_unfull(&mut self, PageId);
-}
+/// Storage backend of the pages of an index.
+///
+/// `RamPageCache` and thereby `Index` run on top of any implementation.
+/// Perlin ships `FsPageManager` (a file), `MmapPageStore` (a memory mapped
+/// file, unix only) and `MemPageStore` (in memory).
+///
+/// Besides full pages a store keeps unfull pages: The last few blocks of a
+/// listing that do not fill a page. To not waste a page on each of them,
+/// they are packed together onto shared pages. The first byte of such a page
+/// counts the unfull pages on it, so block 0 is never handed out.
+///
+/// A page that does not match its checksum is reported as
+/// `Error::Corruption`, a page id the store never handed out as
+/// `Error::UnknownPage`.
+pub trait PageStore {
+    /// Stores the first `block_id` blocks of `page` next to other unfull
+    /// pages and returns where they ended up
+    fn store_unfull(&mut self, page: Page, block_id: BlockId) -> Result<UnfullPage>;
 
-pub trait BlockManager {
-    fn store_block(&mut self, Block) -> PageId;
-    fn store_in_place(&mut self, PageId, BlockId, Block);
-    fn flush_page(&mut self, PageId) -> PageId;
-    fn flush_unfull(&mut self, PageId, BlockId) -> UnfullPage;    
-}
+    /// Stores a page and returns its id. Ids of deleted pages are reused
+    fn store_full(&mut self, page: Page) -> Result<PageId>;
+
+    /// Returns a copy of a stored page
+    fn get_page(&self, page_id: PageId) -> Result<Page>;
 
+    /// Frees a full page
+    fn delete_page(&mut self, page_id: PageId) -> Result<()>;
 
+    /// Frees an unfull page. The page it is on is freed once all unfull
+    /// pages on it are
+    fn delete_unfull(&mut self, page_id: PageId) -> Result<()>;
 
+    /// Counters of this store. Stores that keep none return zeros
+    fn stats(&self) -> StoreStats {
+        StoreStats::default()
+    }
+}
+
+/// Assembles pages block by block before handing them to a `PageStore`
+pub trait BlockManager {
+    /// Starts a new page with `block` as its first block. The returned id is
+    /// only valid until the page is flushed
+    fn store_block(&mut self, block: Block) -> PageId;
+    fn store_in_place(&mut self, page_id: PageId, block_id: BlockId, block: Block) -> Result<()>;
+    /// Stores a page that was filled completely and returns its final id.
+    /// If storing fails, the page is kept and flushing can be retried
+    fn flush_page(&mut self, page_id: PageId) -> Result<PageId>;
+    /// Stores the first `block_id` blocks of a page as unfull page
+    fn flush_unfull(&mut self, page_id: PageId, block_id: BlockId) -> Result<UnfullPage>;
+    /// Frees a stored page
+    fn delete_page(&mut self, page_id: PageId) -> Result<()>;
+    /// Frees a stored unfull page
+    fn delete_unfull(&mut self, page_id: PageId) -> Result<()>;
+}
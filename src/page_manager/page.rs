@@ -1,14 +1,25 @@
 use std::slice;
 use std::fmt;
 use std::mem;
-use std::io;
+use std::error::Error;
+use std::io::{self, Read, Write};
 use std::u64;
 use std::ops::{Index, IndexMut};
 
-use page_manager:: {BLOCKSIZE, Block, BlockId};
+use utils::persistence::Persistent;
+use utils::crc32c::crc32c;
+use error;
+use page_manager:: {BLOCKSIZE, Block, BlockId, PageCache};
 
 pub const PAGESIZE: usize = 64;
 
+/// Number of bytes of a page
+pub const PAGE_BYTES: usize = PAGESIZE * BLOCKSIZE;
+
+/// Number of bytes a page takes up in a page file: The page followed by its
+/// checksum
+pub const STORED_PAGE_BYTES: usize = PAGE_BYTES + 4;
+
 #[derive(Copy)]
 pub struct Page(pub [Block; PAGESIZE]);
 
@@ -93,6 +104,14 @@ impl Pages {
     pub fn has_unfull(&self) -> bool {
         self.1.is_some()
     }
+
+    /// Reads every page, failing on the first one that cannot be read
+    pub fn verify(&self, cache: &dyn PageCache) -> error::Result<()> {
+        for page_id in self.0.iter().chain(self.1.as_ref().map(|unfull| &unfull.0)) {
+            cache.get_page(*page_id)?;
+        }
+        Ok(())
+    }
 }
 
 impl Default for Pages {
@@ -101,6 +120,41 @@ impl Default for Pages {
     }
 }
 
+impl Persistent for PageId {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.0.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok(PageId(u64::read_from(source)?))
+    }
+}
+
+impl Persistent for UnfullPage {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.0.write_to(target)?;
+        self.1.write_to(target)?;
+        self.2.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok(UnfullPage(PageId::read_from(source)?,
+                      BlockId::read_from(source)?,
+                      BlockId::read_from(source)?))
+    }
+}
+
+impl Persistent for Pages {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.0.write_to(target)?;
+        self.1.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok(Pages(Vec::read_from(source)?, Option::read_from(source)?))
+    }
+}
+
 impl Page {
     pub fn empty() -> Self {
         Page([Block::empty(); PAGESIZE])
@@ -116,8 +170,70 @@ impl Page {
         unsafe {mem::transmute(raw)}
     }
 
+    /// CRC32C of the page
+    pub fn checksum(&self) -> u32 {
+        crc32c(self.as_slice())
+    }
+
+    /// Writes the page followed by its checksum
+    pub fn write_checked<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        let mut raw = [0; STORED_PAGE_BYTES];
+        raw[..PAGE_BYTES].copy_from_slice(self.as_slice());
+        raw[PAGE_BYTES..].copy_from_slice(&self.checksum().to_le_bytes());
+        // One write, so the page is torn at worst, never half checked
+        target.write_all(&raw)
+    }
+
+    /// Reads a page written by `write_checked` and verifies its checksum
+    pub fn read_checked<R: Read>(source: &mut R, page_id: PageId) -> error::Result<Page> {
+        let mut raw = [0; STORED_PAGE_BYTES];
+        source.read_exact(&mut raw)?;
+        let page = Page::from_read(&mut &raw[..PAGE_BYTES]);
+        let mut stored = [0; 4];
+        stored.copy_from_slice(&raw[PAGE_BYTES..]);
+        CorruptPage::check(page_id, &page, u32::from_le_bytes(stored))?;
+        Ok(page)
+    }
+}
+
+/// A page whose content does not match its checksum, e.g. because it was
+/// torn by a power loss while being written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CorruptPage {
+    pub page_id: PageId,
+    /// Checksum written with the page
+    pub stored: u32,
+    /// Checksum of the content read
+    pub computed: u32,
+}
+
+impl CorruptPage {
+    /// Fails if `stored` is not the checksum of `page`
+    pub fn check(page_id: PageId, page: &Page, stored: u32) -> Result<(), CorruptPage> {
+        let computed = page.checksum();
+        if stored == computed {
+            return Ok(());
+        }
+        Err(CorruptPage {
+            page_id,
+            stored,
+            computed,
+        })
+    }
+}
+
+impl fmt::Display for CorruptPage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "Page {} is corrupt: stored checksum {:08x}, computed {:08x}",
+               self.page_id.0,
+               self.stored,
+               self.computed)
+    }
 }
 
+impl Error for CorruptPage {}
+
 impl fmt::Debug for Page {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{:?}", &self.0 as &[Block])
@@ -1,43 +1,395 @@
-use std::collections::BTreeMap;
-use std::sync::{RwLock, Arc};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard, Arc, Weak};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
+use error::{Error, Result};
 use utils::counter::Counter;
 use page_manager::{FsPageManager, UnfullPage, Page, Block, BlockManager, PageStore, PageId,
-                   BlockId, PageCache};
+                   BlockId, PageCache, CacheStats, CacheEvent, CacheListener};
 
-const CACHESIZE: usize = 16;
+/// Number of pages cached by `RamPageCache::new` (4MiB)
+const DEFAULT_CAPACITY: usize = 1024;
 
+/// Caches pages of a `PageStore` in RAM and assembles new pages block by
+/// block before they are stored.
+///
+/// At most `capacity` pages are cached. When full, pages are evicted
+/// using the CLOCK algorithm.
+///
+/// Other threads read pages through a `CacheReader`. Pages they still need
+/// are protected with a `Pin`.
 #[derive(Debug)]
-pub struct RamPageCache {
-    cache: RwLock<Vec<(PageId, Arc<Page>)>>,
+pub struct RamPageCache<S = FsPageManager> {
+    shared: Arc<Shared<S>>,
     counter: Counter,
     construction_cache: BTreeMap<PageId, Page>,
-    store: FsPageManager,
+    // Running batches, innermost last
+    batches: Vec<Batch>,
+    // For every pin, oldest first, the pages freed after it was taken
+    pinned: VecDeque<(Weak<()>, Vec<Allocation>)>,
 }
 
-impl RamPageCache {
-    pub fn new(store: FsPageManager) -> Self {
+/// The part of a `RamPageCache` shared with its readers
+#[derive(Debug)]
+struct Shared<S> {
+    cache: RwLock<Clock>,
+    store: RwLock<S>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    listener: RwLock<Option<Box<dyn CacheListener>>>,
+}
+
+/// Reads pages of a `RamPageCache` from any thread. Pages read are cached
+/// in the `RamPageCache`.
+///
+/// A reader may read pages that are freed and reused meanwhile. Hold a
+/// `Pin` as long as the pages are needed.
+#[derive(Debug)]
+pub struct CacheReader<S = FsPageManager>(Arc<Shared<S>>);
+
+/// Keeps the pages stored before it was taken from being reused.
+/// See `RamPageCache::pin`
+#[derive(Clone, Debug)]
+pub struct Pin {
+    _handle: Arc<()>,
+}
+
+/// Pages stored and freed since `RamPageCache::begin`
+#[derive(Debug)]
+struct Batch {
+    // Construction pages from this id on were started in the batch
+    first_construction: u64,
+    // Construction pages started before, as they were before the batch
+    overwritten: BTreeMap<PageId, Page>,
+    stored: Vec<Allocation>,
+    freed: Vec<Allocation>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Allocation {
+    Full(PageId),
+    Unfull(PageId),
+}
+
+impl<S: PageStore> RamPageCache<S> {
+    /// Creates a cache that holds up to 1024 pages (4MiB) in RAM
+    pub fn new(store: S) -> Self {
+        RamPageCache::with_capacity(store, DEFAULT_CAPACITY)
+    }
+
+    /// Creates a cache that holds at most `capacity` pages in RAM.
+    /// A capacity of 0 disables caching.
+    pub fn with_capacity(store: S, capacity: usize) -> Self {
         RamPageCache {
+            shared: Arc::new(Shared {
+                cache: RwLock::new(Clock::new(capacity)),
+                store: RwLock::new(store),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                evictions: AtomicU64::new(0),
+                listener: RwLock::new(None),
+            }),
             counter: Counter::new(),
-            cache: RwLock::new(Vec::with_capacity(CACHESIZE)),
             construction_cache: BTreeMap::new(),
-            store: store,
+            batches: Vec::new(),
+            pinned: VecDeque::new(),
+        }
+    }
+
+    /// Calls `listener` on every event from now on
+    pub fn set_listener<L: CacheListener + 'static>(&mut self, listener: L) {
+        *self.shared.listener.write().unwrap() = Some(Box::new(listener));
+    }
+
+    /// Counters of this cache and its store
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.shared.hits.load(Ordering::Relaxed),
+            misses: self.shared.misses.load(Ordering::Relaxed),
+            evictions: self.shared.evictions.load(Ordering::Relaxed),
+            cached_pages: self.shared.cache.read().unwrap().slots.len(),
+            store: self.store().stats(),
         }
     }
 
+    /// Maximum number of pages held in RAM
+    pub fn capacity(&self) -> usize {
+        self.shared.cache.read().unwrap().capacity
+    }
+
+    /// The page store this cache is backed by
+    pub fn store(&self) -> RwLockReadGuard<'_, S> {
+        self.shared.store.read().unwrap()
+    }
+
+    /// Mutable access to the page store. Pages must not be changed through
+    /// it, the cache would not notice
+    pub fn store_mut(&mut self) -> RwLockWriteGuard<'_, S> {
+        self.shared.store.write().unwrap()
+    }
+
+    /// Returns a reader of the pages of this cache for other threads
+    pub fn reader(&self) -> CacheReader<S> {
+        CacheReader(self.shared.clone())
+    }
+
+    /// Pages deleted while the returned pin or a clone of it is alive are
+    /// only freed once it and all pins taken before are dropped. So readers
+    /// can keep reading the pages stored before
+    pub fn pin(&mut self) -> Pin {
+        let pin = Arc::new(());
+        self.pinned.push_back((Arc::downgrade(&pin), Vec::new()));
+        Pin { _handle: pin }
+    }
+
+    /// Frees the pages that waited for pins dropped by now. This happens
+    /// on every deletion as well
+    pub fn reclaim(&mut self) -> Result<()> {
+        while self.pinned.front().is_some_and(|(pin, _)| pin.strong_count() == 0) {
+            let (_, freed) = self.pinned.pop_front().unwrap();
+            for allocation in freed {
+                self.free(allocation)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Starts a batch. Until it is committed or rolled back, deleted pages
+    /// are not freed but remembered, so the pages stored before the batch
+    /// stay intact. So do pages under construction: Rolling back restores
+    /// them.
+    ///
+    /// Batches nest. An inner batch that is committed becomes part of the
+    /// outer one
+    pub fn begin(&mut self) {
+        self.batches.push(Batch {
+            first_construction: self.counter.retrieve(),
+            overwritten: BTreeMap::new(),
+            stored: Vec::new(),
+            freed: Vec::new(),
+        });
+    }
+
+    /// Returns true between `begin` and `commit_batch` or `rollback_batch`
+    pub fn in_batch(&self) -> bool {
+        !self.batches.is_empty()
+    }
+
+    /// Ends the innermost batch and frees the pages deleted during it.
+    ///
+    /// The batch ends even if freeing fails. Then some pages are not reused
+    pub fn commit_batch(&mut self) -> Result<()> {
+        if let Some(batch) = self.batches.pop() {
+            if let Some(outer) = self.batches.last_mut() {
+                for (page_id, page) in batch.overwritten {
+                    if page_id.0 < outer.first_construction {
+                        outer.overwritten.entry(page_id).or_insert(page);
+                    }
+                }
+                outer.stored.extend(batch.stored);
+                outer.freed.extend(batch.freed);
+                return Ok(());
+            }
+            for allocation in batch.freed {
+                self.release(allocation)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the innermost batch and frees the pages stored during it
+    /// instead. Pages started during it are dropped, pages started before
+    /// are put back under construction as they were
+    pub fn rollback_batch(&mut self) -> Result<()> {
+        if let Some(batch) = self.batches.pop() {
+            let first = PageId(batch.first_construction);
+            self.construction_cache.split_off(&first);
+            self.construction_cache.extend(batch.overwritten);
+            for allocation in batch.stored {
+                self.defer(allocation)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Frees a page, unless it has to wait for the batch to be committed or
+    /// for pins to be dropped
+    fn release(&mut self, allocation: Allocation) -> Result<()> {
+        if let Some(batch) = self.batches.last_mut() {
+            batch.freed.push(allocation);
+            return Ok(());
+        }
+        self.defer(allocation)
+    }
+
+    /// Frees a page once the pins taken before are dropped
+    fn defer(&mut self, allocation: Allocation) -> Result<()> {
+        self.reclaim()?;
+        match self.pinned.back_mut() {
+            Some(&mut (_, ref mut freed)) => freed.push(allocation),
+            None => return self.free(allocation),
+        }
+        Ok(())
+    }
+
+    /// Remembers a construction page started before the running batch
+    /// before it is changed or stored
+    fn overwrite(&mut self, page_id: PageId) {
+        if let Some(batch) = self.batches.last_mut() {
+            if page_id.0 < batch.first_construction {
+                if let Some(page) = self.construction_cache.get(&page_id) {
+                    batch.overwritten.entry(page_id).or_insert(*page);
+                }
+            }
+        }
+    }
+
+    fn free(&mut self, allocation: Allocation) -> Result<()> {
+        match allocation {
+            Allocation::Full(page_id) => {
+                let mut store = self.shared.store.write().unwrap();
+                self.shared.invalidate(page_id);
+                store.delete_page(page_id)
+            }
+            Allocation::Unfull(page_id) => {
+                let mut store = self.shared.store.write().unwrap();
+                self.shared.invalidate(page_id);
+                store.delete_unfull(page_id)
+            }
+        }
+    }
+}
+
+impl<S: PageStore> Shared<S> {
+    fn get_page(&self, page_id: PageId) -> Result<Arc<Page>> {
+        if let Some(page) = self.cache.read().unwrap().get(page_id) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            self.notify(CacheEvent::Hit(page_id));
+            return Ok(page);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        self.notify(CacheEvent::Miss(page_id));
+        // The page must not change before it is cached. Otherwise the copy
+        // read here would outlive its invalidation
+        let store = self.store.read().unwrap();
+        let page = Arc::new(store.get_page(page_id)?);
+        let evicted = self.cache.write().unwrap().insert(page_id, page.clone());
+        drop(store);
+        if let Some(evicted) = evicted {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+            self.notify(CacheEvent::Evict(evicted));
+        }
+        Ok(page)
+    }
+
     #[inline]
-    fn search_page(&self, page_id: &PageId) -> Result<usize, usize> {
-        self.cache.read().unwrap().binary_search_by_key(page_id, |&(pid, _)| pid)
+    fn notify(&self, event: CacheEvent) {
+        if let Some(ref listener) = *self.listener.read().unwrap() {
+            listener.on_event(event);
+        }
     }
 
-    fn invalidate(&mut self, page_id: PageId) {
-        if let Ok(index) = self.search_page(&page_id) {
-            self.cache.write().unwrap().remove(index);
+    /// Drops a page from the cache. Call it while holding the store
+    /// locked for writing, so no reader caches the page as it was before
+    fn invalidate(&self, page_id: PageId) {
+        self.cache.write().unwrap().remove(page_id);
+    }
+}
+
+impl<S> Clone for CacheReader<S> {
+    fn clone(&self) -> Self {
+        CacheReader(self.0.clone())
+    }
+}
+
+impl<S: PageStore> PageCache for CacheReader<S> {
+    fn get_page(&self, page_id: PageId) -> Result<Arc<Page>> {
+        self.0.get_page(page_id)
+    }
+}
+
+#[derive(Debug)]
+struct Slot {
+    page_id: PageId,
+    page: Arc<Page>,
+    // Set on every hit, cleared when the clock hand passes
+    referenced: AtomicBool,
+}
+
+/// Cached pages arranged in a circle.
+///
+/// On eviction the hand sweeps the circle: referenced pages get a second
+/// chance, the first unreferenced page is replaced. Pages enter
+/// unreferenced, so a page read only once is evicted before the ones read
+/// repeatedly.
+#[derive(Debug)]
+struct Clock {
+    slots: Vec<Slot>,
+    index: BTreeMap<PageId, usize>,
+    hand: usize,
+    capacity: usize,
+}
+
+impl Clock {
+    fn new(capacity: usize) -> Self {
+        Clock {
+            slots: Vec::new(),
+            index: BTreeMap::new(),
+            hand: 0,
+            capacity,
+        }
+    }
+
+    fn get(&self, page_id: PageId) -> Option<Arc<Page>> {
+        self.index.get(&page_id).map(|&i| {
+            let slot = &self.slots[i];
+            slot.referenced.store(true, Ordering::Relaxed);
+            slot.page.clone()
+        })
+    }
+
+    /// Returns the id of the evicted page, if any
+    fn insert(&mut self, page_id: PageId, page: Arc<Page>) -> Option<PageId> {
+        if self.capacity == 0 || self.index.contains_key(&page_id) {
+            return None;
+        }
+        let slot = Slot {
+            page_id,
+            page,
+            referenced: AtomicBool::new(false),
+        };
+        if self.slots.len() < self.capacity {
+            self.index.insert(page_id, self.slots.len());
+            self.slots.push(slot);
+            return None;
+        }
+        // Advance the hand to the first unreferenced page
+        while self.slots[self.hand].referenced.swap(false, Ordering::Relaxed) {
+            self.hand = (self.hand + 1) % self.slots.len();
+        }
+        let evicted = self.slots[self.hand].page_id;
+        self.index.remove(&evicted);
+        self.index.insert(page_id, self.hand);
+        self.slots[self.hand] = slot;
+        self.hand = (self.hand + 1) % self.slots.len();
+        Some(evicted)
+    }
+
+    fn remove(&mut self, page_id: PageId) {
+        if let Some(i) = self.index.remove(&page_id) {
+            self.slots.swap_remove(i);
+            if let Some(moved) = self.slots.get(i) {
+                self.index.insert(moved.page_id, i);
+            }
+            if self.hand >= self.slots.len() {
+                self.hand = 0;
+            }
         }
     }
 }
 
-impl BlockManager for RamPageCache {
+impl<S: PageStore> BlockManager for RamPageCache<S> {
     fn store_block(&mut self, block: Block) -> PageId {
         let page_id = PageId(self.counter.retrieve_and_inc());
         let mut p = Page::empty();
@@ -46,86 +398,92 @@ impl BlockManager for RamPageCache {
         page_id
     }
 
-    fn store_in_place(&mut self, page_id: PageId, block_id: BlockId, block: Block) {
+    fn store_in_place(&mut self, page_id: PageId, block_id: BlockId, block: Block) -> Result<()> {
+        self.overwrite(page_id);
         //See if page is in construction cache
-        if let Some(page) = self.construction_cache.get_mut(&page_id) {
-            page[block_id]  = block;
-        } else {
-            //If the page is not in construction cache we might get a pageid collision
-            //Unresolved for now
-            unreachable!();
+        match self.construction_cache.get_mut(&page_id) {
+            Some(page) => {
+                page[block_id] = block;
+                Ok(())
+            }
+            None => Err(Error::UnknownPage(page_id)),
         }
     }
 
-    fn flush_page(&mut self, page_id: PageId) -> PageId {
-        if let Some(page) = self.construction_cache.remove(&page_id)
-        {
-            self.invalidate(page_id);
-            return self.store.store_full(page);
+    fn flush_page(&mut self, page_id: PageId) -> Result<PageId> {
+        let page = match self.construction_cache.get(&page_id) {
+            Some(page) => *page,
+            None => return Err(Error::UnknownPage(page_id)),
+        };
+        let stored_id = {
+            let mut store = self.shared.store.write().unwrap();
+            let stored_id = store.store_full(page)?;
+            // The store might reuse the id of a cached, deleted page
+            self.shared.invalidate(stored_id);
+            stored_id
+        };
+        self.overwrite(page_id);
+        self.construction_cache.remove(&page_id);
+        if let Some(batch) = self.batches.last_mut() {
+            batch.stored.push(Allocation::Full(stored_id));
         }
-        unreachable!();
-        // If page is not in cache it needs not to be flushed
+        self.shared.notify(CacheEvent::Flush(stored_id));
+        Ok(stored_id)
     }
 
-    fn flush_unfull(&mut self, page_id: PageId, block_id: BlockId) -> UnfullPage {
-        if let Some(page) = self.construction_cache.remove(&page_id)
-        {
-            self.invalidate(page_id);
-            return self.store.store_unfull(page, block_id);
+    fn flush_unfull(&mut self, page_id: PageId, block_id: BlockId) -> Result<UnfullPage> {
+        let page = match self.construction_cache.get(&page_id) {
+            Some(page) => *page,
+            None => return Err(Error::UnknownPage(page_id)),
+        };
+        let unfull_page = {
+            let mut store = self.shared.store.write().unwrap();
+            let unfull_page = store.store_unfull(page, block_id)?;
+            // The blocks might be appended to a cached page
+            self.shared.invalidate(unfull_page.page_id());
+            unfull_page
+        };
+        self.overwrite(page_id);
+        self.construction_cache.remove(&page_id);
+        if let Some(batch) = self.batches.last_mut() {
+            batch.stored.push(Allocation::Unfull(unfull_page.page_id()));
         }
-        unreachable!();
-        // If page is not in cache it needs not to be flushed
+        self.shared.notify(CacheEvent::FlushUnfull(unfull_page));
+        Ok(unfull_page)
+    }
+
+    fn delete_page(&mut self, page_id: PageId) -> Result<()> {
+        self.release(Allocation::Full(page_id))
+    }
+
+    fn delete_unfull(&mut self, page_id: PageId) -> Result<()> {
+        self.release(Allocation::Unfull(page_id))
     }
 }
 
-impl PageCache for RamPageCache {
-    fn delete_page(&mut self, page_id: PageId) {
-        self.store.delete_page(page_id);
-        self.invalidate(page_id);
-    }
-
-    fn delete_unfull(&mut self, page_id: PageId) {
-        self.store.delete_unfull(page_id);
-        self.invalidate(page_id);
-    }
-
-    fn get_page(&self, page_id: PageId) -> Arc<Page> {
-        use std::cmp;
-        match self.search_page(&page_id) {
-            // Page in cache
-            Ok(index) => self.cache.read().unwrap()[index].1.clone(),
-            // Page not in cache
-            Err(index) => {
-                // Get it, arc it
-                let page = Arc::new(self.store.get_page(page_id));
-                // If cache is not full
-                if self.cache.read().unwrap().len() < CACHESIZE {
-                    // Insert it
-                    self.cache.write().unwrap().insert(index, (page_id, page.clone()));
-                } else {
-                    // Otherwise replace it
-                    let index = cmp::min(index, CACHESIZE - 1);
-                    self.cache.write().unwrap()[index] = (page_id, page.clone());
-                }
-                page
-            }
-        }
+impl<S: PageStore> PageCache for RamPageCache<S> {
+    fn get_page(&self, page_id: PageId) -> Result<Arc<Page>> {
+        self.shared.get_page(page_id)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::sync::{Arc, Barrier};
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::thread;
     use test_utils::create_test_dir;
 
-    use super::RamPageCache;
-    use page_manager::{BlockManager, FsPageManager, Page, PageCache, UnfullPage, PageId, Block,
-                       BlockId, BLOCKSIZE, PAGESIZE};
+    use super::{RamPageCache, DEFAULT_CAPACITY};
+    use error::Error;
+    use page_manager::{BlockManager, FsPageManager, MemPageStore, Page, PageCache, PageStore,
+                       UnfullPage,
+                       PageId, Block, BlockId, BLOCKSIZE, PAGESIZE};
 
 
     fn new_cache(name: &str) -> RamPageCache {
         let path = &create_test_dir(format!("ram_page_cache/{}", name).as_str());
-        let pmgr = FsPageManager::new(&path.join("pages.bin"));
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
         RamPageCache::new(pmgr)
     }
 
@@ -136,17 +494,17 @@ mod tests {
         assert_eq!(cache.store_block(Block([1; BLOCKSIZE])), PageId(0));
         assert_eq!(cache.store_block(Block([2; BLOCKSIZE])), PageId(1));
         assert_eq!(cache.store_block(Block([3; BLOCKSIZE])), PageId(2));
-        cache.store_in_place(PageId(0), BlockId(1), Block([15; BLOCKSIZE]));
-        cache.flush_page(PageId(0));
-        cache.flush_page(PageId(1));
-        cache.flush_page(PageId(2));
+        cache.store_in_place(PageId(0), BlockId(1), Block([15; BLOCKSIZE])).unwrap();
+        cache.flush_page(PageId(0)).unwrap();
+        cache.flush_page(PageId(1)).unwrap();
+        cache.flush_page(PageId(2)).unwrap();
         let mut p0 = Page::empty();
         p0[BlockId::first()] = Block([1; BLOCKSIZE]);
         p0[BlockId(1)] = Block([15; BLOCKSIZE]);
-        assert_eq!(cache.get_page(PageId(0)), Arc::new(p0));
+        assert_eq!(cache.get_page(PageId(0)).unwrap(), Arc::new(p0));
         let mut p2 = Page::empty();
         p2[BlockId::first()] = Block([3; BLOCKSIZE]);
-        assert_eq!(cache.get_page(PageId(2)), Arc::new(p2));
+        assert_eq!(cache.get_page(PageId(2)).unwrap(), Arc::new(p2));
     }
 
 
@@ -154,12 +512,12 @@ mod tests {
     fn basic_unfull() {
         let mut pmgr = new_cache("basic_unfull");
         assert_eq!(pmgr.store_block(Block([1; BLOCKSIZE])), PageId(0));
-        assert_eq!(pmgr.flush_unfull(PageId(0), BlockId(1)),
+        assert_eq!(pmgr.flush_unfull(PageId(0), BlockId(1)).unwrap(),
                    UnfullPage::new(PageId(0), BlockId(1), BlockId(2)));
         let mut p = Page::empty();
         p[BlockId::first()].0[0] = 1;
         p[BlockId(1)] = Block([1; BLOCKSIZE]);
-        assert_eq!(pmgr.get_page(PageId(0)), Arc::new(p));
+        assert_eq!(pmgr.get_page(PageId(0)).unwrap(), Arc::new(p));
     }
 
 
@@ -171,11 +529,11 @@ mod tests {
         for j in 1..PAGESIZE {
             cache.store_in_place(PageId(0),
                                  BlockId(j as u16),
-                                 Block([(j % 255) as u8; BLOCKSIZE]));
+                                 Block([(j % 255) as u8; BLOCKSIZE])).unwrap();
             ref_page[BlockId(j as u16)] = Block([(j % 255) as u8; BLOCKSIZE]);
         }
-        cache.flush_page(PageId(0));
-        assert_eq!(cache.get_page(PageId(0)), Arc::new(ref_page));
+        cache.flush_page(PageId(0)).unwrap();
+        assert_eq!(cache.get_page(PageId(0)).unwrap(), Arc::new(ref_page));
     }
 
     #[test]
@@ -184,12 +542,222 @@ mod tests {
         for i in 0..2048 {
             assert_eq!(cache.store_block(Block([(i % 255) as u8; BLOCKSIZE])),
                        PageId(i));
-            cache.flush_page(PageId(i));
+            cache.flush_page(PageId(i)).unwrap();
         }
         for i in 0..2048 {
             let mut p = Page::empty();
             p[BlockId::first()] = Block([(i % 255) as u8; BLOCKSIZE]);
-            assert_eq!(cache.get_page(PageId(i)), Arc::new(p));
+            assert_eq!(cache.get_page(PageId(i)).unwrap(), Arc::new(p));
+        }
+    }
+
+    fn cached_pages<S>(cache: &RamPageCache<S>) -> Vec<PageId> {
+        cache.shared.cache.read().unwrap().index.keys().cloned().collect()
+    }
+
+    fn small_cache(capacity: usize, pages: u64) -> RamPageCache<MemPageStore> {
+        let mut cache = RamPageCache::with_capacity(MemPageStore::new(), capacity);
+        for i in 0..pages {
+            cache.store_block(Block([i as u8; BLOCKSIZE]));
+            cache.flush_page(PageId(i)).unwrap();
+        }
+        cache
+    }
+
+    #[test]
+    fn capacity() {
+        assert_eq!(new_cache("capacity").capacity(), DEFAULT_CAPACITY);
+        let cache = small_cache(4, 16);
+        assert_eq!(cache.capacity(), 4);
+        for i in 0..16 {
+            assert_eq!(cache.get_page(PageId(i)).unwrap()[BlockId::first()],
+                       Block([i as u8; BLOCKSIZE]));
+            assert!(cached_pages(&cache).len() <= 4);
+        }
+        assert_eq!(cached_pages(&cache), vec![PageId(12), PageId(13), PageId(14), PageId(15)]);
+        // Caching disabled
+        let cache = small_cache(0, 2);
+        assert_eq!(cache.get_page(PageId(1)).unwrap()[BlockId::first()], Block([1; BLOCKSIZE]));
+        assert_eq!(cached_pages(&cache), vec![]);
+    }
+
+    #[test]
+    fn clock_eviction() {
+        let cache = small_cache(2, 4);
+        cache.get_page(PageId(0)).unwrap();
+        cache.get_page(PageId(1)).unwrap();
+        cache.get_page(PageId(1)).unwrap();
+        // Page 0 was read once, so it goes first
+        cache.get_page(PageId(2)).unwrap();
+        assert_eq!(cached_pages(&cache), vec![PageId(1), PageId(2)]);
+        // Page 1 got its second chance
+        cache.get_page(PageId(2)).unwrap();
+        cache.get_page(PageId(3)).unwrap();
+        assert_eq!(cached_pages(&cache), vec![PageId(2), PageId(3)]);
+    }
+
+    #[test]
+    fn invalidate() {
+        let mut cache = small_cache(3, 4);
+        for i in 0..3 {
+            cache.get_page(PageId(i)).unwrap();
+        }
+        cache.delete_page(PageId(0)).unwrap();
+        assert_eq!(cached_pages(&cache), vec![PageId(1), PageId(2)]);
+        // Deleted page is reused and must not be served from cache
+        cache.store_block(Block([7; BLOCKSIZE]));
+        assert_eq!(cache.flush_page(PageId(4)).unwrap(), PageId(0));
+        assert_eq!(cache.get_page(PageId(0)).unwrap()[BlockId::first()], Block([7; BLOCKSIZE]));
+        cache.get_page(PageId(3)).unwrap();
+        assert_eq!(cached_pages(&cache).len(), 3);
+    }
+
+    #[test]
+    fn batch() {
+        let mut cache = small_cache(3, 2);
+        cache.begin();
+        assert!(cache.in_batch());
+        // Deleted pages are not reused during the batch
+        cache.delete_page(PageId(0)).unwrap();
+        cache.store_block(Block([7; BLOCKSIZE]));
+        assert_eq!(cache.flush_page(PageId(2)).unwrap(), PageId(2));
+        cache.store_block(Block([8; BLOCKSIZE]));
+        cache.rollback_batch().unwrap();
+        assert!(!cache.in_batch());
+        assert_eq!(cache.get_page(PageId(0)).unwrap()[BlockId::first()], Block([0; BLOCKSIZE]));
+        assert!(cache.flush_page(PageId(3)).is_err());
+        // The page stored during the batch was freed
+        cache.store_block(Block([9; BLOCKSIZE]));
+        assert_eq!(cache.flush_page(PageId(4)).unwrap(), PageId(2));
+        cache.begin();
+        cache.delete_page(PageId(1)).unwrap();
+        cache.commit_batch().unwrap();
+        cache.store_block(Block([10; BLOCKSIZE]));
+        assert_eq!(cache.flush_page(PageId(5)).unwrap(), PageId(1));
+    }
+
+    #[test]
+    fn batch_restores_construction() {
+        let mut cache = small_cache(0, 0);
+        let started = cache.store_block(Block([1; BLOCKSIZE]));
+        let flushed = cache.store_block(Block([2; BLOCKSIZE]));
+        cache.begin();
+        cache.store_in_place(started, BlockId(1), Block([3; BLOCKSIZE])).unwrap();
+        cache.begin();
+        cache.store_in_place(started, BlockId(2), Block([4; BLOCKSIZE])).unwrap();
+        cache.commit_batch().unwrap();
+        cache.flush_page(flushed).unwrap();
+        cache.rollback_batch().unwrap();
+        // Both pages are under construction again, as before the batch
+        cache.store_in_place(flushed, BlockId(1), Block([5; BLOCKSIZE])).unwrap();
+        let page_id = cache.flush_page(started).unwrap();
+        let page = cache.get_page(page_id).unwrap();
+        assert_eq!(page[BlockId::first()], Block([1; BLOCKSIZE]));
+        assert_eq!(page[BlockId(1)], Block([0; BLOCKSIZE]));
+        assert_eq!(page[BlockId(2)], Block([0; BLOCKSIZE]));
+    }
+
+    #[test]
+    fn pin() {
+        let mut cache = small_cache(3, 3);
+        let reader = cache.reader();
+        let pin = cache.pin();
+        cache.delete_page(PageId(1)).unwrap();
+        let later = cache.pin();
+        cache.delete_page(PageId(2)).unwrap();
+        // Pinned pages are not reused and stay readable
+        cache.store_block(Block([7; BLOCKSIZE]));
+        assert_eq!(cache.flush_page(PageId(3)).unwrap(), PageId(3));
+        assert_eq!(reader.get_page(PageId(1)).unwrap()[BlockId::first()], Block([1; BLOCKSIZE]));
+        // Page 2 waits for the older pin as well
+        drop(later);
+        cache.reclaim().unwrap();
+        cache.store_block(Block([8; BLOCKSIZE]));
+        assert_eq!(cache.flush_page(PageId(4)).unwrap(), PageId(4));
+        drop(pin);
+        cache.reclaim().unwrap();
+        cache.store_block(Block([9; BLOCKSIZE]));
+        assert_eq!(cache.flush_page(PageId(5)).unwrap(), PageId(2));
+        assert_eq!(reader.get_page(PageId(2)).unwrap()[BlockId::first()], Block([9; BLOCKSIZE]));
+    }
+
+    #[test]
+    fn unknown_page() {
+        let mut cache = small_cache(3, 1);
+        assert!(matches!(cache.get_page(PageId(1)), Err(Error::UnknownPage(PageId(1)))));
+        assert!(matches!(cache.flush_page(PageId(1)), Err(Error::UnknownPage(PageId(1)))));
+        assert!(matches!(cache.store_in_place(PageId(1), BlockId::first(), Block::empty()),
+                         Err(Error::UnknownPage(PageId(1)))));
+    }
+
+    #[test]
+    fn stats() {
+        use std::sync::Mutex;
+        use page_manager::{CacheEvent, CacheStats, StoreStats};
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut cache = small_cache(1, 2);
+        {
+            let events = events.clone();
+            cache.set_listener(move |event| events.lock().unwrap().push(event));
+        }
+        cache.get_page(PageId(0)).unwrap();
+        cache.get_page(PageId(0)).unwrap();
+        cache.get_page(PageId(1)).unwrap();
+        cache.store_block(Block([2; BLOCKSIZE]));
+        cache.flush_page(PageId(2)).unwrap();
+        assert_eq!(*events.lock().unwrap(),
+                   vec![CacheEvent::Miss(PageId(0)),
+                        CacheEvent::Hit(PageId(0)),
+                        CacheEvent::Miss(PageId(1)),
+                        CacheEvent::Evict(PageId(0)),
+                        CacheEvent::Flush(PageId(2))]);
+        assert_eq!(cache.stats(),
+                   CacheStats {
+                       hits: 1,
+                       misses: 2,
+                       evictions: 1,
+                       cached_pages: 1,
+                       store: StoreStats::default(),
+                   });
+        assert_eq!(cache.stats().hit_ratio(), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn read_while_appending() {
+        // Holds one page. Reading page 0 evicts the page appended to
+        let mut cache = small_cache(1, 1);
+        let target = Arc::new(AtomicU64::new(1));
+        let stop = Arc::new(AtomicBool::new(false));
+        let barrier = Arc::new(Barrier::new(2));
+        let rounds = 10000;
+        let thread = {
+            let (reader, target, stop, barrier) =
+                (cache.reader(), target.clone(), stop.clone(), barrier.clone());
+            thread::spawn(move || {
+                for _ in 0..rounds {
+                    barrier.wait();
+                    while !stop.load(Ordering::SeqCst) {
+                        let _ = reader.get_page(PageId(target.load(Ordering::SeqCst)));
+                    }
+                    barrier.wait();
+                }
+            })
+        };
+        for round in 0..rounds {
+            stop.store(false, Ordering::SeqCst);
+            barrier.wait();
+            // The reader reads the unfull page the blocks are appended to
+            let page_id = cache.store_block(Block([round as u8; BLOCKSIZE]));
+            let unfull = cache.flush_unfull(page_id, BlockId(1)).unwrap();
+            stop.store(true, Ordering::SeqCst);
+            barrier.wait();
+            let page_id = unfull.page_id();
+            assert_eq!(*cache.get_page(page_id).unwrap(), cache.store().get_page(page_id).unwrap());
+            target.store(page_id.0, Ordering::SeqCst);
+            // So the reader misses it and reads it from the store next round
+            cache.get_page(PageId(0)).unwrap();
         }
+        thread.join().unwrap();
     }
 }
@@ -0,0 +1,81 @@
+use std::fmt;
+
+use page_manager::{PageId, UnfullPage};
+
+/// Counters of a `PageStore` since it was created or opened
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    /// Number of pages read from the medium
+    pub pages_read: u64,
+    /// Number of full and unfull pages written to the medium
+    pub pages_written: u64,
+    /// Number of times a page holding unfull pages was written again to
+    /// add or release an unfull page
+    pub unfull_rewrites: u64,
+    /// Size of the page file
+    pub bytes_on_disk: u64,
+}
+
+/// Counters of a `RamPageCache` since it was created
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of pages served from RAM
+    pub hits: u64,
+    /// Number of pages read from the store
+    pub misses: u64,
+    /// Number of pages dropped from RAM to make room for others
+    pub evictions: u64,
+    /// Number of pages currently held in RAM
+    pub cached_pages: usize,
+    pub store: StoreStats,
+}
+
+impl CacheStats {
+    /// Share of page requests served from RAM. 0 if there were none
+    pub fn hit_ratio(&self) -> f64 {
+        let requests = self.hits + self.misses;
+        if requests == 0 {
+            0.0
+        } else {
+            self.hits as f64 / requests as f64
+        }
+    }
+}
+
+/// Something that happened in a `RamPageCache`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheEvent {
+    /// A page was served from RAM
+    Hit(PageId),
+    /// A page was read from the store
+    Miss(PageId),
+    /// A page was dropped from RAM
+    Evict(PageId),
+    /// A full page was written to the store
+    Flush(PageId),
+    /// An unfull page was written to the store
+    FlushUnfull(UnfullPage),
+}
+
+/// Receives the events of a `RamPageCache` as they happen, e.g. to forward
+/// them to a metrics system.
+///
+/// Called while pages are read, so implementations should be cheap.
+/// Implemented for closures.
+pub trait CacheListener: Send + Sync {
+    fn on_event(&self, event: CacheEvent);
+}
+
+impl<F> CacheListener for F
+    where F: Fn(CacheEvent) + Send + Sync
+{
+    fn on_event(&self, event: CacheEvent) {
+        self(event)
+    }
+}
+
+impl fmt::Debug for dyn CacheListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CacheListener")
+    }
+}
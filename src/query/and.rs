@@ -0,0 +1,208 @@
+use std::cmp;
+
+use index::posting::Posting;
+use utils::seeking_iterator::SeekingIterator;
+use query::QueryIterator;
+
+/// Yields the postings that are contained in both operands
+#[derive(Clone, Debug)]
+pub struct And<A, B> {
+    a: A,
+    b: B,
+}
+
+impl<A: QueryIterator, B: QueryIterator> And<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        And { a, b }
+    }
+
+    /// Leapfrogs both operands until they agree on a posting >= target
+    fn align(&mut self, mut target: Posting) -> Option<Posting> {
+        loop {
+            let b = self.b.next_seek(&target)?;
+            if b.doc_id() == target.doc_id() {
+                return Some(target);
+            }
+            target = self.a.next_seek(&b)?;
+            if target.doc_id() == b.doc_id() {
+                return Some(target);
+            }
+        }
+    }
+}
+
+impl<A: QueryIterator, B: QueryIterator> Iterator for And<A, B> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Posting> {
+        let target = self.a.next()?;
+        self.align(target)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let upper = match (self.a.size_hint().1, self.b.size_hint().1) {
+            (Some(a), Some(b)) => Some(cmp::min(a, b)),
+            (a, b) => a.or(b),
+        };
+        (0, upper)
+    }
+}
+
+impl<A: QueryIterator, B: QueryIterator> SeekingIterator for And<A, B> {
+    type Item = Posting;
+
+    fn next_seek(&mut self, other: &Posting) -> Option<Posting> {
+        let target = self.a.next_seek(other)?;
+        self.align(target)
+    }
+}
+
+/// Yields the postings that are contained in all operands.
+///
+/// Operands are ordered by their length, so the shortest one drives the
+/// intersection.
+#[derive(Clone, Debug)]
+pub struct NAryAnd<I> {
+    operands: Vec<I>,
+}
+
+impl<I: QueryIterator> NAryAnd<I> {
+    pub fn new(mut operands: Vec<I>) -> Self {
+        operands.sort_by_key(|operand| operand.size_hint().1.unwrap_or(usize::MAX));
+        NAryAnd { operands }
+    }
+
+    fn align(&mut self, target: Posting) -> Option<Posting> {
+        align(&mut self.operands, target)
+    }
+}
+
+/// Leapfrogs all operands until they agree on a posting >= target.
+/// `target` has to be proposed by the first operand.
+pub fn align<I: QueryIterator>(operands: &mut [I], mut target: Posting) -> Option<Posting> {
+    // Index of the operand that proposed the current target
+    let mut proposer = 0;
+    let mut i = 1 % operands.len();
+    while i != proposer {
+        let posting = operands[i].next_seek(&target)?;
+        if posting.doc_id() != target.doc_id() {
+            // Overshot. Every other operand has to catch up
+            target = posting;
+            proposer = i;
+        }
+        i = (i + 1) % operands.len();
+    }
+    Some(target)
+}
+
+impl<I: QueryIterator> Iterator for NAryAnd<I> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Posting> {
+        let target = self.operands.first_mut()?.next()?;
+        self.align(target)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.operands.first() {
+            Some(operand) => (0, operand.size_hint().1),
+            None => (0, Some(0)),
+        }
+    }
+}
+
+impl<I: QueryIterator> SeekingIterator for NAryAnd<I> {
+    type Item = Posting;
+
+    fn next_seek(&mut self, other: &Posting) -> Option<Posting> {
+        let target = self.operands.first_mut()?.next_seek(other)?;
+        self.align(target)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use test_utils::create_test_dir;
+
+    use super::{And, NAryAnd};
+    use index::Index;
+    use index::posting::{Posting, DocId};
+    use index::vocabulary::SharedVocabulary;
+    use page_manager::{FsPageManager, RamPageCache};
+    use utils::seeking_iterator::SeekingIterator;
+
+    fn new_index(name: &str) -> Index<usize> {
+        let path = &create_test_dir(format!("query_and/{}", name).as_str());
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        let mut index = Index::<usize>::new(RamPageCache::new(pmgr), SharedVocabulary::new());
+        // Term t is contained in every doc that is a multiple of t
+        for i in 0..10_000 {
+            index.index_document((2..20).filter(|t| i % t == 0), None).unwrap();
+        }
+        index.commit().unwrap();
+        index
+    }
+
+    fn multiples_of(n: u32) -> Vec<Posting> {
+        (0..10_000).filter(|i| i % n == 0).map(|i| Posting(DocId(i), 1)).collect()
+    }
+
+    #[test]
+    fn basic() {
+        let index = new_index("basic");
+        let and = And::new(index.query_atom(&2).1, index.query_atom(&3).1);
+        assert_eq!(and.collect::<Vec<_>>(), multiples_of(6));
+        let and = And::new(index.query_atom(&17).1, index.query_atom(&2).1);
+        assert_eq!(and.collect::<Vec<_>>(), multiples_of(34));
+    }
+
+    #[test]
+    fn empty() {
+        let index = new_index("empty");
+        let and = And::new(index.query_atom(&2).1, index.query_atom(&100).1);
+        assert_eq!(and.collect::<Vec<_>>(), vec![]);
+    }
+
+    #[test]
+    fn seeking() {
+        let index = new_index("seeking");
+        let mut and = And::new(index.query_atom(&4).1, index.query_atom(&6).1);
+        assert_eq!(and.next(), Some(Posting(DocId(0), 1)));
+        assert_eq!(and.next_seek(&Posting(DocId(5000), 1)), Some(Posting(DocId(5004), 1)));
+        assert_eq!(and.next(), Some(Posting(DocId(5016), 1)));
+        assert_eq!(and.next_seek(&Posting(DocId(10_000), 1)), None);
+    }
+
+    #[test]
+    fn n_ary() {
+        let index = new_index("n_ary");
+        let and = NAryAnd::new(vec![index.query_atom(&2).1,
+                                    index.query_atom(&3).1,
+                                    index.query_atom(&5).1,
+                                    index.query_atom(&7).1]);
+        assert_eq!(and.collect::<Vec<_>>(), multiples_of(210));
+        let and = NAryAnd::new(vec![index.query_atom(&4).1, index.query_atom(&6).1]);
+        assert_eq!(and.collect::<Vec<_>>(), multiples_of(12));
+        let and = NAryAnd::new(vec![index.query_atom(&19).1]);
+        assert_eq!(and.collect::<Vec<_>>(), multiples_of(19));
+    }
+
+    #[test]
+    fn term_frequencies() {
+        let path = &create_test_dir("query_and/term_frequencies");
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        let mut index = Index::<usize>::new(RamPageCache::new(pmgr), SharedVocabulary::new());
+        for i in 0..100 {
+            // Term 1 occurs i % 3 + 1 times
+            index.index_document((0..i % 3 + 2).map(|t| t.min(1)), None).unwrap();
+        }
+        index.commit().unwrap();
+        // Operands agree on documents, whatever their term frequencies
+        let and = And::new(index.query_atom(&1).1, index.query_atom(&0).1);
+        assert_eq!(and.map(|p| p.tf()).collect::<Vec<_>>(),
+                   (0..100).map(|i| i % 3 + 1).collect::<Vec<_>>());
+        let and = NAryAnd::new(vec![index.query_atom(&0).1, index.query_atom(&1).1]);
+        assert_eq!(and.count(), 100);
+    }
+}
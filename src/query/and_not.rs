@@ -0,0 +1,99 @@
+use index::posting::Posting;
+use utils::seeking_iterator::{SeekingIterator, PeekableSeekable};
+use query::QueryIterator;
+
+/// Yields the postings of the first operand that are not contained in the
+/// second one
+#[derive(Clone, Debug)]
+pub struct AndNot<A, B: QueryIterator> {
+    a: A,
+    not: PeekableSeekable<B>,
+}
+
+impl<A: QueryIterator, B: QueryIterator> AndNot<A, B> {
+    pub fn new(a: A, not: B) -> Self {
+        AndNot {
+            a,
+            not: PeekableSeekable::new(not),
+        }
+    }
+
+    fn filter(&mut self, mut candidate: Posting) -> Option<Posting> {
+        while self.not.peek_from(&candidate).map(Posting::doc_id) == Some(candidate.doc_id()) {
+            candidate = self.a.next()?;
+        }
+        Some(candidate)
+    }
+}
+
+impl<A: QueryIterator, B: QueryIterator> Iterator for AndNot<A, B> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Posting> {
+        let candidate = self.a.next()?;
+        self.filter(candidate)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.a.size_hint().1)
+    }
+}
+
+impl<A: QueryIterator, B: QueryIterator> SeekingIterator for AndNot<A, B> {
+    type Item = Posting;
+
+    fn next_seek(&mut self, other: &Posting) -> Option<Posting> {
+        let candidate = self.a.next_seek(other)?;
+        self.filter(candidate)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use test_utils::create_test_dir;
+
+    use super::AndNot;
+    use index::Index;
+    use index::posting::{Posting, DocId};
+    use index::vocabulary::SharedVocabulary;
+    use page_manager::{FsPageManager, RamPageCache};
+    use utils::seeking_iterator::SeekingIterator;
+
+    fn new_index(name: &str) -> Index<usize> {
+        let path = &create_test_dir(format!("query_and_not/{}", name).as_str());
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        let mut index = Index::<usize>::new(RamPageCache::new(pmgr), SharedVocabulary::new());
+        // Term t is contained in every doc that is a multiple of t
+        for i in 0..10_000 {
+            index.index_document((2..20).filter(|t| i % t == 0), None).unwrap();
+        }
+        index.commit().unwrap();
+        index
+    }
+
+    #[test]
+    fn basic() {
+        let index = new_index("basic");
+        let and_not = AndNot::new(index.query_atom(&2).1, index.query_atom(&3).1);
+        assert_eq!(and_not.collect::<Vec<_>>(),
+                   (0..10_000)
+                       .filter(|i| i % 2 == 0 && i % 3 != 0)
+                       .map(|i| Posting(DocId(i), 1))
+                       .collect::<Vec<_>>());
+        let and_not = AndNot::new(index.query_atom(&4).1, index.query_atom(&2).1);
+        assert_eq!(and_not.collect::<Vec<_>>(), vec![]);
+        let and_not = AndNot::new(index.query_atom(&19).1, index.query_atom(&100).1);
+        assert_eq!(and_not.count(), 527);
+    }
+
+    #[test]
+    fn seeking() {
+        let index = new_index("seeking");
+        let mut and_not = AndNot::new(index.query_atom(&5).1, index.query_atom(&2).1);
+        assert_eq!(and_not.next(), Some(Posting(DocId(5), 1)));
+        assert_eq!(and_not.next_seek(&Posting(DocId(5000), 1)), Some(Posting(DocId(5005), 1)));
+        assert_eq!(and_not.next(), Some(Posting(DocId(5015), 1)));
+        assert_eq!(and_not.next_seek(&Posting(DocId(9996), 1)), None);
+    }
+}
@@ -0,0 +1,109 @@
+//! Boolean query operators over posting iterators.
+//!
+//! Every operator takes iterators that yield `Posting`s in ascending order
+//! and can seek (see `SeekingIterator`). Every operator is such an iterator
+//! itself. So operators nest and are evaluated lazily:
+//!
+//! ```rust,ignore
+//! // (a AND b) OR (c AND NOT d)
+//! let query = Or::new(And::new(a, b), AndNot::new(c, d));
+//! ```
+//!
+//! Intersections leapfrog through their operands with `next_seek`. This
+//! skips whole blocks of the underlying listings.
+//!
+//! Operators with differently typed operands can be combined through
+//! `Box<QueryIterator>`.
+//!
+//! `Phrase` and `Near` match on the positions of terms. They need the
+//! `PositionalIterator`s of a positional index.
+use index::posting::Posting;
+use utils::seeking_iterator::SeekingIterator;
+
+pub use query::and::{And, NAryAnd};
+pub use query::or::{Or, NAryOr};
+pub use query::and_not::AndNot;
+pub use query::phrase::{Phrase, Near};
+
+mod and;
+mod or;
+mod and_not;
+mod phrase;
+
+/// An iterator over postings in ascending order that can seek.
+/// This is what query operators consume and yield.
+pub trait QueryIterator: Iterator<Item = Posting> + SeekingIterator<Item = Posting> {}
+
+impl<T> QueryIterator for T where T: Iterator<Item = Posting> + SeekingIterator<Item = Posting> {}
+
+
+#[cfg(test)]
+mod tests {
+    use test_utils::create_test_dir;
+
+    use super::{QueryIterator, And, Or, AndNot, NAryOr};
+    use index::Index;
+    use index::posting::{Posting, DocId};
+    use index::vocabulary::SharedVocabulary;
+    use page_manager::{FsPageManager, RamPageCache};
+
+    fn new_index(name: &str) -> Index<usize> {
+        let path = &create_test_dir(format!("query/{}", name).as_str());
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        Index::<usize>::new(RamPageCache::new(pmgr), SharedVocabulary::new())
+    }
+
+    #[test]
+    fn nested() {
+        let mut index = new_index("nested");
+        // Term 2 is in every second doc, term 3 in every third...
+        for i in 0..1000 {
+            index.index_document((2..10).filter(|t| i % t == 0), None).unwrap();
+        }
+        index.commit().unwrap();
+        // (2 AND 3) OR (5 AND NOT 7)
+        let query = Or::new(And::new(index.query_atom(&2).1, index.query_atom(&3).1),
+                            AndNot::new(index.query_atom(&5).1, index.query_atom(&7).1));
+        assert_eq!(query.collect::<Vec<_>>(),
+                   (0..1000)
+                       .filter(|i| (i % 2 == 0 && i % 3 == 0) || (i % 5 == 0 && i % 7 != 0))
+                       .map(|i| Posting(DocId(i), 1))
+                       .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn boxed() {
+        let mut index = new_index("boxed");
+        for i in 0..1000 {
+            index.index_document((2..10).filter(|t| i % t == 0), None).unwrap();
+        }
+        index.commit().unwrap();
+        // 4 OR (3 AND 5) OR (9 AND NOT 2)
+        let operands: Vec<Box<dyn QueryIterator>> =
+            vec![Box::new(index.query_atom(&4).1),
+                 Box::new(And::new(index.query_atom(&3).1, index.query_atom(&5).1)),
+                 Box::new(AndNot::new(index.query_atom(&9).1, index.query_atom(&2).1))];
+        assert_eq!(NAryOr::new(operands).collect::<Vec<_>>(),
+                   (0..1000)
+                       .filter(|i| i % 4 == 0 || i % 15 == 0 || (i % 9 == 0 && i % 2 != 0))
+                       .map(|i| Posting(DocId(i), 1))
+                       .collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn deleted_documents() {
+        let mut index = new_index("deleted_documents");
+        for i in 0..1000 {
+            index.index_document((2..10).filter(|t| i % t == 0), None).unwrap();
+        }
+        index.commit().unwrap();
+        index.delete_document(DocId(6));
+        index.delete_document(DocId(600));
+        let query = And::new(index.query_atom(&2).1, index.query_atom(&3).1);
+        assert_eq!(query.collect::<Vec<_>>(),
+                   (0..1000)
+                       .filter(|i| i % 6 == 0 && *i != 6 && *i != 600)
+                       .map(|i| Posting(DocId(i), 1))
+                       .collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,175 @@
+use index::posting::Posting;
+use utils::seeking_iterator::{SeekingIterator, PeekableSeekable};
+use query::QueryIterator;
+
+/// Yields the postings that are contained in any of the operands
+#[derive(Clone, Debug)]
+pub struct Or<A: QueryIterator, B: QueryIterator> {
+    a: PeekableSeekable<A>,
+    b: PeekableSeekable<B>,
+}
+
+impl<A: QueryIterator, B: QueryIterator> Or<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        Or {
+            a: PeekableSeekable::new(a),
+            b: PeekableSeekable::new(b),
+        }
+    }
+}
+
+impl<A: QueryIterator, B: QueryIterator> Iterator for Or<A, B> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Posting> {
+        let a = self.a.peek().cloned();
+        let b = self.b.peek().cloned();
+        match (a, b) {
+            (Some(a), Some(b)) => {
+                if a.doc_id() <= b.doc_id() {
+                    if a.doc_id() == b.doc_id() {
+                        self.b.next();
+                    }
+                    self.a.next()
+                } else {
+                    self.b.next()
+                }
+            }
+            (Some(_), None) => self.a.next(),
+            (None, Some(_)) => self.b.next(),
+            (None, None) => None,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (a_lower, a_upper) = self.a.size_hint();
+        let (b_lower, b_upper) = self.b.size_hint();
+        let upper = match (a_upper, b_upper) {
+            (Some(a), Some(b)) => a.checked_add(b),
+            _ => None,
+        };
+        (a_lower.max(b_lower), upper)
+    }
+}
+
+impl<A: QueryIterator, B: QueryIterator> SeekingIterator for Or<A, B> {
+    type Item = Posting;
+
+    fn next_seek(&mut self, other: &Posting) -> Option<Posting> {
+        self.a.peek_from(other);
+        self.b.peek_from(other);
+        self.next()
+    }
+}
+
+/// Yields the postings that are contained in any of the operands
+#[derive(Clone, Debug)]
+pub struct NAryOr<I: QueryIterator> {
+    operands: Vec<PeekableSeekable<I>>,
+}
+
+impl<I: QueryIterator> NAryOr<I> {
+    pub fn new(operands: Vec<I>) -> Self {
+        NAryOr { operands: operands.into_iter().map(PeekableSeekable::new).collect() }
+    }
+}
+
+impl<I: QueryIterator> Iterator for NAryOr<I> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Posting> {
+        let min = self.operands
+            .iter_mut()
+            .filter_map(|operand| operand.peek().cloned())
+            .min_by_key(Posting::doc_id)?;
+        // Every operand that contains the minimum has to move on
+        for operand in &mut self.operands {
+            if operand.peek().map(Posting::doc_id) == Some(min.doc_id()) {
+                operand.next();
+            }
+        }
+        Some(min)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.operands.iter().fold((0, Some(0)), |(lower, upper), operand| {
+            let (op_lower, op_upper) = operand.size_hint();
+            (lower.max(op_lower),
+             upper.and_then(|upper| op_upper.and_then(|op_upper| upper.checked_add(op_upper))))
+        })
+    }
+}
+
+impl<I: QueryIterator> SeekingIterator for NAryOr<I> {
+    type Item = Posting;
+
+    fn next_seek(&mut self, other: &Posting) -> Option<Posting> {
+        for operand in &mut self.operands {
+            operand.peek_from(other);
+        }
+        self.next()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use test_utils::create_test_dir;
+
+    use super::{Or, NAryOr};
+    use index::Index;
+    use index::posting::{Posting, DocId};
+    use index::vocabulary::SharedVocabulary;
+    use page_manager::{FsPageManager, RamPageCache};
+    use utils::seeking_iterator::SeekingIterator;
+
+    fn new_index(name: &str) -> Index<usize> {
+        let path = &create_test_dir(format!("query_or/{}", name).as_str());
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        let mut index = Index::<usize>::new(RamPageCache::new(pmgr), SharedVocabulary::new());
+        // Term t is contained in every doc that is a multiple of t
+        for i in 0..10_000 {
+            index.index_document((2..20).filter(|t| i % t == 0), None).unwrap();
+        }
+        index.commit().unwrap();
+        index
+    }
+
+    fn multiples_of_any(n: &[u32]) -> Vec<Posting> {
+        (0..10_000).filter(|i| n.iter().any(|n| i % n == 0)).map(|i| Posting(DocId(i), 1)).collect()
+    }
+
+    #[test]
+    fn basic() {
+        let index = new_index("basic");
+        let or = Or::new(index.query_atom(&2).1, index.query_atom(&3).1);
+        assert_eq!(or.collect::<Vec<_>>(), multiples_of_any(&[2, 3]));
+        let or = Or::new(index.query_atom(&17).1, index.query_atom(&100).1);
+        assert_eq!(or.collect::<Vec<_>>(), multiples_of_any(&[17]));
+    }
+
+    #[test]
+    fn seeking() {
+        let index = new_index("seeking");
+        let mut or = Or::new(index.query_atom(&13).1, index.query_atom(&17).1);
+        assert_eq!(or.next(), Some(Posting(DocId(0), 1)));
+        assert_eq!(or.next(), Some(Posting(DocId(13), 1)));
+        assert_eq!(or.next_seek(&Posting(DocId(5000), 1)), Some(Posting(DocId(5005), 1)));
+        assert_eq!(or.next(), Some(Posting(DocId(5015), 1)));
+        assert_eq!(or.next_seek(&Posting(DocId(5016), 1)), Some(Posting(DocId(5018), 1)));
+        assert_eq!(or.next_seek(&Posting(DocId(10_000), 1)), None);
+    }
+
+    #[test]
+    fn n_ary() {
+        let index = new_index("n_ary");
+        let or = NAryOr::new(vec![index.query_atom(&11).1,
+                                  index.query_atom(&13).1,
+                                  index.query_atom(&17).1,
+                                  index.query_atom(&100).1]);
+        assert_eq!(or.collect::<Vec<_>>(), multiples_of_any(&[11, 13, 17]));
+        let mut or = NAryOr::new(vec![index.query_atom(&11).1, index.query_atom(&13).1]);
+        assert_eq!(or.next_seek(&Posting(DocId(100), 1)), Some(Posting(DocId(104), 1)));
+        assert_eq!(or.next(), Some(Posting(DocId(110), 1)));
+    }
+}
@@ -0,0 +1,236 @@
+use index::posting::Posting;
+use index::positions::PositionalIterator;
+use utils::seeking_iterator::SeekingIterator;
+use query::and::align;
+
+/// Yields the postings of the documents that contain the operands as an
+/// exact phrase: In the given order and at consecutive positions.
+///
+/// Needs the operands of a positional index. See `Index::query_positional`.
+#[derive(Clone, Debug)]
+pub struct Phrase<'a> {
+    operands: Vec<PositionalIterator<'a>>,
+}
+
+impl<'a> Phrase<'a> {
+    pub fn new(operands: Vec<PositionalIterator<'a>>) -> Self {
+        Phrase { operands }
+    }
+}
+
+impl<'a> Iterator for Phrase<'a> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Posting> {
+        let candidate = self.operands.first_mut()?.next();
+        find(&mut self.operands, candidate, is_phrase)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        size_hint(&self.operands)
+    }
+}
+
+impl<'a> SeekingIterator for Phrase<'a> {
+    type Item = Posting;
+
+    fn next_seek(&mut self, other: &Posting) -> Option<Posting> {
+        let candidate = self.operands.first_mut()?.next_seek(other);
+        find(&mut self.operands, candidate, is_phrase)
+    }
+}
+
+/// Yields the postings of the documents that contain all operands close to
+/// each other, in any order.
+///
+/// The operands have to fit into a window of `operands + slop` positions.
+/// So a slop of 0 allows no other term in between them.
+#[derive(Clone, Debug)]
+pub struct Near<'a> {
+    operands: Vec<PositionalIterator<'a>>,
+    slop: u32,
+}
+
+impl<'a> Near<'a> {
+    pub fn new(operands: Vec<PositionalIterator<'a>>, slop: u32) -> Self {
+        Near { operands, slop }
+    }
+}
+
+impl<'a> Iterator for Near<'a> {
+    type Item = Posting;
+
+    fn next(&mut self) -> Option<Posting> {
+        let candidate = self.operands.first_mut()?.next();
+        let slop = self.slop;
+        find(&mut self.operands, candidate, |positions| is_near(positions, slop))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        size_hint(&self.operands)
+    }
+}
+
+impl<'a> SeekingIterator for Near<'a> {
+    type Item = Posting;
+
+    fn next_seek(&mut self, other: &Posting) -> Option<Posting> {
+        let candidate = self.operands.first_mut()?.next_seek(other);
+        let slop = self.slop;
+        find(&mut self.operands, candidate, |positions| is_near(positions, slop))
+    }
+}
+
+/// Intersects the operands starting at `candidate` until the positions of
+/// a common document are accepted by `matches`
+fn find<F>(operands: &mut [PositionalIterator], mut candidate: Option<Posting>, matches: F) -> Option<Posting>
+    where F: Fn(&[Vec<u32>]) -> bool
+{
+    loop {
+        let posting = align(operands, candidate?)?;
+        let positions = operands.iter_mut()
+            .map(|operand| operand.positions(posting.doc_id()))
+            .collect::<Option<Vec<_>>>();
+        if positions.is_some_and(|positions| matches(&positions)) {
+            return Some(posting);
+        }
+        candidate = operands[0].next();
+    }
+}
+
+fn size_hint(operands: &[PositionalIterator]) -> (usize, Option<usize>) {
+    match operands.first() {
+        Some(operand) => (0, operand.size_hint().1),
+        None => (0, Some(0)),
+    }
+}
+
+/// Does the i-th operand occur at start + i for some start?
+fn is_phrase(positions: &[Vec<u32>]) -> bool {
+    positions[0].iter().any(|&start| {
+        positions.iter()
+            .enumerate()
+            .skip(1)
+            .all(|(i, positions)| positions.binary_search(&(start + i as u32)).is_ok())
+    })
+}
+
+/// Is there a window of `positions.len() + slop` positions containing every
+/// operand?
+fn is_near(positions: &[Vec<u32>], slop: u32) -> bool {
+    if positions.iter().any(|positions| positions.is_empty()) {
+        return false;
+    }
+    let width = positions.len() as u32 - 1 + slop;
+    // Slide a window over the positions by always advancing the operand
+    // with the smallest position
+    let mut heads = vec![0; positions.len()];
+    loop {
+        let (min_operand, min) = heads.iter()
+            .enumerate()
+            .map(|(i, head)| (i, positions[i][*head]))
+            .min_by_key(|&(_, position)| position)
+            .unwrap();
+        let max = heads.iter().enumerate().map(|(i, head)| positions[i][*head]).max().unwrap();
+        if max - min <= width {
+            return true;
+        }
+        heads[min_operand] += 1;
+        if heads[min_operand] == positions[min_operand].len() {
+            return false;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use test_utils::create_test_dir;
+
+    use super::{Phrase, Near};
+    use index::Index;
+    use index::posting::{Posting, DocId};
+    use index::vocabulary::SharedVocabulary;
+    use page_manager::{FsPageManager, RamPageCache};
+    use utils::seeking_iterator::SeekingIterator;
+
+    fn new_index(name: &str) -> Index<&'static str> {
+        let path = &create_test_dir(format!("query_phrase/{}", name).as_str());
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        let mut index = Index::new_positional(RamPageCache::new(pmgr), SharedVocabulary::new());
+        for doc in &["the quick brown fox",
+                     "the brown quick fox",
+                     "quick brown",
+                     "the fox is quick and brown",
+                     "brown fox brown quick fox"] {
+            index.index_document(doc.split(' '), None).unwrap();
+        }
+        index.commit().unwrap();
+        index
+    }
+
+    fn docs<I: Iterator<Item = Posting>>(iter: I) -> Vec<u32> {
+        iter.map(|posting| posting.doc_id().0).collect()
+    }
+
+    #[test]
+    fn phrase() {
+        let index = new_index("phrase");
+        let terms = |terms: &[&'static str]| {
+            terms.iter().map(|term| index.query_positional(term).unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(docs(Phrase::new(terms(&["quick", "brown"]))), vec![0, 2]);
+        assert_eq!(docs(Phrase::new(terms(&["the", "quick", "brown"]))), vec![0]);
+        assert_eq!(docs(Phrase::new(terms(&["brown", "fox"]))), vec![0, 4]);
+        assert_eq!(docs(Phrase::new(terms(&["fox"]))), vec![0, 1, 3, 4]);
+        assert_eq!(docs(Phrase::new(terms(&["fox", "the"]))), vec![]);
+        assert_eq!(docs(Phrase::new(terms(&["quick", "unknown"]))), vec![]);
+        assert_eq!(docs(Phrase::new(vec![])), vec![]);
+        let mut phrase = Phrase::new(terms(&["brown", "fox"]));
+        assert_eq!(phrase.next_seek(&Posting(DocId(1), 0)), Some(Posting(DocId(4), 2)));
+    }
+
+    #[test]
+    fn near() {
+        let index = new_index("near");
+        let terms = |terms: &[&'static str]| {
+            terms.iter().map(|term| index.query_positional(term).unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(docs(Near::new(terms(&["quick", "brown"]), 0)), vec![0, 1, 2, 4]);
+        assert_eq!(docs(Near::new(terms(&["quick", "brown"]), 1)), vec![0, 1, 2, 3, 4]);
+        assert_eq!(docs(Near::new(terms(&["the", "fox"]), 1)), vec![3]);
+        assert_eq!(docs(Near::new(terms(&["the", "fox"]), 2)), vec![0, 1, 3]);
+    }
+
+    #[test]
+    fn many_documents() {
+        let path = &create_test_dir("query_phrase/many_documents");
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        let mut index = Index::<u32>::new_positional(RamPageCache::new(pmgr), SharedVocabulary::new());
+        // Every document contains 1 and 2. They are adjacent in every third one
+        for i in 0..3000u32 {
+            let gap = if i % 3 == 0 { 0 } else { i % 7 + 1 };
+            let doc = (0..i % 11).map(|t| t + 100)
+                .chain(Some(1))
+                .chain((0..gap).map(|t| t + 100))
+                .chain(Some(2));
+            index.index_document(doc, None).unwrap();
+        }
+        index.commit().unwrap();
+        index.delete_document(DocId(300));
+        let phrase = Phrase::new(vec![index.query_positional(&1).unwrap(),
+                                      index.query_positional(&2).unwrap()]);
+        assert_eq!(docs(phrase),
+                   (0..3000).filter(|i| i % 3 == 0 && *i != 300).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn not_positional() {
+        let path = &create_test_dir("query_phrase/not_positional");
+        let pmgr = FsPageManager::new(&path.join("pages.bin")).unwrap();
+        let mut index = Index::<u32>::new(RamPageCache::new(pmgr), SharedVocabulary::new());
+        index.index_document(0..10, None).unwrap();
+        index.commit().unwrap();
+        assert!(index.query_positional(&1).is_none());
+    }
+}
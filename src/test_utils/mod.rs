@@ -1,6 +1,12 @@
 use std::fs;
 use std::env::temp_dir;
+use std::io;
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use error::{Error, Result};
+use page_manager::{PageStore, MemPageStore, Page, PageId, BlockId, UnfullPage};
 
 const TEST_FOLDER: &'static str = "perlin-core_tests";
 
@@ -14,3 +20,57 @@ pub fn create_test_dir(dir: &str) -> PathBuf {
     fs::create_dir_all(&path).unwrap();
     path
 }
+
+/// A `MemPageStore` whose writes fail on demand
+pub struct FailingStore {
+    store: MemPageStore,
+    writes_left: Arc<AtomicUsize>,
+}
+
+impl FailingStore {
+    /// Returns the store and the number of writes that still succeed.
+    /// Unlimited until it is set
+    pub fn new() -> (Self, Arc<AtomicUsize>) {
+        let writes_left = Arc::new(AtomicUsize::new(usize::MAX));
+        let store = FailingStore {
+            store: MemPageStore::new(),
+            writes_left: writes_left.clone(),
+        };
+        (store, writes_left)
+    }
+
+    fn write(&self) -> Result<()> {
+        let left = self.writes_left.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+            n.checked_sub(1)
+        });
+        match left {
+            Ok(_) => Ok(()),
+            Err(_) => Err(Error::Io(io::Error::other("injected write failure"))),
+        }
+    }
+}
+
+impl PageStore for FailingStore {
+    fn store_unfull(&mut self, page: Page, block_id: BlockId) -> Result<UnfullPage> {
+        self.write()?;
+        self.store.store_unfull(page, block_id)
+    }
+
+    fn store_full(&mut self, page: Page) -> Result<PageId> {
+        self.write()?;
+        self.store.store_full(page)
+    }
+
+    fn get_page(&self, page_id: PageId) -> Result<Page> {
+        self.store.get_page(page_id)
+    }
+
+    fn delete_page(&mut self, page_id: PageId) -> Result<()> {
+        self.store.delete_page(page_id)
+    }
+
+    fn delete_unfull(&mut self, page_id: PageId) -> Result<()> {
+        self.write()?;
+        self.store.delete_unfull(page_id)
+    }
+}
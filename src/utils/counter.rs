@@ -1,3 +1,7 @@
+use std::io::{self, Read, Write};
+
+use utils::persistence::Persistent;
+
 #[derive(Debug)]
 pub struct Counter(u64);
 
@@ -16,3 +20,13 @@ impl Counter {
         self.0
     }
 }
+
+impl Persistent for Counter {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.0.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok(Counter(u64::read_from(source)?))
+    }
+}
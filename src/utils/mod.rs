@@ -2,6 +2,8 @@ pub mod progress;
 pub mod seeking_iterator;
 pub mod counter;
 pub mod ring_buffer;
+pub mod persistence;
+pub mod crc32c;
 
 pub trait Baseable<T> {
     fn add_base(&mut self, T);
@@ -0,0 +1,175 @@
+//! This module provides the trait `Persistent`.
+//!
+//! Types implementing `Persistent` can be written to and read back from any
+//! `io::Write` or `io::Read`. It is used to store the metadata of an index
+//! (listings, vocabulary, page store state) next to its pages.
+//! All numbers are written in little endian byte order.
+use std::io::{self, Read, Write};
+
+/// Collections read are preallocated for at most this many elements. Their
+/// length is read from the source, which may be corrupt
+pub const MAX_PREALLOCATION: usize = 4096;
+
+/// Trait for types that can be written to and restored from a byte stream
+pub trait Persistent: Sized {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()>;
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self>;
+}
+
+macro_rules! persistent_number {
+    ($t:ty) => {
+        impl Persistent for $t {
+            #[inline]
+            fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+                target.write_all(&self.to_le_bytes())
+            }
+
+            #[inline]
+            fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+                let mut bytes = [0u8; ::std::mem::size_of::<$t>()];
+                source.read_exact(&mut bytes)?;
+                Ok(<$t>::from_le_bytes(bytes))
+            }
+        }
+    };
+}
+
+persistent_number!(u8);
+persistent_number!(u16);
+persistent_number!(u32);
+persistent_number!(u64);
+
+impl Persistent for usize {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        (*self as u64).write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok(u64::read_from(source)? as usize)
+    }
+}
+
+impl Persistent for bool {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        (*self as u8).write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok(u8::read_from(source)? != 0)
+    }
+}
+
+impl Persistent for String {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.len().write_to(target)?;
+        target.write_all(self.as_bytes())
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        let len = usize::read_from(source)?;
+        let mut bytes = Vec::with_capacity(len.min(MAX_PREALLOCATION));
+        source.take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() != len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "string cut off"));
+        }
+        String::from_utf8(bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl<T: Persistent> Persistent for Vec<T> {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.len().write_to(target)?;
+        for element in self {
+            element.write_to(target)?;
+        }
+        Ok(())
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        let len = usize::read_from(source)?;
+        let mut result = Vec::with_capacity(len.min(MAX_PREALLOCATION));
+        for _ in 0..len {
+            result.push(T::read_from(source)?);
+        }
+        Ok(result)
+    }
+}
+
+impl<T: Persistent> Persistent for Option<T> {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        match *self {
+            Some(ref value) => {
+                true.write_to(target)?;
+                value.write_to(target)
+            }
+            None => false.write_to(target),
+        }
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        if bool::read_from(source)? {
+            Ok(Some(T::read_from(source)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<A: Persistent, B: Persistent> Persistent for (A, B) {
+    fn write_to<W: Write>(&self, target: &mut W) -> io::Result<()> {
+        self.0.write_to(target)?;
+        self.1.write_to(target)
+    }
+
+    fn read_from<R: Read>(source: &mut R) -> io::Result<Self> {
+        Ok((A::read_from(source)?, B::read_from(source)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::Persistent;
+
+    fn roundtrip<T: Persistent>(value: &T) -> T {
+        let mut bytes = Vec::new();
+        value.write_to(&mut bytes).unwrap();
+        T::read_from(&mut Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(roundtrip(&0u8), 0);
+        assert_eq!(roundtrip(&1024u16), 1024);
+        assert_eq!(roundtrip(&u32::MAX), u32::MAX);
+        assert_eq!(roundtrip(&123_456_789_012u64), 123_456_789_012);
+        assert_eq!(roundtrip(&42usize), 42);
+    }
+
+    #[test]
+    fn collections() {
+        assert_eq!(roundtrip(&String::from("perlin")), "perlin");
+        assert_eq!(roundtrip(&vec![1u32, 2, 3]), vec![1, 2, 3]);
+        assert_eq!(roundtrip(&Some(5u16)), Some(5));
+        assert_eq!(roundtrip(&None::<u16>), None);
+        assert_eq!(roundtrip(&(7u8, String::from("tf"))), (7, String::from("tf")));
+    }
+
+    #[test]
+    fn truncated() {
+        let mut bytes = Vec::new();
+        vec![1u32, 2, 3].write_to(&mut bytes).unwrap();
+        bytes.pop();
+        assert!(Vec::<u32>::read_from(&mut Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    fn corrupt_length() {
+        let mut bytes = Vec::new();
+        usize::MAX.write_to(&mut bytes).unwrap();
+        7u32.write_to(&mut bytes).unwrap();
+        assert!(Vec::<u32>::read_from(&mut Cursor::new(&bytes)).is_err());
+        assert!(String::read_from(&mut Cursor::new(&bytes)).is_err());
+    }
+}
@@ -14,6 +14,23 @@ pub trait SeekingIterator {
     fn next_seek(&mut self, &Self::Item) -> Option<Self::Item>;
 }
 
+/// Items are sought and compared by a key. For postings this is their
+/// document, so that any payload is ignored.
+pub trait SeekKey {
+    type Key: Ord;
+
+    fn seek_key(&self) -> Self::Key;
+}
+
+impl<S: SeekingIterator + ?Sized> SeekingIterator for Box<S> {
+    type Item = S::Item;
+
+    #[inline]
+    fn next_seek(&mut self, other: &Self::Item) -> Option<Self::Item> {
+        (**self).next_seek(other)
+    }
+}
+
 /// Wraps an iterator and provides peeking abilities to it.
 /// Very similar to `std::iter::Peekable`
 #[derive(Clone, Debug)]
@@ -24,7 +41,7 @@ pub struct PeekableSeekable<I: Iterator> {
 
 impl<I> SeekingIterator for PeekableSeekable<I>
     where I: Iterator<Item = <I as SeekingIterator>::Item> + SeekingIterator,
-          <I as SeekingIterator>::Item: Ord
+          <I as SeekingIterator>::Item: SeekKey
 {
     type Item = <I as SeekingIterator>::Item;
 
@@ -34,7 +51,7 @@ impl<I> SeekingIterator for PeekableSeekable<I>
         let peeked = self.peeked.take();
         if peeked.is_some() {
             let val = peeked.unwrap();
-            if val >= *other {
+            if val.seek_key() >= other.seek_key() {
                 return Some(val);
             }
         }
@@ -80,6 +97,26 @@ impl<I> PeekableSeekable<I>
     }
 }
 
+impl<I> PeekableSeekable<I>
+    where I: Iterator<Item = <I as SeekingIterator>::Item> + SeekingIterator,
+          <I as SeekingIterator>::Item: SeekKey
+{
+    /// Peeks at the first item that is >= `other`.
+    /// Smaller items are skipped. Unlike `peek_seek` a smaller, already peeked
+    /// item is never returned.
+    #[inline]
+    pub fn peek_from(&mut self, other: &<I as SeekingIterator>::Item) -> Option<&<I as Iterator>::Item> {
+        let outdated = match self.peeked {
+            Some(ref value) => value.seek_key() < other.seek_key(),
+            None => true,
+        };
+        if outdated {
+            self.peeked = self.iter.next_seek(other);
+        }
+        self.peeked.as_ref()
+    }
+}
+
 // Heavily "inspired" by `std::iter::Peekable`
 impl<I> Iterator for PeekableSeekable<I>
     where I: Iterator<Item = <I as SeekingIterator>::Item> + SeekingIterator